@@ -1,249 +1,647 @@
 use jni::{
-    objects::{JClass, JString},
-    sys::{jboolean, jobjectArray, jstring},
+    objects::{JByteArray, JClass, JObject, JString, JThrowable},
+    sys::{jboolean, jlong, jobjectArray, jstring, JNI_FALSE},
     JNIEnv,
 };
 
-use neng_pass::rusqlite;
+use neng_pass::session::Session;
+use zeroize::Zeroizing;
 
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
 
-fn open_and_prepare_database(data_dir: &PathBuf) -> Result<rusqlite::Connection, String> {
-    let sql_connection = match rusqlite::Connection::open(data_dir.join("passwords.db")) {
-        Ok(connection) => connection,
-        Err(err) => return Err(format!("Failed to open the database: {}", err)),
-    };
+/// Where the exceptions thrown to Java are, all of them subclasses of
+/// `NengPassException`.
+const EXCEPTION_PACKAGE: &str = "io/github/earthtraveller1/nengpass";
 
-    if let Err(err) = sql_connection.execute(
-        "CREATE TABLE IF NOT EXISTS passwords (name TEXT, password BLOB);",
-        (),
-    ) {
-        return Err(format!(
-            "Failed to prepare the table in the database: {}",
-            err
-        ));
+/// Why a JNI function failed.
+#[derive(Debug)]
+enum BridgeError {
+    NengPass(neng_pass::Error),
+    Jni(jni::errors::Error),
+    /// The handle of the vault is 0, because it was closed.
+    Closed,
+}
+
+impl From<neng_pass::Error> for BridgeError {
+    fn from(value: neng_pass::Error) -> Self {
+        Self::NengPass(value)
+    }
+}
+
+impl From<jni::errors::Error> for BridgeError {
+    fn from(value: jni::errors::Error) -> Self {
+        Self::Jni(value)
+    }
+}
+
+/// The exception thrown to Java for an error.
+fn exception_class(p_error: &neng_pass::Error) -> &'static str {
+    match p_error {
+        neng_pass::Error::MasterKeyDoesntExist => "NoMasterKeyException",
+        neng_pass::Error::WrongKeyForData => "WrongMasterKeyException",
+        neng_pass::Error::VaultLocked => "VaultLockedException",
+        _ => match p_error.exit_code() {
+            3 => "WrongMasterKeyException",
+            4 => "EntryNotFoundException",
+            5 => "EntryExistsException",
+            6 => "InvalidInputException",
+            7 => "StorageException",
+            _ => "NengPassException",
+        },
     }
+}
+
+/// Throws one of our exceptions, with the message and the error code. If that
+/// fails, a `RuntimeException` is thrown instead, so that Java always sees
+/// that the call failed.
+fn throw(p_env: &mut JNIEnv, p_class: &str, p_message: &str, p_code: &str) {
+    let class = format!("{}/{}", EXCEPTION_PACKAGE, p_class);
+    let result = (|| -> jni::errors::Result<()> {
+        let message = p_env.new_string(p_message)?;
+        let code = p_env.new_string(p_code)?;
+        let exception = p_env.new_object(
+            class.as_str(),
+            "(Ljava/lang/String;Ljava/lang/String;)V",
+            &[(&message).into(), (&code).into()],
+        )?;
+        p_env.throw(JThrowable::from(exception))
+    })();
+
+    if result.is_err() {
+        let _ = p_env.exception_clear();
+        let _ = p_env.throw_new("java/lang/RuntimeException", p_message);
+    }
+}
+
+/// Runs the body of a JNI function. Errors and panics are thrown to Java as
+/// exceptions instead of taking the whole app down, and `p_default` is
+/// returned, which Java ignores since an exception is pending.
+fn run<'local, T>(
+    p_env: &mut JNIEnv<'local>,
+    p_default: T,
+    p_body: impl FnOnce(&mut JNIEnv<'local>) -> Result<T, BridgeError>,
+) -> T {
+    match std::panic::catch_unwind(AssertUnwindSafe(|| p_body(p_env))) {
+        Ok(Ok(value)) => value,
+        Ok(Err(BridgeError::NengPass(err))) => {
+            throw(p_env, exception_class(&err), &err.get_message(), err.code());
+            p_default
+        }
+        Ok(Err(BridgeError::Closed)) => {
+            throw(
+                p_env,
+                "NengPassException",
+                "The vault was closed.",
+                "vault_closed",
+            );
+            p_default
+        }
+        // Java already has an exception to deal with.
+        Ok(Err(BridgeError::Jni(jni::errors::Error::JavaException))) => p_default,
+        Ok(Err(BridgeError::Jni(err))) => {
+            throw(p_env, "NengPassException", &err.to_string(), "jni_error");
+            p_default
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            log::error!("[RUST]: Panicked: {}", message);
+
+            throw(
+                p_env,
+                "NengPassException",
+                &format!("neng-pass panicked: {}", message),
+                "panic",
+            );
+            p_default
+        }
+    }
+}
 
-    Ok(sql_connection)
+/// The open vaults, by the handles given to Java, which only gets a handle as
+/// a `long`. The master key stays in the session, so that Java doesn't have to
+/// keep it around. A handle that was closed, or never opened, is only ever an
+/// error, even if Java keeps using it.
+struct Vaults {
+    vaults: HashMap<jlong, Arc<Mutex<Session>>>,
+    next_handle: jlong,
+}
+
+fn vaults() -> MutexGuard<'static, Vaults> {
+    static VAULTS: OnceLock<Mutex<Vaults>> = OnceLock::new();
+    VAULTS
+        .get_or_init(|| {
+            Mutex::new(Vaults {
+                vaults: HashMap::new(),
+                next_handle: 1,
+            })
+        })
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Keeps a vault open, and returns its handle.
+fn register(p_vault: Session) -> jlong {
+    let mut vaults = vaults();
+    let handle = vaults.next_handle;
+    vaults.next_handle += 1;
+    vaults.vaults.insert(handle, Arc::new(Mutex::new(p_vault)));
+    handle
+}
+
+/// The vault behind a handle from `openVault`. A call that is still using it
+/// keeps it open, even if Java closes it in the meantime.
+fn vault(p_handle: jlong) -> Result<Arc<Mutex<Session>>, BridgeError> {
+    vaults()
+        .vaults
+        .get(&p_handle)
+        .cloned()
+        .ok_or(BridgeError::Closed)
+}
+
+fn lock(p_vault: &Mutex<Session>) -> MutexGuard<'_, Session> {
+    // A panic while the vault was in use leaves nothing half done, SQLite
+    // rolls unfinished transactions back.
+    p_vault
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A master key that Java passed as UTF-8 bytes, which it can wipe afterwards,
+/// unlike a `String`.
+fn get_master_key(
+    p_env: &mut JNIEnv,
+    p_master_key: &JByteArray,
+) -> Result<Zeroizing<String>, BridgeError> {
+    let bytes = Zeroizing::new(p_env.convert_byte_array(p_master_key)?);
+    let master_key = std::str::from_utf8(&bytes).map_err(|_| {
+        neng_pass::Error::SyntaxError("The master key is not valid UTF-8".to_string())
+    })?;
+
+    Ok(Zeroizing::new(master_key.to_string()))
+}
+
+fn get_string(p_env: &mut JNIEnv, p_string: &JString) -> Result<String, BridgeError> {
+    Ok(p_env.get_string(p_string)?.into())
+}
+
+/// The string Java passed, or `None` if it passed `null`.
+fn get_optional_string(
+    p_env: &mut JNIEnv,
+    p_string: &JString,
+) -> Result<Option<String>, BridgeError> {
+    if p_string.is_null() {
+        return Ok(None);
+    }
+
+    get_string(p_env, p_string).map(Some)
 }
 
 #[no_mangle]
 pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_init(
-    _env: JNIEnv,
+    mut env: JNIEnv,
+    _p_class: JClass,
+) {
+    run(&mut env, (), |_| {
+        // The logger is already set when the app comes back to the login
+        // screen.
+        #[cfg(target_os = "android")]
+        {
+            let _ = android_log::init("io.github.earthtraveller1.nengpass");
+            log_panics::init();
+        }
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_openVault(
+    mut env: JNIEnv,
+    _p_class: JClass,
+    p_data_dir: JString,
+) -> jlong {
+    run(&mut env, 0, |env| {
+        let data_dir = get_string(env, &p_data_dir)?;
+
+        log::debug!("[RUST]: Data directory: {}", data_dir);
+
+        Ok(register(Session::open(data_dir)?))
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_close(
+    mut env: JNIEnv,
     _p_class: JClass,
+    p_vault: jlong,
 ) {
-    android_log::init("io.github.earthtraveller1.nengpass").unwrap();
-    log_panics::init();
+    run(&mut env, (), |_| {
+        vaults().vaults.remove(&p_vault);
+        Ok(())
+    })
 }
 
 #[no_mangle]
-//                     Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_setMasterKey
 pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_setMasterKey(
     mut env: JNIEnv,
     _p_class: JClass,
-    p_file: JString,
-    p_master_key: JString,
+    p_vault: jlong,
+    p_new_master_key: JByteArray,
 ) {
-    let file_name = env
-        .get_string(&p_file)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-    let master_key = env
-        .get_string(&p_master_key)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-    neng_pass::set_master_key(&file_name, &master_key).unwrap();
+    run(&mut env, (), |env| {
+        let master_key = get_master_key(env, &p_new_master_key)?;
+        let vault = vault(p_vault)?;
+        let mut vault = lock(&vault);
+        Ok(vault.set_master_key(&master_key)?)
+    })
 }
 
 #[no_mangle]
-pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_isMasterKeyCorrect(
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_unlock(
     mut env: JNIEnv,
     _p_class: JClass,
-    p_file: JString,
-    p_master_key: JString,
-) -> jboolean {
-    // android_log::init("io.github.earthtraveller1.nengpass").unwrap();
-    // log_panics::init();
+    p_vault: jlong,
+    p_master_key: JByteArray,
+) {
+    run(&mut env, (), |env| {
+        let master_key = get_master_key(env, &p_master_key)?;
+        let vault = vault(p_vault)?;
+        let mut vault = lock(&vault);
+        Ok(vault.unlock(&master_key)?)
+    })
+}
 
-    let file_name = env
-        .get_string(&p_file)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-    let master_key = env
-        .get_string(&p_master_key)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+#[no_mangle]
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_lock(
+    mut env: JNIEnv,
+    _p_class: JClass,
+    p_vault: jlong,
+) {
+    run(&mut env, (), |_| {
+        let vault = vault(p_vault)?;
+        lock(&vault).lock();
+        Ok(())
+    })
+}
 
-    neng_pass::query_master_key(&file_name, &master_key).is_ok() as jboolean
+#[no_mangle]
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_isUnlocked(
+    mut env: JNIEnv,
+    _p_class: JClass,
+    p_vault: jlong,
+) -> jboolean {
+    run(&mut env, JNI_FALSE, |_| {
+        let vault = vault(p_vault)?;
+        let is_unlocked = lock(&vault).is_unlocked();
+        Ok(is_unlocked as jboolean)
+    })
 }
 
 #[no_mangle]
 pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_getPasswordList(
     mut env: JNIEnv,
     _p_class: JClass,
-    p_database_file: JString,
+    p_vault: jlong,
 ) -> jobjectArray {
-    let string_class = env.find_class("java/lang/String").unwrap();
-    let database_file = env
-        .get_string(&p_database_file)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-
-    log::debug!("[RUST]: Database file: {}", database_file);
+    run(&mut env, std::ptr::null_mut(), |env| {
+        let vault = vault(p_vault)?;
+        let vault = lock(&vault);
 
-    let database = open_and_prepare_database(&PathBuf::from(database_file)).unwrap();
+        let native_passwords = vault.list_entries()?;
 
-    let mut sql_statement = database.prepare("SELECT name FROM passwords;").unwrap();
+        let passwords = env.new_object_array(
+            native_passwords.len() as i32,
+            "java/lang/String",
+            JObject::null(),
+        )?;
 
-    let mut native_passwords = Vec::<String>::new();
+        for (index, password) in native_passwords.iter().enumerate() {
+            let password = env.new_string(password)?;
+            env.set_object_array_element(&passwords, index as i32, password)?;
+        }
 
-    for row in sql_statement
-        .query_map([], |row| row.get::<_, String>(0))
-        .unwrap()
-    {
-        let row = row.unwrap();
-        native_passwords.push(row);
-    }
-
-    let mut passwords = env
-        .new_object_array(
-            native_passwords.len().try_into().unwrap(),
-            string_class,
-            env.new_string("").unwrap(),
-        )
-        .unwrap();
-
-    for (index, password) in native_passwords.iter().enumerate() {
-        env.set_object_array_element(
-            &mut passwords,
-            index.try_into().unwrap(),
-            env.new_string(password).unwrap(),
-        )
-        .unwrap();
-    }
-
-    passwords.as_raw()
+        Ok(passwords.as_raw())
+    })
 }
 
 #[no_mangle]
 pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_generatePassword(
-    env: JNIEnv,
+    mut env: JNIEnv,
     _p_class: JClass,
 ) -> jstring {
-    let password = std::str::from_utf8(&neng_pass::generate_password())
-        .unwrap()
-        .to_string();
-    env.new_string(password).unwrap().as_raw()
+    run(&mut env, std::ptr::null_mut(), |env| {
+        let password = String::from_utf8_lossy(&neng_pass::generate_password()).to_string();
+        Ok(env.new_string(password)?.as_raw())
+    })
 }
 
 #[no_mangle]
 pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_savePassword(
     mut env: JNIEnv,
     _p_class: JClass,
-    p_database_file: JString,
-    p_master_key: JString,
+    p_vault: jlong,
     p_name: JString,
     p_password: JString,
 ) {
-    let database_file = env
-        .get_string(&p_database_file)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+    run(&mut env, (), |env| {
+        let name = get_string(env, &p_name)?;
+        let password = get_string(env, &p_password)?;
+
+        let vault = vault(p_vault)?;
+        lock(&vault).save_entry(&name, &password)?;
+        Ok(())
+    })
+}
 
-    let database = open_and_prepare_database(&PathBuf::from(database_file)).unwrap();
+#[no_mangle]
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_getPassword(
+    mut env: JNIEnv,
+    _p_class: JClass,
+    p_vault: jlong,
+    p_name: JString,
+) -> jstring {
+    run(&mut env, std::ptr::null_mut(), |env| {
+        let name = get_string(env, &p_name)?;
 
-    let master_key = env
-        .get_string(&p_master_key)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+        let vault = vault(p_vault)?;
+        let password = lock(&vault).get_entry(&name)?;
+        Ok(env.new_string(password)?.as_raw())
+    })
+}
 
-    let name = env
-        .get_string(&p_name)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+#[no_mangle]
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_deletePassword(
+    mut env: JNIEnv,
+    _p_class: JClass,
+    p_vault: jlong,
+    p_name: JString,
+) {
+    run(&mut env, (), |env| {
+        let name = get_string(env, &p_name)?;
 
-    let password = env
-        .get_string(&p_password)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+        let vault = vault(p_vault)?;
+        lock(&vault).delete_entry(&name)?;
+        Ok(())
+    })
+}
 
-    neng_pass::create_password(master_key, &name, &password, &database).unwrap();
+#[no_mangle]
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_getAutofillDatasets(
+    mut env: JNIEnv,
+    _p_class: JClass,
+    p_vault: jlong,
+    p_package_name: JString,
+    p_web_domain: JString,
+) -> jobjectArray {
+    run(&mut env, std::ptr::null_mut(), |env| {
+        let package_name = get_string(env, &p_package_name)?;
+        let web_domain = get_optional_string(env, &p_web_domain)?;
+
+        let vault = vault(p_vault)?;
+        let vault = lock(&vault);
+
+        // Browsers report the domain of the page they show, which is only
+        // believed from the ones the vault trusts.
+        let candidates = neng_pass::autofill::find_autofill_entries(
+            &package_name,
+            web_domain.as_deref(),
+            vault.sql_connection(),
+        )?;
+        let logins = vault.logins(candidates)?;
+
+        let dataset_class = env.find_class("io/github/earthtraveller1/nengpass/AutofillDataset")?;
+
+        let datasets =
+            env.new_object_array(logins.len() as i32, &dataset_class, JObject::null())?;
+
+        for (index, login) in logins.iter().enumerate() {
+            let name = env.new_string(&login.name)?;
+            let username = match &login.username {
+                Some(username) => JObject::from(env.new_string(username)?),
+                None => JObject::null(),
+            };
+            let password = env.new_string(&login.password)?;
+
+            let dataset = env.new_object(
+                &dataset_class,
+                "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V",
+                &[(&name).into(), (&username).into(), (&password).into()],
+            )?;
+
+            env.set_object_array_element(&datasets, index as i32, dataset)?;
+        }
+
+        Ok(datasets.as_raw())
+    })
 }
 
 #[no_mangle]
-pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_getPassword(
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_saveAutofillCredentials(
     mut env: JNIEnv,
     _p_class: JClass,
-    p_database_file: JString,
-    p_master_key: JString,
-    p_name: JString,
+    p_vault: jlong,
+    p_package_name: JString,
+    p_web_domain: JString,
+    p_username: JString,
+    p_password: JString,
 ) -> jstring {
-    let database_file = env
-        .get_string(&p_database_file)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-
-    let master_key = env
-        .get_string(&p_master_key)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+    run(&mut env, std::ptr::null_mut(), |env| {
+        let package_name = get_string(env, &p_package_name)?;
+        let web_domain = get_optional_string(env, &p_web_domain)?;
+        let username = get_optional_string(env, &p_username)?;
+        let password = get_string(env, &p_password)?;
+
+        let vault = vault(p_vault)?;
+        let vault = lock(&vault);
+
+        // Logins entered in an app that is linked to a domain are saved for
+        // the domain, so that its website is offered them too. The domain of a
+        // page is only believed from a trusted browser.
+        let web_domain = match web_domain {
+            Some(web_domain)
+                if neng_pass::autofill::is_browser(&package_name, vault.sql_connection())? =>
+            {
+                Some(web_domain)
+            }
+            _ => None,
+        };
+        let url = match web_domain.as_deref() {
+            Some(web_domain) if !web_domain.is_empty() => format!("https://{}/", web_domain),
+            _ => match neng_pass::autofill::app_links(Some(&package_name), vault.sql_connection())?
+                .first()
+            {
+                Some(link) => format!("https://{}/", link.domain),
+                None => neng_pass::autofill::app_url(&package_name),
+            },
+        };
+
+        let name = vault.save_login(&url, username.as_deref(), &password)?;
+
+        Ok(env.new_string(name)?.as_raw())
+    })
+}
 
-    let name = env
-        .get_string(&p_name)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+#[no_mangle]
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_addAppLink(
+    mut env: JNIEnv,
+    _p_class: JClass,
+    p_vault: jlong,
+    p_package_name: JString,
+    p_domain: JString,
+) {
+    run(&mut env, (), |env| {
+        let package_name = get_string(env, &p_package_name)?;
+        let domain = get_string(env, &p_domain)?;
+
+        let vault = vault(p_vault)?;
+        let vault = lock(&vault);
+        // Only whoever can unlock the vault decides who is offered its logins.
+        vault.master_key()?;
+        neng_pass::autofill::add_app_link(&package_name, &domain, vault.sql_connection())?;
+        Ok(())
+    })
+}
 
-    let database = open_and_prepare_database(&PathBuf::from(database_file)).unwrap();
+#[no_mangle]
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_removeAppLink(
+    mut env: JNIEnv,
+    _p_class: JClass,
+    p_vault: jlong,
+    p_package_name: JString,
+    p_domain: JString,
+) {
+    run(&mut env, (), |env| {
+        let package_name = get_string(env, &p_package_name)?;
+        let domain = get_string(env, &p_domain)?;
+
+        let vault = vault(p_vault)?;
+        let vault = lock(&vault);
+        vault.master_key()?;
+        neng_pass::autofill::remove_app_link(&package_name, &domain, vault.sql_connection())?;
+        Ok(())
+    })
+}
 
-    let password = neng_pass::get_password(master_key, &name, &database).unwrap();
-    env.new_string(password).unwrap().as_raw()
+#[no_mangle]
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_addBrowser(
+    mut env: JNIEnv,
+    _p_class: JClass,
+    p_vault: jlong,
+    p_package_name: JString,
+) {
+    run(&mut env, (), |env| {
+        let package_name = get_string(env, &p_package_name)?;
+
+        let vault = vault(p_vault)?;
+        let vault = lock(&vault);
+        vault.master_key()?;
+        neng_pass::autofill::add_browser(&package_name, vault.sql_connection())?;
+        Ok(())
+    })
 }
 
 #[no_mangle]
-pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_deletePassword(
+pub extern "system" fn Java_io_github_earthtraveller1_nengpass_NengPass_00024Companion_removeBrowser(
     mut env: JNIEnv,
     _p_class: JClass,
-    p_database_file: JString,
-    p_name: JString,
+    p_vault: jlong,
+    p_package_name: JString,
 ) {
-    let database_file = env
-        .get_string(&p_database_file)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-
-    let name = env
-        .get_string(&p_name)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-
-    let database = open_and_prepare_database(&PathBuf::from(database_file)).unwrap();
-    neng_pass::delete_password(&name, &database).unwrap();
+    run(&mut env, (), |env| {
+        let package_name = get_string(env, &p_package_name)?;
+
+        let vault = vault(p_vault)?;
+        let vault = lock(&vault);
+        vault.master_key()?;
+        neng_pass::autofill::remove_browser(&package_name, vault.sql_connection())?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault_with_master_key(p_data_dir: &std::path::Path) -> Session {
+        let mut vault = Session::open(p_data_dir).unwrap();
+        vault.set_master_key("hunter2").unwrap();
+        vault.lock();
+        vault
+    }
+
+    /// The class of the exception that Java would get for a failed call.
+    fn thrown(p_result: Result<(), BridgeError>) -> &'static str {
+        match p_result {
+            Err(BridgeError::NengPass(err)) => exception_class(&err),
+            Err(_) => "NengPassException",
+            Ok(()) => panic!("the call succeeded"),
+        }
+    }
+
+    #[test]
+    fn closed_handles_are_refused() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let handle = register(vault_with_master_key(data_dir.path()));
+        let in_use = vault(handle).unwrap();
+
+        // What `close` does.
+        vaults().vaults.remove(&handle);
+        assert!(matches!(vault(handle), Err(BridgeError::Closed)));
+        assert!(matches!(vault(0), Err(BridgeError::Closed)));
+
+        // A call that was still using the vault can finish.
+        lock(&in_use).unlock("hunter2").unwrap();
+    }
+
+    #[test]
+    fn errors_map_to_exceptions() {
+        use neng_pass::Error;
+
+        let cases = [
+            (Error::MasterKeyDoesntExist, "NoMasterKeyException"),
+            (Error::WrongKeyForData, "WrongMasterKeyException"),
+            (
+                Error::PasswordDoesntExist(Box::from("github")),
+                "EntryNotFoundException",
+            ),
+            (Error::PasswordAlreadyExists, "EntryExistsException"),
+            (Error::PasswordTooLong, "InvalidInputException"),
+            (Error::SyntaxError(String::new()), "InvalidInputException"),
+            (
+                Error::IOError(std::io::ErrorKind::Other.into()),
+                "StorageException",
+            ),
+            (Error::SyncError(String::new()), "NengPassException"),
+        ];
+        for (error, class) in cases {
+            assert_eq!(exception_class(&error), class, "for {:?}", error.code());
+        }
+    }
+
+    #[test]
+    fn wrong_master_keys_are_refused() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let mut vault = vault_with_master_key(data_dir.path());
+
+        assert_eq!(
+            thrown(vault.unlock("hunter3").map_err(BridgeError::from)),
+            "WrongMasterKeyException"
+        );
+        assert_eq!(
+            thrown(vault.master_key().map(|_| ()).map_err(BridgeError::from)),
+            "VaultLockedException"
+        );
+
+        // Only a key that was checked is kept for decrypting.
+        vault.unlock("hunter2").unwrap();
+        assert_eq!(
+            vault.master_key().unwrap().as_bytes(),
+            neng_pass::MasterKey::new("hunter2").unwrap().as_bytes()
+        );
+    }
 }
@@ -0,0 +1,4 @@
+package io.github.earthtraveller1.nengpass
+
+/** A login offered by [NengPass.getAutofillDatasets]. */
+data class AutofillDataset(val name: String, val username: String?, val password: String)
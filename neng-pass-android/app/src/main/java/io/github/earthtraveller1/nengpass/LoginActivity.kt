@@ -73,11 +73,9 @@ class LoginActivity : ComponentActivity() {
                     return@Button
                 }
 
-                NengPass.setMasterKey("${applicationInfo.dataDir}/master_key", passwordValue)
+                Vault.get(applicationContext).setMasterKey(passwordValue)
 
-                val intent = Intent(applicationContext, PasswordListActivity::class.java)
-                intent.putExtra("masterKey", passwordValue)
-                startActivity(intent)
+                startActivity(Intent(applicationContext, PasswordListActivity::class.java))
             }) {
                 Text("Ok")
             }
@@ -98,11 +96,10 @@ class LoginActivity : ComponentActivity() {
             NengPass.PasswordField(modifier, "Password", passwordValue, setPasswordValue)
 
             Button(onClick = {
-                if (NengPass.isMasterKeyCorrect("${applicationInfo.dataDir}/master_key", passwordValue)) {
-                    val intent = Intent(applicationContext, PasswordListActivity::class.java)
-                    intent.putExtra("masterKey", passwordValue)
-                    startActivity(intent)
-                } else {
+                try {
+                    Vault.get(applicationContext).unlock(passwordValue)
+                    startActivity(Intent(applicationContext, PasswordListActivity::class.java))
+                } catch (e: WrongMasterKeyException) {
                     setPasswordIncorrectDialog(true)
                 }
             }) {
@@ -17,21 +17,59 @@ interface NengPass {
             System.loadLibrary("neng_pass_android")
         }
 
+        // The native functions throw a NengPassException, or one of its
+        // subclasses, when they fail.
+
         external fun init()
 
-        external fun setMasterKey(file: String, newMasterKey: String)
+        /**
+         * Opens the vault in [dataDir], and returns a handle to it that the
+         * other functions take. Use [Vault] rather than the handle itself.
+         */
+        external fun openVault(dataDir: String): Long
+
+        external fun close(vault: Long)
+
+        /** Sets the master key, which must not have been set yet, and unlocks the vault with it. */
+        external fun setMasterKey(vault: Long, newMasterKey: ByteArray)
 
-        external fun isMasterKeyCorrect(file: String, masterKey: String): Boolean
+        /** Throws a [WrongMasterKeyException] if the master key is wrong. */
+        external fun unlock(vault: Long, masterKey: ByteArray)
 
-        external fun getPasswordList(databaseFile: String): Array<String>
+        external fun lock(vault: Long)
+
+        external fun isUnlocked(vault: Long): Boolean
+
+        external fun getPasswordList(vault: Long): Array<String>
 
         external fun generatePassword(): String
 
-        external fun savePassword(databaseFile: String, masterKey: String, name: String, password: String)
+        external fun savePassword(vault: Long, name: String, password: String)
+
+        external fun getPassword(vault: Long, name: String): String
+
+        external fun deletePassword(vault: Long, name: String)
+
+        /**
+         * The logins to offer for an app, or for the page a browser shows when
+         * [webDomain] is given, the best matches first. [webDomain] is ignored
+         * unless the app is one of the trusted browsers.
+         */
+        external fun getAutofillDatasets(vault: Long, packageName: String, webDomain: String?): Array<AutofillDataset>
+
+        /** Saves a login entered in an app or on a page, and returns the name of its entry. */
+        external fun saveAutofillCredentials(
+            vault: Long, packageName: String, webDomain: String?, username: String?, password: String
+        ): String
+
+        external fun addAppLink(vault: Long, packageName: String, domain: String)
+
+        external fun removeAppLink(vault: Long, packageName: String, domain: String)
 
-        external fun getPassword(databaseFile: String, masterKey: String, name: String): String
+        /** Trusts an app to report the domain of the page it shows. */
+        external fun addBrowser(vault: Long, packageName: String)
 
-        external fun deletePassword(databaseFile: String, name: String)
+        external fun removeBrowser(vault: Long, packageName: String)
 
         @Composable
         public fun PasswordField(
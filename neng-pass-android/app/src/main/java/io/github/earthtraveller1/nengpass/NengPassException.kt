@@ -0,0 +1,28 @@
+package io.github.earthtraveller1.nengpass
+
+/**
+ * Thrown by the native functions of [NengPass] when they fail. [code] is the
+ * same code that the CLI prints with `--format json`, like `password_not_found`.
+ */
+open class NengPassException(message: String, val code: String) : Exception(message)
+
+/** The master key is wrong, or the data wasn't encrypted with it. */
+class WrongMasterKeyException(message: String, code: String) : NengPassException(message, code)
+
+/** The vault has to be unlocked with [Vault.unlock] first. */
+class VaultLockedException(message: String, code: String) : NengPassException(message, code)
+
+/** No master key was set yet. */
+class NoMasterKeyException(message: String, code: String) : NengPassException(message, code)
+
+/** An entry with that name already exists. */
+class EntryExistsException(message: String, code: String) : NengPassException(message, code)
+
+/** There is no entry with that name. */
+class EntryNotFoundException(message: String, code: String) : NengPassException(message, code)
+
+/** The name, password or master key isn't valid, for example because it is too long. */
+class InvalidInputException(message: String, code: String) : NengPassException(message, code)
+
+/** The vault couldn't be read or written. */
+class StorageException(message: String, code: String) : NengPassException(message, code)
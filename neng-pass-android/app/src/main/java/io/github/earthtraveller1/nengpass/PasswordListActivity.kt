@@ -26,7 +26,7 @@ import androidx.compose.ui.window.Dialog
 import io.github.earthtraveller1.nengpass.ui.theme.NengPassTheme
 
 class PasswordListActivity : ComponentActivity() {
-    private var masterKey: String = ""
+    private lateinit var vault: Vault
 
     @Composable
     fun TopBar(modifier: Modifier = Modifier, setNewPasswordDialog: (Boolean) -> Unit) {
@@ -136,8 +136,8 @@ class PasswordListActivity : ComponentActivity() {
 
                     Button(
                         onClick = {
-                            NengPass.deletePassword(applicationContext.dataDir.canonicalPath, passwordName)
-                            setPasswordList(NengPass.getPasswordList(applicationContext.dataDir.canonicalPath))
+                            vault.deletePassword(passwordName)
+                            setPasswordList(vault.getPasswordList())
                             setDeletePasswordDialog(false)
                         },
                         colors = buttonColors(
@@ -160,7 +160,7 @@ class PasswordListActivity : ComponentActivity() {
         setDeletePasswordDialog: (Boolean) -> Unit,
         passwordName: String
     ) {
-        val password = NengPass.getPassword(applicationContext.dataDir.canonicalPath, masterKey, passwordName)
+        val password = vault.getPassword(passwordName)
         val (isPasswordVisible, setIsPasswordVisible) = remember { mutableStateOf(false) }
 
         Dialog(onDismissRequest = { setPasswordDialog(false) }) {
@@ -267,17 +267,20 @@ class PasswordListActivity : ComponentActivity() {
                             setNewPasswordError("You didn't enter your password")
                         } else {
                             setNewPasswordError("")
-                            NengPass.savePassword(
-                                applicationContext.dataDir.canonicalPath,
-                                masterKey,
-                                newPasswordName,
-                                newPasswordValue
-                            )
+                            try {
+                                vault.savePassword(newPasswordName, newPasswordValue)
+                            } catch (e: EntryExistsException) {
+                                setNewPasswordError("A password with that name already exists")
+                                return@Button
+                            } catch (e: NengPassException) {
+                                setNewPasswordError(e.message ?: "Failed to save the password")
+                                return@Button
+                            }
                             setNewPasswordName("")
                             setNewPasswordValue("")
 
                             // Refresh the password list.
-                            val newPasswordList = NengPass.getPasswordList(applicationContext.dataDir.canonicalPath)
+                            val newPasswordList = vault.getPasswordList()
                             setPasswordList(newPasswordList)
 
                             setDialog(false)
@@ -306,7 +309,7 @@ class PasswordListActivity : ComponentActivity() {
 
         val (currentPasswordName, setCurrentPasswordName) = remember { mutableStateOf("") }
 
-        val passwordListValue = NengPass.getPasswordList(applicationContext.dataDir.canonicalPath)
+        val passwordListValue = vault.getPasswordList()
         val (passwordList, setPasswordList) = remember { mutableStateOf(passwordListValue) }
 
         NengPassTheme {
@@ -358,7 +361,13 @@ class PasswordListActivity : ComponentActivity() {
     override fun onCreate(savedInstanceState: Bundle?) {
         super.onCreate(savedInstanceState)
 
-        masterKey = intent.extras!!.getString("masterKey")!!
+        vault = Vault.get(applicationContext)
+
+        // The app may have been restarted since the vault was unlocked.
+        if (!vault.isUnlocked) {
+            finish()
+            return
+        }
 
         setContent {
             MainContent()
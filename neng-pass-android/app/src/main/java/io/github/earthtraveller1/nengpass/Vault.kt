@@ -0,0 +1,79 @@
+package io.github.earthtraveller1.nengpass
+
+import android.content.Context
+
+/**
+ * The vault, opened in native code. The master key is only kept there while
+ * the vault is unlocked, so master keys are passed as bytes, which are wiped
+ * once they were used.
+ */
+class Vault private constructor(dataDir: String) : AutoCloseable {
+    private var handle: Long = NengPass.openVault(dataDir)
+
+    val isUnlocked: Boolean
+        get() = NengPass.isUnlocked(handle)
+
+    fun setMasterKey(newMasterKey: String) {
+        val bytes = newMasterKey.toByteArray()
+        try {
+            NengPass.setMasterKey(handle, bytes)
+        } finally {
+            bytes.fill(0)
+        }
+    }
+
+    fun unlock(masterKey: String) {
+        val bytes = masterKey.toByteArray()
+        try {
+            NengPass.unlock(handle, bytes)
+        } finally {
+            bytes.fill(0)
+        }
+    }
+
+    fun lock() = NengPass.lock(handle)
+
+    fun getPasswordList(): Array<String> = NengPass.getPasswordList(handle)
+
+    fun savePassword(name: String, password: String) = NengPass.savePassword(handle, name, password)
+
+    fun getPassword(name: String): String = NengPass.getPassword(handle, name)
+
+    fun deletePassword(name: String) = NengPass.deletePassword(handle, name)
+
+    fun getAutofillDatasets(packageName: String, webDomain: String?): Array<AutofillDataset> =
+        NengPass.getAutofillDatasets(handle, packageName, webDomain)
+
+    fun saveAutofillCredentials(packageName: String, webDomain: String?, username: String?, password: String): String =
+        NengPass.saveAutofillCredentials(handle, packageName, webDomain, username, password)
+
+    fun addAppLink(packageName: String, domain: String) = NengPass.addAppLink(handle, packageName, domain)
+
+    fun removeAppLink(packageName: String, domain: String) = NengPass.removeAppLink(handle, packageName, domain)
+
+    fun addBrowser(packageName: String) = NengPass.addBrowser(handle, packageName)
+
+    fun removeBrowser(packageName: String) = NengPass.removeBrowser(handle, packageName)
+
+    override fun close() {
+        NengPass.close(handle)
+        handle = 0
+
+        // The next activity gets a vault that is open.
+        synchronized(Companion) {
+            if (vault === this) {
+                vault = null
+            }
+        }
+    }
+
+    companion object {
+        private var vault: Vault? = null
+
+        /** The vault of the app, shared by all of its activities. */
+        @Synchronized
+        fun get(context: Context): Vault {
+            return vault ?: Vault(context.applicationInfo.dataDir).also { vault = it }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+#ifndef NENG_PASS_H
+#define NENG_PASS_H
+
+/* This file is generated by cbindgen from neng-pass-ffi, don't edit it. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define NP_OK 0
+
+/**
+ * Anything else, including a bug in neng-pass.
+ */
+#define NP_ERROR_OTHER 1
+
+/**
+ * The master key is wrong or wasn't set yet.
+ */
+#define NP_ERROR_AUTH 3
+
+#define NP_ERROR_NOT_FOUND 4
+
+#define NP_ERROR_EXISTS 5
+
+/**
+ * An argument is invalid: `NULL`, not UTF-8, or too long. Also returned for
+ * an entry that C can't be given, because it has a NUL in it.
+ */
+#define NP_ERROR_INVALID 6
+
+/**
+ * The vault couldn't be read or written.
+ */
+#define NP_ERROR_STORAGE 7
+
+#define NP_ERROR_EXTERNAL 8
+
+#define NP_ERROR_CRYPTO 9
+
+/**
+ * The vault has to be unlocked first.
+ */
+#define NP_ERROR_LOCKED 10
+
+/**
+ * An open vault. It must not be used by two threads at once.
+ */
+typedef struct NpVault NpVault;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Opens the vault in a data directory, creating it if needed, and stores it
+ * in `out_vault`. The vault is locked until `np_vault_unlock` is called.
+ *
+ * # Safety
+ *
+ * `data_dir` must be a NUL-terminated string, and `out_vault` must point to
+ * writable memory.
+ */
+int32_t np_vault_open(const char *data_dir, NpVault **out_vault);
+
+/**
+ * Closes a vault, forgetting its master key. `vault` may be `NULL`.
+ *
+ * # Safety
+ *
+ * `vault` must come from `np_vault_open`, and not be used anymore afterwards.
+ */
+void np_vault_close(NpVault *vault);
+
+/**
+ * Checks the master key, and keeps it until the vault is locked or closed.
+ *
+ * # Safety
+ *
+ * `vault` must come from `np_vault_open`, and `master_key` must be a
+ * NUL-terminated string.
+ */
+int32_t np_vault_unlock(NpVault *vault, const char *master_key);
+
+/**
+ * Forgets the master key.
+ *
+ * # Safety
+ *
+ * `vault` must come from `np_vault_open`.
+ */
+int32_t np_vault_lock(NpVault *vault);
+
+/**
+ * Lists the names of the entries. `out_names` gets an array of `out_len`
+ * strings, to free with `np_string_array_free`.
+ *
+ * # Safety
+ *
+ * `vault` must come from `np_vault_open`, and `out_names` and `out_len` must
+ * point to writable memory.
+ */
+int32_t np_entry_list(NpVault *vault, char ***out_names, size_t *out_len);
+
+/**
+ * Decrypts an entry. `out_password` gets the password, to free with
+ * `np_string_free`.
+ *
+ * # Safety
+ *
+ * `vault` must come from `np_vault_open`, `name` must be a NUL-terminated
+ * string, and `out_password` must point to writable memory.
+ */
+int32_t np_entry_get(NpVault *vault, const char *name, char **out_password);
+
+/**
+ * Saves a new entry.
+ *
+ * # Safety
+ *
+ * `vault` must come from `np_vault_open`, and `name` and `password` must be
+ * NUL-terminated strings.
+ */
+int32_t np_entry_save(NpVault *vault, const char *name, const char *password);
+
+/**
+ * Deletes an entry.
+ *
+ * # Safety
+ *
+ * `vault` must come from `np_vault_open`, and `name` must be a
+ * NUL-terminated string.
+ */
+int32_t np_entry_delete(NpVault *vault, const char *name);
+
+/**
+ * Generates a random password, to free with `np_string_free`.
+ */
+char *np_generate_password(void);
+
+/**
+ * The message of the last error on this thread, or `NULL` if there was none.
+ * It stays valid until the next call that fails on the same thread.
+ */
+const char *np_last_error_message(void);
+
+/**
+ * Wipes and frees a string returned by neng-pass. `string` may be `NULL`.
+ *
+ * # Safety
+ *
+ * `string` must come from neng-pass, and not be used anymore afterwards.
+ */
+void np_string_free(char *string);
+
+/**
+ * Frees an array of strings returned by `np_entry_list`, and the strings in
+ * it. `names` may be `NULL`.
+ *
+ * # Safety
+ *
+ * `names` and `len` must come from `np_entry_list`, and not be used anymore
+ * afterwards.
+ */
+void np_string_array_free(char **names, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NENG_PASS_H */
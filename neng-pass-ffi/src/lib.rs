@@ -0,0 +1,465 @@
+//! A C API to the vault, for programs that embed neng-pass instead of running
+//! the CLI. `include/neng_pass.h` is generated from this file by cbindgen.
+//!
+//! Every function that can fail returns `NP_OK` or an error code, which are
+//! the exit codes of the CLI, and leaves a message for `np_last_error_message`.
+//! Strings returned to the caller are freed with `np_string_free`, which wipes
+//! them first, since they can be passwords.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::AssertUnwindSafe;
+
+use neng_pass::session::Session;
+use zeroize::Zeroize;
+
+pub const NP_OK: i32 = 0;
+/// Anything else, including a bug in neng-pass.
+pub const NP_ERROR_OTHER: i32 = 1;
+/// The master key is wrong or wasn't set yet.
+pub const NP_ERROR_AUTH: i32 = 3;
+pub const NP_ERROR_NOT_FOUND: i32 = 4;
+pub const NP_ERROR_EXISTS: i32 = 5;
+/// An argument is invalid: `NULL`, not UTF-8, or too long. Also returned for
+/// an entry that C can't be given, because it has a NUL in it.
+pub const NP_ERROR_INVALID: i32 = 6;
+/// The vault couldn't be read or written.
+pub const NP_ERROR_STORAGE: i32 = 7;
+pub const NP_ERROR_EXTERNAL: i32 = 8;
+pub const NP_ERROR_CRYPTO: i32 = 9;
+/// The vault has to be unlocked first.
+pub const NP_ERROR_LOCKED: i32 = 10;
+
+/// An open vault. It must not be used by two threads at once.
+pub struct NpVault {
+    session: Session,
+}
+
+enum FfiError {
+    NengPass(neng_pass::Error),
+    InvalidArgument(String),
+}
+
+impl From<neng_pass::Error> for FfiError {
+    fn from(p_error: neng_pass::Error) -> FfiError {
+        FfiError::NengPass(p_error)
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(p_message: String) {
+    let message =
+        CString::new(p_message.replace('\0', "")).expect("the message has no NUL in it anymore");
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+/// Runs the body of a function, and turns its errors and panics into error
+/// codes, since neither can cross into C.
+fn run(p_body: impl FnOnce() -> Result<(), FfiError>) -> i32 {
+    let (code, message) = match std::panic::catch_unwind(AssertUnwindSafe(p_body)) {
+        Ok(Ok(())) => return NP_OK,
+        Ok(Err(FfiError::NengPass(neng_pass::Error::VaultLocked))) => (
+            NP_ERROR_LOCKED,
+            neng_pass::Error::VaultLocked.get_message(),
+        ),
+        Ok(Err(FfiError::NengPass(err))) => (err.exit_code(), err.get_message()),
+        Ok(Err(FfiError::InvalidArgument(message))) => (NP_ERROR_INVALID, message),
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            (NP_ERROR_OTHER, format!("neng-pass panicked: {}", message))
+        }
+    };
+
+    set_last_error(message);
+    code
+}
+
+/// A string argument, or an error if it is `NULL` or not UTF-8.
+unsafe fn str_argument<'a>(p_string: *const c_char, p_name: &str) -> Result<&'a str, FfiError> {
+    if p_string.is_null() {
+        return Err(FfiError::InvalidArgument(format!("{} is NULL", p_name)));
+    }
+
+    CStr::from_ptr(p_string)
+        .to_str()
+        .map_err(|_| FfiError::InvalidArgument(format!("{} is not valid UTF-8", p_name)))
+}
+
+unsafe fn vault_argument<'a>(p_vault: *mut NpVault) -> Result<&'a mut NpVault, FfiError> {
+    p_vault
+        .as_mut()
+        .ok_or_else(|| FfiError::InvalidArgument("vault is NULL".to_string()))
+}
+
+unsafe fn out_argument<'a, T>(p_out: *mut T, p_name: &str) -> Result<&'a mut T, FfiError> {
+    p_out
+        .as_mut()
+        .ok_or_else(|| FfiError::InvalidArgument(format!("{} is NULL", p_name)))
+}
+
+/// A string for C, to free with `np_string_free`, or an error if it has a NUL
+/// in it, since C would only see the part before it.
+fn into_c_string(p_string: String) -> Result<*mut c_char, FfiError> {
+    match CString::new(p_string) {
+        Ok(string) => Ok(string.into_raw()),
+        Err(err) => {
+            // Wipe what was copied before giving up.
+            let mut bytes = err.into_vec();
+            bytes.zeroize();
+            Err(FfiError::InvalidArgument(
+                "The entry has a NUL character in it, which C strings can't hold.".to_string(),
+            ))
+        }
+    }
+}
+
+/// Opens the vault in a data directory, creating it if needed, and stores it
+/// in `out_vault`. The vault is locked until `np_vault_unlock` is called.
+///
+/// # Safety
+///
+/// `data_dir` must be a NUL-terminated string, and `out_vault` must point to
+/// writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn np_vault_open(
+    data_dir: *const c_char,
+    out_vault: *mut *mut NpVault,
+) -> i32 {
+    run(|| {
+        let data_dir = str_argument(data_dir, "data_dir")?;
+        let out_vault = out_argument(out_vault, "out_vault")?;
+
+        *out_vault = Box::into_raw(Box::new(NpVault {
+            session: Session::open(data_dir)?,
+        }));
+        Ok(())
+    })
+}
+
+/// Closes a vault, forgetting its master key. `vault` may be `NULL`.
+///
+/// # Safety
+///
+/// `vault` must come from `np_vault_open`, and not be used anymore afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn np_vault_close(vault: *mut NpVault) {
+    if !vault.is_null() {
+        drop(Box::from_raw(vault));
+    }
+}
+
+/// Checks the master key, and keeps it until the vault is locked or closed.
+///
+/// # Safety
+///
+/// `vault` must come from `np_vault_open`, and `master_key` must be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn np_vault_unlock(vault: *mut NpVault, master_key: *const c_char) -> i32 {
+    run(|| {
+        let vault = vault_argument(vault)?;
+        let master_key = str_argument(master_key, "master_key")?;
+
+        vault.session.unlock(master_key)?;
+        Ok(())
+    })
+}
+
+/// Forgets the master key.
+///
+/// # Safety
+///
+/// `vault` must come from `np_vault_open`.
+#[no_mangle]
+pub unsafe extern "C" fn np_vault_lock(vault: *mut NpVault) -> i32 {
+    run(|| {
+        vault_argument(vault)?.session.lock();
+        Ok(())
+    })
+}
+
+/// Lists the names of the entries. `out_names` gets an array of `out_len`
+/// strings, to free with `np_string_array_free`.
+///
+/// # Safety
+///
+/// `vault` must come from `np_vault_open`, and `out_names` and `out_len` must
+/// point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn np_entry_list(
+    vault: *mut NpVault,
+    out_names: *mut *mut *mut c_char,
+    out_len: *mut usize,
+) -> i32 {
+    run(|| {
+        let vault = vault_argument(vault)?;
+        let out_names = out_argument(out_names, "out_names")?;
+        let out_len = out_argument(out_len, "out_len")?;
+
+        let mut names = Vec::new();
+        for name in vault.session.list_entries()? {
+            match into_c_string(name) {
+                Ok(name) => names.push(name),
+                Err(err) => {
+                    for name in names {
+                        np_string_free(name);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        let names = names.into_boxed_slice();
+
+        *out_len = names.len();
+        *out_names = Box::into_raw(names) as *mut *mut c_char;
+        Ok(())
+    })
+}
+
+/// Decrypts an entry. `out_password` gets the password, to free with
+/// `np_string_free`.
+///
+/// # Safety
+///
+/// `vault` must come from `np_vault_open`, `name` must be a NUL-terminated
+/// string, and `out_password` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn np_entry_get(
+    vault: *mut NpVault,
+    name: *const c_char,
+    out_password: *mut *mut c_char,
+) -> i32 {
+    run(|| {
+        let vault = vault_argument(vault)?;
+        let name = str_argument(name, "name")?;
+        let out_password = out_argument(out_password, "out_password")?;
+
+        *out_password = into_c_string(vault.session.get_entry(name)?)?;
+        Ok(())
+    })
+}
+
+/// Saves a new entry.
+///
+/// # Safety
+///
+/// `vault` must come from `np_vault_open`, and `name` and `password` must be
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn np_entry_save(
+    vault: *mut NpVault,
+    name: *const c_char,
+    password: *const c_char,
+) -> i32 {
+    run(|| {
+        let vault = vault_argument(vault)?;
+        let name = str_argument(name, "name")?;
+        let password = str_argument(password, "password")?;
+
+        vault.session.save_entry(name, password)?;
+        Ok(())
+    })
+}
+
+/// Deletes an entry.
+///
+/// # Safety
+///
+/// `vault` must come from `np_vault_open`, and `name` must be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn np_entry_delete(vault: *mut NpVault, name: *const c_char) -> i32 {
+    run(|| {
+        let vault = vault_argument(vault)?;
+        let name = str_argument(name, "name")?;
+
+        vault.session.delete_entry(name)?;
+        Ok(())
+    })
+}
+
+/// Generates a random password, to free with `np_string_free`.
+#[no_mangle]
+pub extern "C" fn np_generate_password() -> *mut c_char {
+    // Generated passwords are printable ASCII, so they never have a NUL.
+    into_c_string(String::from_utf8_lossy(&neng_pass::generate_password()).to_string())
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// The message of the last error on this thread, or `NULL` if there was none.
+/// It stays valid until the next call that fails on the same thread.
+#[no_mangle]
+pub extern "C" fn np_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Wipes and frees a string returned by neng-pass. `string` may be `NULL`.
+///
+/// # Safety
+///
+/// `string` must come from neng-pass, and not be used anymore afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn np_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        CString::from_raw(string).into_bytes_with_nul().zeroize();
+    }
+}
+
+/// Frees an array of strings returned by `np_entry_list`, and the strings in
+/// it. `names` may be `NULL`.
+///
+/// # Safety
+///
+/// `names` and `len` must come from `np_entry_list`, and not be used anymore
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn np_string_array_free(names: *mut *mut c_char, len: usize) {
+    if names.is_null() {
+        return;
+    }
+
+    let names = Box::from_raw(std::ptr::slice_from_raw_parts_mut(names, len));
+    for name in names.iter() {
+        np_string_free(*name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(np_last_error_message()) }
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn entries_round_trip_through_c() {
+        let data_dir = tempfile::tempdir().unwrap();
+        neng_pass::set_master_key(
+            &data_dir.path().join("master_key").to_string_lossy(),
+            "hunter2",
+        )
+        .unwrap();
+        let data_dir = CString::new(data_dir.path().to_string_lossy().as_bytes()).unwrap();
+
+        unsafe {
+            let mut vault = std::ptr::null_mut();
+            assert_eq!(np_vault_open(data_dir.as_ptr(), &mut vault), NP_OK);
+
+            let github = CString::new("github").unwrap();
+            let mut password = std::ptr::null_mut();
+            assert_eq!(
+                np_entry_get(vault, github.as_ptr(), &mut password),
+                NP_ERROR_LOCKED
+            );
+            assert_eq!(last_error(), "The vault is locked, unlock it first.");
+
+            let wrong_key = CString::new("hunter3").unwrap();
+            assert_eq!(np_vault_unlock(vault, wrong_key.as_ptr()), NP_ERROR_AUTH);
+            let master_key = CString::new("hunter2").unwrap();
+            assert_eq!(np_vault_unlock(vault, master_key.as_ptr()), NP_OK);
+
+            let saved = CString::new("hunter4").unwrap();
+            assert_eq!(np_entry_save(vault, github.as_ptr(), saved.as_ptr()), NP_OK);
+            assert_eq!(np_entry_get(vault, github.as_ptr(), &mut password), NP_OK);
+            assert_eq!(CStr::from_ptr(password), saved.as_c_str());
+            np_string_free(password);
+
+            let mut names = std::ptr::null_mut();
+            let mut len = 0;
+            assert_eq!(np_entry_list(vault, &mut names, &mut len), NP_OK);
+            assert_eq!(len, 1);
+            assert_eq!(CStr::from_ptr(*names), github.as_c_str());
+            np_string_array_free(names, len);
+
+            let gitlab = CString::new("gitlab").unwrap();
+            assert_eq!(
+                np_entry_get(vault, gitlab.as_ptr(), &mut password),
+                NP_ERROR_NOT_FOUND
+            );
+            assert_eq!(
+                np_entry_get(vault, std::ptr::null(), &mut password),
+                NP_ERROR_INVALID
+            );
+            assert_eq!(last_error(), "name is NULL");
+
+            np_vault_close(vault);
+        }
+    }
+
+    #[test]
+    fn entries_with_nul_are_refused() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_dir_string = CString::new(data_dir.path().to_string_lossy().as_bytes()).unwrap();
+
+        unsafe {
+            let mut vault = std::ptr::null_mut();
+            assert_eq!(np_vault_open(data_dir_string.as_ptr(), &mut vault), NP_OK);
+            (*vault).session.set_master_key("hunter2").unwrap();
+            (*vault)
+                .session
+                .save_entry("github", "hunter\0four")
+                .unwrap();
+
+            let github = CString::new("github").unwrap();
+            let mut password = std::ptr::null_mut();
+            assert_eq!(
+                np_entry_get(vault, github.as_ptr(), &mut password),
+                NP_ERROR_INVALID
+            );
+            assert!(password.is_null());
+
+            (*vault).session.save_entry("git\0hub", "hunter4").unwrap();
+            let mut names = std::ptr::null_mut();
+            let mut len = 0;
+            assert_eq!(np_entry_list(vault, &mut names, &mut len), NP_ERROR_INVALID);
+            assert!(names.is_null());
+
+            np_vault_close(vault);
+        }
+    }
+
+    /// The header has to be regenerated by hand, so check that it was. Skipped
+    /// where cbindgen isn't installed.
+    #[test]
+    fn header_is_up_to_date() {
+        let crate_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        let output = match std::process::Command::new("cbindgen")
+            .current_dir(crate_dir)
+            .args(["--config", "cbindgen.toml", "--quiet"])
+            .output()
+        {
+            Ok(output) => output,
+            Err(_) => {
+                eprintln!("cbindgen isn't installed, skipping");
+                return;
+            }
+        };
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let header = std::fs::read_to_string(crate_dir.join("include/neng_pass.h")).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            header,
+            "include/neng_pass.h is out of date, regenerate it with cbindgen"
+        );
+    }
+}
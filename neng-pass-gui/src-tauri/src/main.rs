@@ -1,17 +1,24 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::{fs::File, path::PathBuf, sync::Mutex};
-use neng_pass::rusqlite;
+use std::{collections::HashMap, sync::Mutex};
 
-use directories::ProjectDirs;
+use neng_pass::{
+    config::{Config, Settings},
+    vault::{Location, Vault},
+};
+use serde::Serialize;
 
 struct InternalState {
-    master_key: Option<String>,
+    /// The name of the vault the commands work on.
+    current_vault: String,
+    /// The vaults that were unlocked, by name. They stay open when switching
+    /// to another one.
+    open_vaults: HashMap<String, Vault>,
 }
 
 struct StaticState {
-    data_dir: PathBuf,
+    config: Config,
 }
 
 struct State {
@@ -21,77 +28,86 @@ struct State {
 
 impl State {
     fn new() -> State {
-        // Ensure that the data directory is created properly.
-        let data_dir = match ProjectDirs::from("io", "earthtraveller1", "neng-pass") {
-            Some(project_dirs) => project_dirs.data_dir().to_owned(),
-            None => PathBuf::from("./.neng-pass"),
+        let config = match Config::load(&neng_pass::config::config_file()) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!(
+                    "[WARNING]: {} Only the default vault is available.",
+                    err.get_message()
+                );
+                Config::default()
+            }
         };
 
-        std::fs::create_dir_all(&data_dir).unwrap();
+        let current_vault =
+            std::env::var("NENG_PASS_VAULT").unwrap_or_else(|_| config.default_vault().to_string());
 
         State {
-            internal_state: Mutex::new(InternalState { master_key: None }),
-            static_state: StaticState { data_dir },
+            internal_state: Mutex::new(InternalState {
+                current_vault,
+                open_vaults: HashMap::new(),
+            }),
+            static_state: StaticState { config },
         }
     }
-}
 
-fn open_and_prepare_database(data_dir: &PathBuf) -> Result<rusqlite::Connection, String> {
-    let sql_connection = match rusqlite::Connection::open(data_dir.join("passwords.db")) {
-        Ok(connection) => connection,
-        Err(err) => return Err(format!("Failed to open the database: {}", err)),
-    };
+    fn current_location(&self) -> Result<Location, String> {
+        let internal_state = self.internal_state.lock().unwrap();
+        Ok(self
+            .static_state
+            .config
+            .resolve(Some(&internal_state.current_vault))?)
+    }
 
-    if let Err(err) =
-        sql_connection.execute("CREATE TABLE IF NOT EXISTS passwords (name TEXT, password BLOB);", ())
-    {
-        return Err(format!(
-            "Failed to prepare the table in the database: {}",
-            err
-        ));
+    fn current_settings(&self) -> Settings {
+        let internal_state = self.internal_state.lock().unwrap();
+        self.static_state
+            .config
+            .settings(Some(&internal_state.current_vault))
     }
+}
+
+/// Runs `p_body` on the current vault, which has to be unlocked, and saves
+/// what it changed.
+fn with_vault<T>(
+    p_state: &State,
+    p_body: impl FnOnce(&Vault) -> Result<T, neng_pass::Error>,
+) -> Result<T, String> {
+    let mut internal_state = p_state.internal_state.lock().unwrap();
+    let current_vault = internal_state.current_vault.clone();
+    let vault = internal_state
+        .open_vaults
+        .get_mut(&current_vault)
+        .ok_or("The master key has not been set!")?;
 
-    Ok(sql_connection)
+    let result = p_body(vault)?;
+    vault.save()?;
+
+    Ok(result)
 }
 
 #[tauri::command]
 fn get_password_list(p_state: tauri::State<'_, State>) -> Result<Vec<String>, String> {
-    let sql_connection = open_and_prepare_database(&p_state.static_state.data_dir)?;
-
-    let mut sql_statement = match sql_connection.prepare("SELECT name FROM passwords") {
-        Err(err) => {
-            return Err(format!(
-                "Failed to query the database for a list of passwords: {}",
-                err
-            ))
-        }
-        Ok(statement) => statement,
-    };
-
-    let mut password_names = Vec::new();
-    if let Ok(password_names_query) = sql_statement.query_map((), |row| row.get::<_, String>(0)) {
-        for row in password_names_query {
-            match row {
-                Ok(row) => {
-                    password_names.push(row);
-                }
-                Err(err) => {
-                    return Err(format!("Failed to read a row from the database: {}", err));
-                }
-            }
-        }
-    } else {
-        return Err("Failed to read rows from the database.".to_string());
-    }
+    with_vault(&p_state, |vault| {
+        neng_pass::list_passwords(vault.sql_connection())
+    })
+    .map_err(|err| {
+        format!(
+            "Failed to query the database for a list of passwords: {}",
+            err
+        )
+    })
+}
 
-    Ok(password_names)
+#[tauri::command]
+fn generate_password(p_state: tauri::State<'_, State>) -> String {
+    neng_pass::generate_password_with(&p_state.current_settings().generator)
 }
 
+/// How many seconds a copied password stays in the clipboard, 0 for ever.
 #[tauri::command]
-fn generate_password() -> Result<String, ()> {
-    std::str::from_utf8(&neng_pass::generate_password())
-        .map(|s| s.to_string())
-        .map_err(|_| ())
+fn get_clipboard_clear_delay(p_state: tauri::State<'_, State>) -> u64 {
+    p_state.current_settings().clipboard_clear_delay.as_secs()
 }
 
 #[tauri::command]
@@ -100,94 +116,160 @@ fn save_password(
     p_name: &str,
     p_password: &str,
 ) -> Result<(), String> {
-    let sql_connection = open_and_prepare_database(&p_state.static_state.data_dir)?;
-    let internal_state = p_state.internal_state.lock().unwrap();
-
-    let master_key = match &internal_state.master_key {
-        Some(master_key) => master_key,
-        None => return Err("The master has not been set yet!".to_string()),
-    };
-
-    match neng_pass::create_password(master_key.clone(), p_name, p_password, &sql_connection) {
-        Ok(_) => Ok(()),
-        Err(err) => Err(err.get_message()),
-    }
+    with_vault(&p_state, |vault| {
+        neng_pass::create_password(
+            vault.master_key().to_string(),
+            p_name,
+            p_password,
+            vault.sql_connection(),
+        )
+    })
 }
 
 #[tauri::command]
 async fn is_master_key_correct(
     p_state: tauri::State<'_, State>,
     p_master_key: &str,
-) -> Result<bool, ()> {
-    let mut data_dir = p_state.static_state.data_dir.clone();
-    data_dir.push("master_key");
+) -> Result<bool, String> {
+    let location = p_state.current_location()?;
 
-    Ok(neng_pass::query_master_key(data_dir.to_str().unwrap(), p_master_key).is_ok())
+    Ok(Vault::open(location, p_master_key).is_ok())
 }
 
+/// Unlocks the current vault with this master key.
 #[tauri::command]
 async fn set_master_key(
     p_master_key: &str,
     p_state: tauri::State<'_, State>,
 ) -> Result<(), String> {
-    (match p_state.internal_state.lock() {
-        Ok(key) => key,
+    let location = p_state.current_location()?;
+    let mut vault = Vault::open(location, p_master_key)?;
+    vault.set_backups(p_state.current_settings().backups);
+
+    let mut internal_state = match p_state.internal_state.lock() {
+        Ok(internal_state) => internal_state,
         Err(_) => return Err("Failed to acquire a lock for the master key.".to_string()),
-    })
-    .master_key = Some(p_master_key.to_string());
+    };
+    let current_vault = internal_state.current_vault.clone();
+    internal_state.open_vaults.insert(current_vault, vault);
 
     Ok(())
 }
 
 #[tauri::command]
 async fn get_password(p_name: &str, p_state: tauri::State<'_, State>) -> Result<String, String> {
-    let internal_state = p_state.internal_state.lock().unwrap();
-    let master_key = internal_state
-        .master_key
-        .clone()
-        .ok_or("The master key has not been set!")?;
+    with_vault(&p_state, |vault| {
+        neng_pass::get_password(
+            vault.master_key().to_string(),
+            p_name,
+            vault.sql_connection(),
+        )
+    })
+}
 
-    let sql_connection = open_and_prepare_database(&p_state.static_state.data_dir)?;
-    let password = neng_pass::get_password(master_key, p_name, &sql_connection)?;
+#[tauri::command]
+async fn delete_password(p_name: &str, p_state: tauri::State<'_, State>) -> Result<(), String> {
+    with_vault(&p_state, |vault| {
+        neng_pass::delete_password(p_name, vault.sql_connection())
+    })
+}
 
-    Ok(password)
+#[tauri::command]
+fn is_master_key_set(p_state: tauri::State<'_, State>) -> Result<bool, String> {
+    Ok(p_state.current_location()?.exists())
 }
 
 #[tauri::command]
-async fn delete_password(p_name: &str, p_state: tauri::State<'_, State>) -> Result<(), String> {
-    let sql_connection = open_and_prepare_database(&p_state.static_state.data_dir)?;
-    neng_pass::delete_password(p_name, &sql_connection)?;
+fn set_new_master_key(
+    p_state: tauri::State<'_, State>,
+    p_new_master_key: &str,
+) -> Result<(), String> {
+    p_state.current_location()?.create(p_new_master_key)?;
 
     Ok(())
 }
 
 #[tauri::command]
-fn is_master_key_set(p_state: tauri::State<'_, State>) -> bool {
-    let mut master_key_path = p_state.static_state.data_dir.clone();
-    master_key_path.push("master_key");
+async fn get_public_key(p_state: tauri::State<'_, State>) -> Result<String, String> {
+    with_vault(&p_state, |vault| {
+        let identity =
+            neng_pass::share::get_or_create_identity(vault.master_key(), vault.sql_connection())?;
+        Ok(identity.to_public().to_string())
+    })
+}
 
-    match File::open(&master_key_path) {
-        Ok(_) => true,
-        Err(_) => false,
-    }
+#[tauri::command]
+async fn share_entry(
+    p_name: &str,
+    p_recipient: &str,
+    p_state: tauri::State<'_, State>,
+) -> Result<String, String> {
+    with_vault(&p_state, |vault| {
+        neng_pass::share::share_entry(
+            vault.master_key(),
+            p_name,
+            p_recipient,
+            vault.sql_connection(),
+        )
+    })
 }
 
 #[tauri::command]
-fn set_new_master_key(
+async fn receive_entry(
+    p_blob: &str,
+    p_name: Option<&str>,
     p_state: tauri::State<'_, State>,
-    p_new_master_key: &str,
-) -> Result<(), String> {
-    let mut master_key_path = p_state.static_state.data_dir.clone();
-    master_key_path.push("master_key");
+) -> Result<String, String> {
+    with_vault(&p_state, |vault| {
+        neng_pass::share::receive_entry(vault.master_key(), p_blob, p_name, vault.sql_connection())
+    })
+}
 
-    neng_pass::set_master_key(
-        master_key_path
-            .to_str()
-            .ok_or(neng_pass::Error::UnknownError)?,
-        p_new_master_key,
-    )?;
+#[derive(Serialize)]
+struct VaultInfo {
+    name: String,
+    path: String,
+    current: bool,
+    unlocked: bool,
+}
 
-    Ok(())
+#[tauri::command]
+fn list_vaults(p_state: tauri::State<'_, State>) -> Vec<VaultInfo> {
+    let internal_state = p_state.internal_state.lock().unwrap();
+    let mut vaults = p_state.static_state.config.vaults();
+
+    // The vault may have been given by its path.
+    if let Ok(location) = p_state
+        .static_state
+        .config
+        .resolve(Some(&internal_state.current_vault))
+    {
+        vaults
+            .entry(internal_state.current_vault.clone())
+            .or_insert(location);
+    }
+
+    vaults
+        .into_iter()
+        .map(|(name, location)| VaultInfo {
+            path: location.path().display().to_string(),
+            current: name == internal_state.current_vault,
+            unlocked: internal_state.open_vaults.contains_key(&name),
+            name,
+        })
+        .collect()
+}
+
+/// Makes the commands work on another vault. Returns whether it is unlocked
+/// already.
+#[tauri::command]
+fn switch_vault(p_name: &str, p_state: tauri::State<'_, State>) -> Result<bool, String> {
+    p_state.static_state.config.resolve(Some(p_name))?;
+
+    let mut internal_state = p_state.internal_state.lock().unwrap();
+    internal_state.current_vault = p_name.to_string();
+
+    Ok(internal_state.open_vaults.contains_key(p_name))
 }
 
 fn main() {
@@ -196,13 +278,19 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             delete_password,
             generate_password,
+            get_clipboard_clear_delay,
             get_password,
             get_password_list,
+            get_public_key,
             is_master_key_correct,
             is_master_key_set,
+            list_vaults,
+            receive_entry,
             save_password,
             set_master_key,
             set_new_master_key,
+            share_entry,
+            switch_vault,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -6,6 +6,7 @@ import Passwords from "./Passwords"
 import { invoke } from "@tauri-apps/api"
 import NewPassword from "./NewPassword"
 import Password from "./Password"
+import VaultPicker from "./components/VaultPicker"
 
 export interface PageContextType {
     setPage: (newPage: Page) => void,
@@ -26,7 +27,7 @@ export default function Index() {
     const [getCurrentPage, setCurrentPage] = createSignal(Page.Login)
     const [getCurrentPassword, setCurrentPassword] = createSignal<string | null>(null)
 
-    const [isMasterKeySet, _] = createResource(async () => {
+    const [isMasterKeySet, { refetch: refetchIsMasterKeySet }] = createResource(async () => {
         const isMasterKeySet = await invoke<boolean>("is_master_key_set")
         return isMasterKeySet
     })
@@ -34,6 +35,16 @@ export default function Index() {
     return <>
         <PasswordContext.Provider value={{setPassword: setCurrentPassword, getPassword: getCurrentPassword}}>
             <PageContext.Provider value={{setPage: setCurrentPage, getPage: getCurrentPage }}>
+                <Show when={getCurrentPage() == Page.Login || getCurrentPage() == Page.Passwords}>
+                    <VaultPicker onSwitch={(unlocked) => {
+                        refetchIsMasterKeySet()
+                        // Remounting the page reloads the passwords of the other vault.
+                        setCurrentPage(Page.Login)
+                        if (unlocked) {
+                            setCurrentPage(Page.Passwords)
+                        }
+                    }} />
+                </Show>
                 <Show when={getCurrentPage() == Page.Login}>
                     <Show when={isMasterKeySet()} fallback={<Setup />}> 
                         <Login />
@@ -21,8 +21,8 @@ export default function Login() {
         }
 
         if (masterKeyCorrect) {
-            pageContext.setPage(Page.Passwords)
             await invoke("set_master_key", { pMasterKey: masterKey})
+            pageContext.setPage(Page.Passwords)
         } else {
             setMessageHeight("max-h-auto p-2 mb-6")
         }
@@ -29,6 +29,16 @@ export default function Password() {
                 const password = await invoke<string>("get_password", {pName: passwordName})
                 await navigator.clipboard.writeText(password)
                 setFirstButtonLabel("Copied to Clipboard!")
+
+                const delay = await invoke<number>("get_clipboard_clear_delay")
+                if (delay > 0) {
+                    setTimeout(async () => {
+                        // Only clear it if nothing else was copied since.
+                        if (await navigator.clipboard.readText() == password) {
+                            await navigator.clipboard.writeText("")
+                        }
+                    }, delay * 1000)
+                }
             } catch (error) {
                 setFirstButtonLabel("Sorry, something went wrong")
             }
@@ -0,0 +1,36 @@
+import { For, Show, createResource } from "solid-js"
+import { invoke } from "@tauri-apps/api"
+
+interface VaultInfo {
+    name: string,
+    path: string,
+    current: boolean,
+    unlocked: boolean,
+}
+
+interface VaultPickerProps {
+    onSwitch: (unlocked: boolean) => void
+}
+
+export default function VaultPicker(props: VaultPickerProps) {
+    const [vaults, { refetch }] = createResource(async () => {
+        return await invoke<VaultInfo[]>("list_vaults")
+    })
+
+    return <Show when={(vaults() ?? []).length > 1}>
+        <select
+            class="p-2 mx-10 mt-6 max-w-4xl bg-neutral-800 outline-none hover:bg-neutral-700 duration-300"
+            onChange={async (event) => {
+                const unlocked = await invoke<boolean>("switch_vault", { pName: event.target.value })
+                await refetch()
+                props.onSwitch(unlocked)
+            }}
+        >
+            <For each={vaults()}>
+                {(vault) => <option value={vault.name} selected={vault.current} title={vault.path}>
+                    {vault.name}
+                </option>}
+            </For>
+        </select>
+    </Show>
+}
@@ -0,0 +1,350 @@
+//! The native messaging protocol, through which a browser extension can
+//! autofill logins from the vault.
+//!
+//! The browser starts the host and writes requests to its standard input,
+//! each one a JSON object preceded by its length as a 32-bit integer in
+//! native byte order. Every request gets exactly one response, framed the
+//! same way. A request can carry an `id`, which is copied into its response.
+//!
+//! Logins are regular entries, named after the site they are for and the
+//! user name, if any: `github.com` or `octocat@github.com`. Which entries
+//! are offered for a page is decided by `neng_pass::autofill`.
+
+use std::io::{Read, Write};
+
+use neng_pass::{autofill::entry_username, Error};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Browsers never send more than this, and can't receive more either.
+pub const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// The logins for the site a page is on.
+    ListForUrl {
+        url: String,
+    },
+    /// The password of one of the logins listed for the page at `url`.
+    GetCredentials {
+        url: String,
+        name: String,
+    },
+    /// Saves a login that was just used on a page, replacing the one with the
+    /// same site and user name.
+    SaveCredentials {
+        url: String,
+        username: Option<String>,
+        password: String,
+    },
+    Generate,
+}
+
+/// Reads the next message, or returns `None` once the browser has closed the
+/// standard input.
+pub fn read_message(p_input: &mut impl Read) -> Result<Option<Vec<u8>>, Error> {
+    let mut length = [0u8; 4];
+    match p_input.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_MESSAGE_LEN {
+        return Err(Error::SyntaxError(format!(
+            "a message of {} bytes is longer than the {} bytes allowed",
+            length, MAX_MESSAGE_LEN
+        )));
+    }
+
+    let mut message = vec![0u8; length];
+    p_input.read_exact(&mut message)?;
+
+    Ok(Some(message))
+}
+
+pub fn write_message(p_output: &mut impl Write, p_message: &Value) -> Result<(), Error> {
+    let message = p_message.to_string();
+    p_output.write_all(&(message.len() as u32).to_ne_bytes())?;
+    p_output.write_all(message.as_bytes())?;
+    p_output.flush()?;
+    Ok(())
+}
+
+fn error_response(p_code: &str, p_message: &str) -> Value {
+    json!({ "error": { "code": p_code, "message": p_message } })
+}
+
+fn list_for_url(p_url: &str, p_sql_connection: &rusqlite::Connection) -> Result<Value, Error> {
+    let entries = neng_pass::autofill::find_entries(p_url, p_sql_connection)?
+        .into_iter()
+        .map(|candidate| {
+            json!({
+                "name": candidate.name,
+                "username": entry_username(&candidate.name),
+                "match": candidate.matched.as_str(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(json!({ "entries": entries }))
+}
+
+/// Only the logins offered for the page can be read, so that a page can't get
+/// the password of another site by asking for it by name.
+fn get_credentials(
+    p_master_key: &str,
+    p_url: &str,
+    p_name: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<Value, Error> {
+    let is_offered = neng_pass::autofill::find_entries(p_url, p_sql_connection)?
+        .iter()
+        .any(|candidate| candidate.name == p_name);
+    if !is_offered {
+        return Err(Error::PasswordDoesntExist(Box::from(p_name)));
+    }
+
+    let password = neng_pass::get_password(p_master_key.to_string(), p_name, p_sql_connection)?;
+    Ok(json!({
+        "name": p_name,
+        "username": entry_username(p_name),
+        "password": password.trim_end_matches('\0'),
+    }))
+}
+
+fn save_credentials(
+    p_master_key: &str,
+    p_url: &str,
+    p_username: Option<&str>,
+    p_password: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<Value, Error> {
+    let name = neng_pass::autofill::save_login(
+        &neng_pass::MasterKey::new(p_master_key)?,
+        p_url,
+        p_username,
+        p_password,
+        p_sql_connection,
+    )?;
+
+    Ok(json!({ "name": name }))
+}
+
+/// Answers one request. Whatever is wrong with it, the answer is a response
+/// for the browser rather than an error, so that the host keeps running.
+pub fn handle_request(
+    p_request: &[u8],
+    p_master_key: Option<&str>,
+    p_sql_connection: &rusqlite::Connection,
+) -> Value {
+    let request: Value = match serde_json::from_slice(p_request) {
+        Ok(request) => request,
+        Err(err) => {
+            return error_response(
+                "invalid_request",
+                &format!("The request isn't JSON: {}", err),
+            )
+        }
+    };
+    let id = request.get("id").cloned();
+
+    let mut response = match serde_json::from_value::<Request>(request) {
+        Ok(request) => {
+            let locked = || {
+                error_response(
+                    "locked",
+                    "The vault is locked. Unlock it with `neng-pass unlock` first.",
+                )
+            };
+
+            let result = match request {
+                // Even the names of the logins are only shown while the vault
+                // is unlocked.
+                Request::ListForUrl { url } => {
+                    p_master_key.map(|_| list_for_url(&url, p_sql_connection))
+                }
+                Request::GetCredentials { url, name } => p_master_key.map(|master_key| {
+                    get_credentials(master_key, &url, &name, p_sql_connection)
+                }),
+                Request::SaveCredentials {
+                    url,
+                    username,
+                    password,
+                } => p_master_key.map(|master_key| {
+                    save_credentials(
+                        master_key,
+                        &url,
+                        username.as_deref(),
+                        &password,
+                        p_sql_connection,
+                    )
+                }),
+                Request::Generate => {
+                    let password = neng_pass::generate_password();
+                    Some(Ok(
+                        json!({ "password": String::from_utf8_lossy(&password) }),
+                    ))
+                }
+            };
+
+            match result {
+                Some(Ok(response)) => response,
+                Some(Err(err)) => error_response(err.code(), &err.get_message()),
+                None => locked(),
+            }
+        }
+        Err(err) => error_response(
+            "invalid_request",
+            &format!("The request isn't valid: {}", err),
+        ),
+    };
+
+    if let (Some(id), Some(response)) = (id, response.as_object_mut()) {
+        response.insert("id".to_string(), id);
+    }
+
+    response
+}
+
+/// Answers requests until the browser closes the input. The master key is
+/// asked for on every request, since the vault can be locked and unlocked
+/// while the host runs.
+pub fn serve(
+    mut p_input: impl Read,
+    mut p_output: impl Write,
+    p_sql_connection: &rusqlite::Connection,
+    p_master_key: impl Fn() -> Option<String>,
+) -> Result<(), Error> {
+    while let Some(request) = read_message(&mut p_input)? {
+        let response = handle_request(&request, p_master_key().as_deref(), p_sql_connection);
+        write_message(&mut p_output, &response)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const MASTER_KEY: &str = "hunter2";
+
+    fn vault() -> rusqlite::Connection {
+        let sql_connection = rusqlite::Connection::open_in_memory().unwrap();
+        neng_pass::prepare_database(&sql_connection).unwrap();
+        neng_pass::autofill::save_login(
+            &neng_pass::MasterKey::new(MASTER_KEY).unwrap(),
+            "https://github.com/login",
+            Some("octocat"),
+            "hunter3",
+            &sql_connection,
+        )
+        .unwrap();
+        sql_connection
+    }
+
+    fn frame(p_message: Value) -> Vec<u8> {
+        let mut framed = Vec::new();
+        write_message(&mut framed, &p_message).unwrap();
+        framed
+    }
+
+    /// Feeds the frames to the host, and returns what it answered, with how
+    /// it stopped.
+    fn exchange(p_input: Vec<u8>, p_master_key: Option<&str>) -> (Vec<Value>, Result<(), Error>) {
+        let mut output = Vec::new();
+        let result = serve(Cursor::new(p_input), &mut output, &vault(), || {
+            p_master_key.map(str::to_string)
+        });
+
+        let mut output = Cursor::new(output);
+        let mut responses = Vec::new();
+        while let Some(response) = read_message(&mut output).unwrap() {
+            responses.push(serde_json::from_slice(&response).unwrap());
+        }
+
+        (responses, result)
+    }
+
+    #[test]
+    fn answers_every_request() {
+        let input = [
+            frame(json!({ "id": 1, "type": "list_for_url", "url": "https://gist.github.com/" })),
+            frame(json!({
+                "id": 2,
+                "type": "get_credentials",
+                "url": "https://github.com/",
+                "name": "octocat@github.com",
+            })),
+            b"\x02\0\0\0{]".to_vec(),
+        ]
+        .concat();
+
+        let (responses, result) = exchange(input, Some(MASTER_KEY));
+        assert!(result.is_ok());
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["entries"][0]["name"], "octocat@github.com");
+        assert_eq!(responses[1]["id"], 2);
+        assert_eq!(responses[1]["password"], "hunter3");
+        assert_eq!(responses[2]["error"]["code"], "invalid_request");
+    }
+
+    #[test]
+    fn other_sites_get_nothing() {
+        let input = frame(json!({
+            "type": "get_credentials",
+            "url": "https://example.com/",
+            "name": "octocat@github.com",
+        }));
+
+        let (responses, _) = exchange(input, Some(MASTER_KEY));
+        assert_eq!(responses[0]["error"]["code"], "password_not_found");
+        assert!(responses[0].get("password").is_none());
+    }
+
+    #[test]
+    fn nothing_is_listed_while_locked() {
+        let input = frame(json!({ "type": "list_for_url", "url": "https://github.com/" }));
+
+        let (responses, _) = exchange(input, None);
+        assert_eq!(responses[0]["error"]["code"], "locked");
+        assert!(responses[0].get("entries").is_none());
+    }
+
+    #[test]
+    fn oversized_messages_stop_the_host() {
+        let mut input = frame(json!({ "type": "generate" }));
+        input.extend((MAX_MESSAGE_LEN as u32 + 1).to_ne_bytes());
+        input.extend(b"{}");
+
+        let (responses, result) = exchange(input, None);
+        assert_eq!(responses.len(), 1);
+        assert!(matches!(result, Err(Error::SyntaxError(_))));
+    }
+
+    #[test]
+    fn truncated_messages_stop_the_host() {
+        // The message is shorter than its length says.
+        let mut input = frame(json!({ "type": "generate" }));
+        input.extend(100u32.to_ne_bytes());
+        input.extend(b"{\"type\": ");
+
+        let (responses, result) = exchange(input, None);
+        assert_eq!(responses.len(), 1);
+        assert!(result.is_err());
+
+        // A length cut short is taken as the browser going away.
+        let mut input = frame(json!({ "type": "generate" }));
+        input.extend([1, 0]);
+
+        let (responses, result) = exchange(input, None);
+        assert_eq!(responses.len(), 1);
+        assert!(result.is_ok());
+    }
+}
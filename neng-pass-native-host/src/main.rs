@@ -0,0 +1,73 @@
+use std::path::Path;
+
+use neng_pass::{config::Config, vault::Location};
+
+/// The master key, from the agent that `neng-pass unlock` starts. There is no
+/// terminal to ask for it, so the vault is locked without the agent.
+fn master_key(p_data_dir: &Path) -> Option<String> {
+    #[cfg(unix)]
+    return neng_pass::agent::get_master_key(&p_data_dir.join("agent.sock"));
+
+    #[cfg(not(unix))]
+    {
+        let _ = p_data_dir;
+        None
+    }
+}
+
+// Browsers pass their own arguments (the extension's origin, the manifest's
+// path), none of which matter here, so there is no command line to parse, and
+// the vault can only be picked with NENG_PASS_VAULT.
+fn main() {
+    let vault = std::env::var("NENG_PASS_VAULT").ok();
+    let location = Config::load(&neng_pass::config::config_file())
+        .and_then(|config| config.resolve(vault.as_deref()));
+    let data_dir = match location {
+        Ok(Location::Directory(data_dir)) => data_dir,
+        Ok(Location::File(path)) => {
+            eprintln!(
+                "[ERROR]: {} is a single-file vault, which only works with vault directories, since the master key is taken from the agent.",
+                path.display()
+            );
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("[ERROR]: {}", err.get_message());
+            std::process::exit(1);
+        }
+    };
+    let data_dir = data_dir.as_path();
+
+    let database_file = data_dir.join("passwords.db");
+    let sql_connection = match rusqlite::Connection::open(&database_file) {
+        Ok(connection) => connection,
+        Err(err) => {
+            eprintln!(
+                "[ERROR]: Failed to open {}: {}",
+                database_file.display(),
+                err
+            );
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = neng_pass::prepare_database(&sql_connection) {
+        eprintln!(
+            "[ERROR]: Failed to prepare the vault: {}",
+            err.get_message()
+        );
+        std::process::exit(1);
+    }
+
+    let result = neng_pass_native_host::serve(
+        std::io::stdin().lock(),
+        std::io::stdout().lock(),
+        &sql_connection,
+        || master_key(data_dir),
+    );
+
+    if let Err(err) = result {
+        eprintln!("[ERROR]: {}", err.get_message());
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,927 @@
+//! A provider of the freedesktop.org Secret Service API, so that programs
+//! that use libsecret (browsers, mail clients, `secret-tool`) keep their
+//! secrets in the vault instead of in another keyring.
+//!
+//! The vault is unlocked as long as a master key can be had, which is when
+//! the neng-pass agent holds one. Unlocking through the API starts a prompt
+//! that asks for the master key and hands it to the agent.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::StreamExt;
+use zbus::{
+    object_server::SignalContext,
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+    ObjectServer,
+};
+
+pub mod session;
+pub mod store;
+
+pub const BUS_NAME: &str = "org.freedesktop.secrets";
+
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const COLLECTION_PREFIX: &str = "/org/freedesktop/secrets/collection/";
+const ALIAS_PREFIX: &str = "/org/freedesktop/secrets/aliases/";
+const SESSION_PREFIX: &str = "/org/freedesktop/secrets/session/";
+const PROMPT_PREFIX: &str = "/org/freedesktop/secrets/prompt/";
+
+const ITEM_LABEL: &str = "org.freedesktop.Secret.Item.Label";
+const ITEM_ATTRIBUTES: &str = "org.freedesktop.Secret.Item.Attributes";
+const COLLECTION_LABEL: &str = "org.freedesktop.Secret.Collection.Label";
+
+#[derive(zbus::DBusError, Debug)]
+#[zbus(prefix = "org.freedesktop.Secret.Error")]
+pub enum Error {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    IsLocked(String),
+    NoSession(String),
+    NoSuchObject(String),
+}
+
+impl From<neng_pass::Error> for Error {
+    fn from(p_error: neng_pass::Error) -> Error {
+        Error::ZBus(zbus::Error::Failure(p_error.get_message()))
+    }
+}
+
+impl From<zbus::fdo::Error> for Error {
+    fn from(p_error: zbus::fdo::Error) -> Error {
+        Error::ZBus(zbus::Error::FDO(Box::new(p_error)))
+    }
+}
+
+impl From<zbus::zvariant::Error> for Error {
+    fn from(p_error: zbus::zvariant::Error) -> Error {
+        Error::ZBus(zbus::Error::Variant(p_error))
+    }
+}
+
+/// Properties can only fail with the standard errors.
+fn property_error(p_error: neng_pass::Error) -> zbus::fdo::Error {
+    zbus::fdo::Error::Failed(p_error.get_message())
+}
+
+/// A secret as it goes over the bus, encrypted for a session.
+#[derive(Debug, serde::Serialize, serde::Deserialize, zbus::zvariant::Type)]
+pub struct Secret {
+    session: OwnedObjectPath,
+    parameters: Vec<u8>,
+    value: Vec<u8>,
+    content_type: String,
+}
+
+/// A session, with the unique name of the client that opened it, so that it
+/// can be closed when the client goes away without closing it.
+struct OpenSession {
+    client: String,
+    session: session::Session,
+}
+
+struct State {
+    sql_connection: rusqlite::Connection,
+    sessions: HashMap<String, OpenSession>,
+    next_id: u64,
+}
+
+/// What every object of the service shares.
+struct Provider {
+    state: Mutex<State>,
+    master_key: Box<dyn Fn() -> Option<String> + Send + Sync>,
+    unlock: Box<dyn Fn() -> bool + Send + Sync>,
+    lock: Box<dyn Fn() + Send + Sync>,
+}
+
+impl Provider {
+    fn vault<T>(
+        &self,
+        p_action: impl FnOnce(&rusqlite::Connection) -> Result<T, neng_pass::Error>,
+    ) -> Result<T, neng_pass::Error> {
+        let state = self.state.lock().unwrap();
+        p_action(&state.sql_connection)
+    }
+
+    fn master_key(&self) -> Result<String, Error> {
+        (self.master_key)().ok_or_else(|| Error::IsLocked("The vault is locked.".to_string()))
+    }
+
+    fn is_locked(&self) -> bool {
+        (self.master_key)().is_none()
+    }
+
+    fn next_id(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        state.next_id
+    }
+
+    /// Encrypts the secret of an item for a session.
+    fn secret_for(
+        &self,
+        p_session: &ObjectPath<'_>,
+        p_id: i64,
+        p_master_key: &str,
+    ) -> Result<Secret, Error> {
+        let state = self.state.lock().unwrap();
+        let session = state
+            .sessions
+            .get(p_session.as_str())
+            .ok_or_else(|| Error::NoSession(format!("There is no session at {}.", p_session)))?;
+
+        let secret = store::secret(&state.sql_connection, p_master_key, p_id)?;
+        let (parameters, value) = session.session.encrypt(&secret.value);
+
+        Ok(Secret {
+            session: p_session.to_owned().into(),
+            parameters,
+            value,
+            content_type: secret.content_type,
+        })
+    }
+
+    /// Decrypts a secret that a client sent in one of its sessions.
+    fn secret_from(&self, p_secret: &Secret) -> Result<store::Secret, Error> {
+        let state = self.state.lock().unwrap();
+        let session = state
+            .sessions
+            .get(p_secret.session.as_str())
+            .ok_or_else(|| {
+                Error::NoSession(format!("There is no session at {}.", p_secret.session))
+            })?;
+
+        let value = session
+            .session
+            .decrypt(&p_secret.parameters, &p_secret.value)
+            .ok_or_else(|| zbus::Error::Failure("The secret couldn't be decrypted.".to_string()))?;
+
+        Ok(store::Secret {
+            value,
+            content_type: p_secret.content_type.clone(),
+        })
+    }
+
+    /// The collection at a path, which can also be the path of an alias.
+    fn collection_at(&self, p_path: &ObjectPath<'_>) -> Option<String> {
+        if let Some(name) = p_path.as_str().strip_prefix(COLLECTION_PREFIX) {
+            let exists = self
+                .vault(|connection| store::collection(connection, name))
+                .ok()??;
+            return Some(exists.name);
+        }
+
+        let alias = p_path.as_str().strip_prefix(ALIAS_PREFIX)?;
+        self.vault(|connection| Ok(store::read_alias(connection, alias)))
+            .ok()?
+    }
+}
+
+fn object_path(p_path: String) -> OwnedObjectPath {
+    ObjectPath::try_from(p_path)
+        .expect("object paths are only made from sanitized names and numbers")
+        .into()
+}
+
+fn no_object() -> OwnedObjectPath {
+    ObjectPath::from_static_str_unchecked("/").into()
+}
+
+fn collection_path(p_name: &str) -> OwnedObjectPath {
+    object_path(format!("{}{}", COLLECTION_PREFIX, p_name))
+}
+
+fn alias_path(p_alias: &str) -> OwnedObjectPath {
+    object_path(format!("{}{}", ALIAS_PREFIX, p_alias))
+}
+
+fn item_path(p_collection: &str, p_id: i64) -> OwnedObjectPath {
+    object_path(format!("{}{}/{}", COLLECTION_PREFIX, p_collection, p_id))
+}
+
+/// The id of the item at a path.
+fn item_id(p_path: &ObjectPath<'_>) -> Option<i64> {
+    let rest = p_path.as_str().strip_prefix(COLLECTION_PREFIX)?;
+    let (_, id) = rest.split_once('/')?;
+    id.parse().ok()
+}
+
+/// Aliases can only be made of what an object path allows.
+fn is_valid_alias(p_alias: &str) -> bool {
+    !p_alias.is_empty()
+        && p_alias
+            .chars()
+            .all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+fn item_label(
+    p_properties: &HashMap<String, OwnedValue>,
+    p_property: &str,
+) -> Result<String, Error> {
+    match p_properties.get(p_property) {
+        Some(label) => Ok(<&str>::try_from(&**label)?.to_string()),
+        None => Ok(String::new()),
+    }
+}
+
+struct Service {
+    provider: Arc<Provider>,
+}
+
+#[zbus::interface(name = "org.freedesktop.Secret.Service")]
+impl Service {
+    async fn open_session(
+        &self,
+        algorithm: &str,
+        input: OwnedValue,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> Result<(OwnedValue, OwnedObjectPath), Error> {
+        let input = match algorithm {
+            session::PLAIN => Vec::new(),
+            session::DH_AES => Vec::<u8>::try_from(input)?,
+            _ => {
+                return Err(zbus::fdo::Error::NotSupported(format!(
+                    "The '{}' algorithm isn't supported.",
+                    algorithm
+                ))
+                .into())
+            }
+        };
+
+        let (session, output) = session::negotiate(algorithm, &input).ok_or_else(|| {
+            zbus::fdo::Error::InvalidArgs("The public key isn't valid.".to_string())
+        })?;
+
+        let path = object_path(format!("{}{}", SESSION_PREFIX, self.provider.next_id()));
+        self.provider
+            .state
+            .lock()
+            .unwrap()
+            .sessions
+            .insert(
+                path.to_string(),
+                OpenSession {
+                    client: header.sender().map(|sender| sender.to_string()).unwrap_or_default(),
+                    session,
+                },
+            );
+
+        server
+            .at(
+                &path,
+                Session {
+                    provider: Arc::clone(&self.provider),
+                },
+            )
+            .await?;
+
+        let output = match algorithm {
+            session::PLAIN => Value::from(""),
+            _ => Value::from(output),
+        };
+
+        Ok((output.try_into()?, path))
+    }
+
+    async fn create_collection(
+        &self,
+        properties: HashMap<String, OwnedValue>,
+        alias: &str,
+        #[zbus(object_server)] server: &ObjectServer,
+        #[zbus(signal_context)] context: SignalContext<'_>,
+    ) -> Result<(OwnedObjectPath, OwnedObjectPath), Error> {
+        if !alias.is_empty() {
+            if let Some(existing) = self
+                .provider
+                .vault(|connection| Ok(store::read_alias(connection, alias)))?
+            {
+                return Ok((collection_path(&existing), no_object()));
+            }
+        }
+
+        let label = item_label(&properties, COLLECTION_LABEL)?;
+        let name_source = if alias.is_empty() { &label } else { alias };
+        let name = self.provider.vault(|connection| {
+            let name = store::create_collection(connection, name_source, &label)?;
+            if is_valid_alias(alias) {
+                store::set_alias(connection, alias, Some(&name))?;
+            }
+            Ok(name)
+        })?;
+
+        let path = collection_path(&name);
+        server
+            .at(&path, Collection::new(&self.provider, &name))
+            .await?;
+        if is_valid_alias(alias) {
+            server
+                .at(alias_path(alias), Collection::new(&self.provider, &name))
+                .await?;
+        }
+        Service::collection_created(&context, path.as_ref()).await?;
+
+        Ok((path, no_object()))
+    }
+
+    fn search_items(
+        &self,
+        attributes: HashMap<String, String>,
+    ) -> Result<(Vec<OwnedObjectPath>, Vec<OwnedObjectPath>), Error> {
+        let paths = self
+            .provider
+            .vault(|connection| store::search(connection, None, &attributes))?
+            .iter()
+            .map(|item| item_path(&item.collection, item.id))
+            .collect();
+
+        if self.provider.is_locked() {
+            Ok((Vec::new(), paths))
+        } else {
+            Ok((paths, Vec::new()))
+        }
+    }
+
+    async fn unlock(
+        &self,
+        objects: Vec<OwnedObjectPath>,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> Result<(Vec<OwnedObjectPath>, OwnedObjectPath), Error> {
+        if !self.provider.is_locked() {
+            return Ok((objects, no_object()));
+        }
+
+        let path = object_path(format!("{}{}", PROMPT_PREFIX, self.provider.next_id()));
+        server
+            .at(
+                &path,
+                Prompt {
+                    provider: Arc::clone(&self.provider),
+                    objects,
+                },
+            )
+            .await?;
+
+        Ok((Vec::new(), path))
+    }
+
+    fn lock(&self, objects: Vec<OwnedObjectPath>) -> (Vec<OwnedObjectPath>, OwnedObjectPath) {
+        (self.provider.lock)();
+        (objects, no_object())
+    }
+
+    fn get_secrets(
+        &self,
+        items: Vec<OwnedObjectPath>,
+        session: ObjectPath<'_>,
+    ) -> Result<HashMap<OwnedObjectPath, Secret>, Error> {
+        let master_key = self.provider.master_key()?;
+        let mut secrets = HashMap::new();
+
+        for path in items {
+            if let Some(id) = item_id(&path) {
+                let secret = self.provider.secret_for(&session, id, &master_key)?;
+                secrets.insert(path, secret);
+            }
+        }
+
+        Ok(secrets)
+    }
+
+    fn read_alias(&self, name: &str) -> Result<OwnedObjectPath, Error> {
+        let collection = self
+            .provider
+            .vault(|connection| Ok(store::read_alias(connection, name)))?;
+        Ok(collection.map_or_else(no_object, |collection| collection_path(&collection)))
+    }
+
+    async fn set_alias(
+        &self,
+        name: &str,
+        collection: ObjectPath<'_>,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> Result<(), Error> {
+        if !is_valid_alias(name) {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "'{}' can't be used as an alias.",
+                name
+            ))
+            .into());
+        }
+
+        let target = match collection.as_str() {
+            "/" => None,
+            _ => Some(self.provider.collection_at(&collection).ok_or_else(|| {
+                Error::NoSuchObject(format!("There is no collection at {}.", collection))
+            })?),
+        };
+
+        self.provider
+            .vault(|connection| store::set_alias(connection, name, target.as_deref()))?;
+
+        // The alias may not have pointed anywhere before.
+        let _ = server.remove::<Collection, _>(alias_path(name)).await;
+        if let Some(target) = target {
+            server
+                .at(alias_path(name), Collection::new(&self.provider, &target))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn collections(&self) -> zbus::fdo::Result<Vec<OwnedObjectPath>> {
+        Ok(self
+            .provider
+            .vault(store::collections)
+            .map_err(property_error)?
+            .iter()
+            .map(|collection| collection_path(&collection.name))
+            .collect())
+    }
+
+    #[zbus(signal)]
+    async fn collection_created(
+        context: &SignalContext<'_>,
+        collection: ObjectPath<'_>,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn collection_deleted(
+        context: &SignalContext<'_>,
+        collection: ObjectPath<'_>,
+    ) -> zbus::Result<()>;
+}
+
+struct Collection {
+    provider: Arc<Provider>,
+    name: String,
+}
+
+impl Collection {
+    fn new(p_provider: &Arc<Provider>, p_name: &str) -> Collection {
+        Collection {
+            provider: Arc::clone(p_provider),
+            name: p_name.to_string(),
+        }
+    }
+
+    fn info(&self) -> zbus::fdo::Result<store::CollectionInfo> {
+        self.provider
+            .vault(|connection| store::collection(connection, &self.name))
+            .map_err(property_error)?
+            .ok_or_else(|| {
+                zbus::fdo::Error::UnknownObject(format!(
+                    "The {} collection was deleted.",
+                    self.name
+                ))
+            })
+    }
+}
+
+#[zbus::interface(name = "org.freedesktop.Secret.Collection")]
+impl Collection {
+    async fn delete(
+        &self,
+        #[zbus(object_server)] server: &ObjectServer,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> Result<OwnedObjectPath, Error> {
+        let (items, aliases) = self.provider.vault(|connection| {
+            let items = store::items(connection, Some(&self.name))?;
+            let aliases = store::aliases(connection)?;
+            store::delete_collection(connection, &self.name)?;
+            Ok((items, aliases))
+        })?;
+
+        for item in items {
+            server
+                .remove::<Item, _>(item_path(&self.name, item.id))
+                .await?;
+        }
+        for (alias, _) in aliases
+            .iter()
+            .filter(|(_, collection)| *collection == self.name)
+        {
+            server.remove::<Collection, _>(alias_path(alias)).await?;
+        }
+
+        let path = collection_path(&self.name);
+        server.remove::<Collection, _>(&path).await?;
+        Service::collection_deleted(
+            &SignalContext::new(connection, SERVICE_PATH)?,
+            path.as_ref(),
+        )
+        .await?;
+
+        Ok(no_object())
+    }
+
+    fn search_items(
+        &self,
+        attributes: HashMap<String, String>,
+    ) -> Result<Vec<OwnedObjectPath>, Error> {
+        Ok(self
+            .provider
+            .vault(|connection| store::search(connection, Some(&self.name), &attributes))?
+            .iter()
+            .map(|item| item_path(&self.name, item.id))
+            .collect())
+    }
+
+    async fn create_item(
+        &self,
+        properties: HashMap<String, OwnedValue>,
+        secret: Secret,
+        replace: bool,
+        #[zbus(object_server)] server: &ObjectServer,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> Result<(OwnedObjectPath, OwnedObjectPath), Error> {
+        let master_key = self.provider.master_key()?;
+        let label = item_label(&properties, ITEM_LABEL)?;
+        let attributes = match properties.get(ITEM_ATTRIBUTES) {
+            Some(attributes) => HashMap::<String, String>::try_from(attributes.try_clone()?)?,
+            None => HashMap::new(),
+        };
+        let secret = self.provider.secret_from(&secret)?;
+
+        let id = self.provider.vault(|connection| {
+            store::create_item(
+                connection,
+                &master_key,
+                &self.name,
+                &label,
+                &attributes,
+                &secret,
+                replace,
+            )
+        })?;
+
+        let path = item_path(&self.name, id);
+        let created = server
+            .at(
+                &path,
+                Item {
+                    provider: Arc::clone(&self.provider),
+                    collection: self.name.clone(),
+                    id,
+                },
+            )
+            .await?;
+
+        let context = SignalContext::new(connection, collection_path(&self.name))?;
+        if created {
+            Collection::item_created(&context, path.as_ref()).await?;
+        } else {
+            Collection::item_changed(&context, path.as_ref()).await?;
+        }
+
+        Ok((path, no_object()))
+    }
+
+    #[zbus(property)]
+    fn items(&self) -> zbus::fdo::Result<Vec<OwnedObjectPath>> {
+        Ok(self
+            .provider
+            .vault(|connection| store::items(connection, Some(&self.name)))
+            .map_err(property_error)?
+            .iter()
+            .map(|item| item_path(&self.name, item.id))
+            .collect())
+    }
+
+    #[zbus(property)]
+    fn label(&self) -> zbus::fdo::Result<String> {
+        Ok(self.info()?.label)
+    }
+
+    #[zbus(property)]
+    fn set_label(&mut self, label: String) -> zbus::fdo::Result<()> {
+        self.provider
+            .vault(|connection| store::set_collection_label(connection, &self.name, &label))
+            .map_err(property_error)
+    }
+
+    #[zbus(property)]
+    fn locked(&self) -> bool {
+        self.provider.is_locked()
+    }
+
+    #[zbus(property)]
+    fn created(&self) -> zbus::fdo::Result<u64> {
+        Ok(self.info()?.created)
+    }
+
+    #[zbus(property)]
+    fn modified(&self) -> zbus::fdo::Result<u64> {
+        Ok(self.info()?.modified)
+    }
+
+    #[zbus(signal)]
+    async fn item_created(context: &SignalContext<'_>, item: ObjectPath<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn item_deleted(context: &SignalContext<'_>, item: ObjectPath<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn item_changed(context: &SignalContext<'_>, item: ObjectPath<'_>) -> zbus::Result<()>;
+}
+
+struct Item {
+    provider: Arc<Provider>,
+    collection: String,
+    id: i64,
+}
+
+impl Item {
+    fn info(&self) -> zbus::fdo::Result<store::Item> {
+        self.provider
+            .vault(|connection| store::item(connection, self.id))
+            .map_err(property_error)?
+            .ok_or_else(|| {
+                zbus::fdo::Error::UnknownObject(format!("The item {} was deleted.", self.id))
+            })
+    }
+}
+
+#[zbus::interface(name = "org.freedesktop.Secret.Item")]
+impl Item {
+    async fn delete(
+        &self,
+        #[zbus(object_server)] server: &ObjectServer,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> Result<OwnedObjectPath, Error> {
+        self.provider
+            .vault(|connection| store::delete_item(connection, self.id))?;
+
+        let path = item_path(&self.collection, self.id);
+        server.remove::<Item, _>(&path).await?;
+
+        let context = SignalContext::new(connection, collection_path(&self.collection))?;
+        Collection::item_deleted(&context, path.as_ref()).await?;
+
+        Ok(no_object())
+    }
+
+    fn get_secret(&self, session: ObjectPath<'_>) -> Result<Secret, Error> {
+        let master_key = self.provider.master_key()?;
+        self.provider.secret_for(&session, self.id, &master_key)
+    }
+
+    fn set_secret(&self, secret: Secret) -> Result<(), Error> {
+        let master_key = self.provider.master_key()?;
+        let secret = self.provider.secret_from(&secret)?;
+        self.provider
+            .vault(|connection| store::set_secret(connection, &master_key, self.id, &secret))?;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn locked(&self) -> bool {
+        self.provider.is_locked()
+    }
+
+    #[zbus(property)]
+    fn attributes(&self) -> zbus::fdo::Result<HashMap<String, String>> {
+        Ok(self.info()?.attributes)
+    }
+
+    #[zbus(property)]
+    fn set_attributes(&mut self, attributes: HashMap<String, String>) -> zbus::fdo::Result<()> {
+        self.provider
+            .vault(|connection| store::set_attributes(connection, self.id, &attributes))
+            .map_err(property_error)
+    }
+
+    #[zbus(property)]
+    fn label(&self) -> zbus::fdo::Result<String> {
+        Ok(self.info()?.label)
+    }
+
+    #[zbus(property)]
+    fn set_label(&mut self, label: String) -> zbus::fdo::Result<()> {
+        self.provider
+            .vault(|connection| store::set_label(connection, self.id, &label))
+            .map_err(property_error)
+    }
+
+    #[zbus(property)]
+    fn created(&self) -> zbus::fdo::Result<u64> {
+        Ok(self.info()?.created)
+    }
+
+    #[zbus(property)]
+    fn modified(&self) -> zbus::fdo::Result<u64> {
+        Ok(self.info()?.modified)
+    }
+}
+
+struct Session {
+    provider: Arc<Provider>,
+}
+
+#[zbus::interface(name = "org.freedesktop.Secret.Session")]
+impl Session {
+    async fn close(
+        &self,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> Result<(), Error> {
+        if let Some(path) = header.path() {
+            self.provider
+                .state
+                .lock()
+                .unwrap()
+                .sessions
+                .remove(path.as_str());
+            server.remove::<Session, _>(path).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Asks for the master key, to unlock the vault for the objects that a
+/// client wanted unlocked.
+struct Prompt {
+    provider: Arc<Provider>,
+    objects: Vec<OwnedObjectPath>,
+}
+
+#[zbus::interface(name = "org.freedesktop.Secret.Prompt")]
+impl Prompt {
+    fn prompt(&self, _window_id: &str, #[zbus(signal_context)] context: SignalContext<'_>) {
+        let provider = Arc::clone(&self.provider);
+        let objects = self.objects.clone();
+        let context = context.to_owned();
+
+        // Asking for the master key takes as long as the user does, and the
+        // result is only announced through the `Completed` signal.
+        std::thread::spawn(move || {
+            let unlocked = (provider.unlock)();
+            let objects = if unlocked { objects } else { Vec::new() };
+            zbus::block_on(finish_prompt(&context, !unlocked, objects));
+        });
+    }
+
+    async fn dismiss(&self, #[zbus(signal_context)] context: SignalContext<'_>) {
+        finish_prompt(&context, true, Vec::new()).await;
+    }
+
+    #[zbus(signal)]
+    async fn completed(
+        context: &SignalContext<'_>,
+        dismissed: bool,
+        result: Value<'_>,
+    ) -> zbus::Result<()>;
+}
+
+async fn finish_prompt(
+    p_context: &SignalContext<'_>,
+    p_dismissed: bool,
+    p_objects: Vec<OwnedObjectPath>,
+) {
+    let _ = Prompt::completed(p_context, p_dismissed, Value::from(p_objects)).await;
+    let _ = p_context
+        .connection()
+        .object_server()
+        .remove::<Prompt, _>(p_context.path())
+        .await;
+}
+
+/// Closes the sessions of the clients that leave the bus. `p_subscribed` is
+/// told once the clients are watched, or why they can't be.
+async fn close_abandoned_sessions(
+    p_connection: zbus::Connection,
+    p_provider: Arc<Provider>,
+    p_subscribed: std::sync::mpsc::Sender<zbus::Result<()>>,
+) {
+    let changes = match zbus::fdo::DBusProxy::new(&p_connection).await {
+        Ok(proxy) => proxy.receive_name_owner_changed().await,
+        Err(err) => Err(err),
+    };
+    let mut changes = match changes {
+        Ok(changes) => {
+            let _ = p_subscribed.send(Ok(()));
+            changes
+        }
+        Err(err) => {
+            let _ = p_subscribed.send(Err(err));
+            return;
+        }
+    };
+
+    while let Some(change) = changes.next().await {
+        let args = match change.args() {
+            Ok(args) if args.new_owner().is_none() => args,
+            _ => continue,
+        };
+
+        let client = args.name().to_string();
+        let paths: Vec<String> = {
+            let mut state = p_provider.state.lock().unwrap();
+            let paths = state
+                .sessions
+                .iter()
+                .filter(|(_, session)| session.client == client)
+                .map(|(path, _)| path.clone())
+                .collect();
+            state.sessions.retain(|_, session| session.client != client);
+            paths
+        };
+
+        for path in paths {
+            let _ = p_connection
+                .object_server()
+                .remove::<Session, _>(path.as_str())
+                .await;
+        }
+    }
+}
+
+/// The Secret Service, provided until this is dropped.
+pub struct Server {
+    // Dropped first, since it holds on to the connection too.
+    _watcher: zbus::Task<()>,
+    _connection: zbus::blocking::Connection,
+}
+
+/// Serves the Secret Service on `p_connection`, usually the session bus,
+/// until the returned server is dropped.
+///
+/// `p_master_key` gives the master key while the vault is unlocked, `p_unlock`
+/// asks the user for it and returns whether the vault got unlocked, and
+/// `p_lock` forgets it.
+pub fn serve(
+    p_connection: zbus::blocking::Connection,
+    p_sql_connection: rusqlite::Connection,
+    p_master_key: impl Fn() -> Option<String> + Send + Sync + 'static,
+    p_unlock: impl Fn() -> bool + Send + Sync + 'static,
+    p_lock: impl Fn() + Send + Sync + 'static,
+) -> Result<Server, Error> {
+    neng_pass::prepare_database(&p_sql_connection)?;
+    store::prepare(&p_sql_connection)?;
+
+    let collections = store::collections(&p_sql_connection)?;
+    let items = store::items(&p_sql_connection, None)?;
+    let aliases = store::aliases(&p_sql_connection)?;
+
+    let provider = Arc::new(Provider {
+        state: Mutex::new(State {
+            sql_connection: p_sql_connection,
+            sessions: HashMap::new(),
+            next_id: 0,
+        }),
+        master_key: Box::new(p_master_key),
+        unlock: Box::new(p_unlock),
+        lock: Box::new(p_lock),
+    });
+
+    {
+        let server = p_connection.object_server();
+
+        server.at(
+            SERVICE_PATH,
+            Service {
+                provider: Arc::clone(&provider),
+            },
+        )?;
+        for collection in &collections {
+            server.at(
+                collection_path(&collection.name),
+                Collection::new(&provider, &collection.name),
+            )?;
+        }
+        for (alias, collection) in &aliases {
+            server.at(alias_path(alias), Collection::new(&provider, collection))?;
+        }
+        for item in items {
+            server.at(
+                item_path(&item.collection, item.id),
+                Item {
+                    provider: Arc::clone(&provider),
+                    collection: item.collection,
+                    id: item.id,
+                },
+            )?;
+        }
+    }
+
+    let (subscribed, is_subscribed) = std::sync::mpsc::channel();
+    let watcher = p_connection.inner().executor().spawn(
+        close_abandoned_sessions(p_connection.inner().clone(), provider, subscribed),
+        "close abandoned sessions",
+    );
+    is_subscribed
+        .recv()
+        .map_err(|_| zbus::Error::Failure("The bus can't be watched.".to_string()))??;
+
+    // The name is only taken once every object is there to be found.
+    p_connection.request_name(BUS_NAME)?;
+
+    Ok(Server {
+        _watcher: watcher,
+        _connection: p_connection,
+    })
+}
@@ -0,0 +1,142 @@
+use clap::{arg, Command};
+
+fn cli() -> Command {
+    Command::new("neng-pass-secret-service")
+        .about("Provides the freedesktop.org Secret Service on the session bus, with the secrets kept in the vault.")
+        .arg(
+            arg!(--vault <VAULT> "The vault to keep the secrets in: the name of one in the configuration file of neng-pass, or the path of a vault directory. The default one, or the one in NENG_PASS_VAULT, by default.")
+                .required(false),
+        )
+        .arg(
+            arg!(-d --database <FILE> "The database to keep the secrets in, the passwords.db of the vault by default.")
+                .required(false),
+        )
+        .arg(
+            arg!(--"neng-pass" <PROGRAM> "The neng-pass program, used to unlock the vault.")
+                .default_value("neng-pass"),
+        )
+}
+
+/// Unlocks the vault the way `neng-pass unlock` does, with the master key
+/// asked for by the program in NENG_PASS_ASKPASS or SSH_ASKPASS, since there
+/// is no terminal to ask in.
+fn unlock(p_neng_pass: &str, p_data_dir: &std::path::Path) -> bool {
+    let askpass = std::env::var("NENG_PASS_ASKPASS")
+        .or_else(|_| std::env::var("SSH_ASKPASS"))
+        .unwrap_or_else(|_| "ssh-askpass".to_string());
+
+    std::process::Command::new(p_neng_pass)
+        .arg("--vault")
+        .arg(p_data_dir)
+        .arg("unlock")
+        .env(
+            "NENG_PASS_MASTER_KEY_COMMAND",
+            format!(
+                "{} 'An application wants to use the neng-pass vault. Enter the master key:'",
+                askpass
+            ),
+        )
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(unix)]
+fn main() {
+    use neng_pass::{config::Config, vault::Location};
+    use std::path::PathBuf;
+
+    let cli_matches = cli().get_matches();
+    let vault = cli_matches
+        .get_one::<String>("vault")
+        .cloned()
+        .or_else(|| std::env::var("NENG_PASS_VAULT").ok());
+
+    let location = Config::load(&neng_pass::config::config_file())
+        .and_then(|config| config.resolve(vault.as_deref()));
+    let data_dir = match location {
+        Ok(Location::Directory(data_dir)) => data_dir,
+        Ok(Location::File(path)) => {
+            eprintln!(
+                "[ERROR]: {} is a single-file vault, which only works with vault directories, since the master key is taken from the agent.",
+                path.display()
+            );
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("[ERROR]: {}", err.get_message());
+            std::process::exit(1);
+        }
+    };
+    let data_dir = data_dir.as_path();
+
+    let database_file = cli_matches
+        .get_one::<String>("database")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| data_dir.join("passwords.db"));
+    let neng_pass = cli_matches.get_one::<String>("neng-pass").unwrap().clone();
+    let agent_socket = data_dir.join("agent.sock");
+
+    if let Err(err) = std::fs::create_dir_all(data_dir) {
+        eprintln!("[ERROR]: Failed to create {}: {}", data_dir.display(), err);
+        std::process::exit(1);
+    }
+
+    let sql_connection = match rusqlite::Connection::open(&database_file) {
+        Ok(connection) => connection,
+        Err(err) => {
+            eprintln!(
+                "[ERROR]: Failed to open {}: {}",
+                database_file.display(),
+                err
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let lock_socket = agent_socket.clone();
+    let unlock_data_dir = data_dir.to_path_buf();
+    let result = zbus::blocking::Connection::session()
+        .map_err(neng_pass_secret_service::Error::from)
+        .and_then(|connection| {
+            neng_pass_secret_service::serve(
+                connection,
+                sql_connection,
+                move || neng_pass::agent::get_master_key(&agent_socket),
+                move || unlock(&neng_pass, &unlock_data_dir),
+                move || {
+                    let _ = neng_pass::agent::lock(&lock_socket);
+                },
+            )
+        });
+
+    let _server = match result {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!(
+                "[ERROR]: Failed to provide {}: {}",
+                neng_pass_secret_service::BUS_NAME,
+                err
+            );
+            std::process::exit(1);
+        }
+    };
+
+    eprintln!(
+        "[INFO]: Providing {} for {}",
+        neng_pass_secret_service::BUS_NAME,
+        database_file.display()
+    );
+    loop {
+        std::thread::park();
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    let _ = (cli, unlock);
+    eprintln!("[ERROR]: The Secret Service is only provided on Unix-like systems.");
+    std::process::exit(1);
+}
@@ -0,0 +1,90 @@
+//! The sessions that secrets are transferred in. Clients either ask for
+//! `plain`, where secrets go over the bus as they are, or for
+//! `dh-ietf1024-sha256-aes128-cbc-pkcs7`, where a key is agreed on with
+//! Diffie-Hellman and every secret is encrypted with AES.
+
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use num_bigint::{BigUint, RandBigInt};
+use rand::RngCore;
+
+pub const PLAIN: &str = "plain";
+pub const DH_AES: &str = "dh-ietf1024-sha256-aes128-cbc-pkcs7";
+
+/// The 1024-bit MODP group of RFC 2409, whose generator is 2.
+const PRIME: &str = "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7EDEE386BFB5A899FA5AE9F24117C4B1FE649286651ECE65381FFFFFFFFFFFFFFFF";
+const PRIME_LEN: usize = 128;
+
+type Encryptor = cbc::Encryptor<aes::Aes128>;
+type Decryptor = cbc::Decryptor<aes::Aes128>;
+
+pub enum Session {
+    Plain,
+    Aes([u8; 16]),
+}
+
+/// Starts a session with the given algorithm. Returns the session and what
+/// to send back to the client, or `None` if the algorithm isn't supported or
+/// the client's input doesn't make sense.
+pub fn negotiate(p_algorithm: &str, p_input: &[u8]) -> Option<(Session, Vec<u8>)> {
+    match p_algorithm {
+        PLAIN => Some((Session::Plain, Vec::new())),
+        DH_AES => {
+            let prime = BigUint::parse_bytes(PRIME.as_bytes(), 16).expect("the prime is valid hex");
+            let client_public = BigUint::from_bytes_be(p_input);
+            if client_public <= BigUint::from(1u8) || client_public >= &prime - 1u8 {
+                return None;
+            }
+
+            let private =
+                rand::thread_rng().gen_biguint_range(&BigUint::from(2u8), &(&prime - 1u8));
+            let public = BigUint::from(2u8).modpow(&private, &prime);
+            let shared = client_public.modpow(&private, &prime);
+
+            let mut key = [0u8; 16];
+            hkdf::Hkdf::<sha2::Sha256>::new(None, &left_pad(&shared.to_bytes_be()))
+                .expand(&[], &mut key)
+                .expect("16 bytes is a valid length for HKDF");
+
+            Some((Session::Aes(key), left_pad(&public.to_bytes_be())))
+        }
+        _ => None,
+    }
+}
+
+/// Numbers are exchanged as big-endian bytes as long as the prime.
+fn left_pad(p_bytes: &[u8]) -> Vec<u8> {
+    let mut padded = vec![0u8; PRIME_LEN.saturating_sub(p_bytes.len())];
+    padded.extend_from_slice(p_bytes);
+    padded
+}
+
+impl Session {
+    /// Encrypts a secret for the client. Returns the parameters and the
+    /// encrypted value.
+    pub fn encrypt(&self, p_value: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        match self {
+            Session::Plain => (Vec::new(), p_value.to_vec()),
+            Session::Aes(key) => {
+                let mut iv = [0u8; 16];
+                rand::thread_rng().fill_bytes(&mut iv);
+                let value =
+                    Encryptor::new(key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(p_value);
+                (iv.to_vec(), value)
+            }
+        }
+    }
+
+    /// Decrypts a secret from the client, or returns `None` if it wasn't
+    /// encrypted with this session's key.
+    pub fn decrypt(&self, p_parameters: &[u8], p_value: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Session::Plain => Some(p_value.to_vec()),
+            Session::Aes(key) => {
+                let iv: [u8; 16] = p_parameters.try_into().ok()?;
+                Decryptor::new(key.into(), &iv.into())
+                    .decrypt_padded_vec_mut::<Pkcs7>(p_value)
+                    .ok()
+            }
+        }
+    }
+}
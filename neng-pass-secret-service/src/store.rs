@@ -0,0 +1,368 @@
+//! Where the collections and items of the Secret Service are kept: tables in
+//! the vault database, with every secret sealed with the master key.
+//!
+//! Labels and attributes are stored as they are, since clients search items
+//! by attributes without unlocking anything first.
+
+use std::collections::HashMap;
+
+use neng_pass::{crypto, pad_master_key, Error};
+
+/// The collection that the `default` alias points to until it is changed.
+pub const DEFAULT_COLLECTION: &str = "login";
+
+pub struct Item {
+    pub id: i64,
+    pub collection: String,
+    pub label: String,
+    pub attributes: HashMap<String, String>,
+    pub created: u64,
+    pub modified: u64,
+}
+
+pub struct CollectionInfo {
+    pub name: String,
+    pub label: String,
+    pub created: u64,
+    pub modified: u64,
+}
+
+pub fn now() -> u64 {
+    (neng_pass::current_timestamp() / 1000) as u64
+}
+
+pub fn prepare(p_sql_connection: &rusqlite::Connection) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "CREATE TABLE IF NOT EXISTS secret_collections (name TEXT PRIMARY KEY, label TEXT NOT NULL, created INTEGER NOT NULL, modified INTEGER NOT NULL);",
+        (),
+    )?;
+    p_sql_connection.execute(
+        "CREATE TABLE IF NOT EXISTS secret_aliases (alias TEXT PRIMARY KEY, collection TEXT NOT NULL);",
+        (),
+    )?;
+    p_sql_connection.execute(
+        "CREATE TABLE IF NOT EXISTS secret_items (id INTEGER PRIMARY KEY AUTOINCREMENT, collection TEXT NOT NULL, label TEXT NOT NULL, attributes TEXT NOT NULL, secret BLOB NOT NULL, created INTEGER NOT NULL, modified INTEGER NOT NULL);",
+        (),
+    )?;
+
+    if collection(p_sql_connection, DEFAULT_COLLECTION)?.is_none() {
+        create_collection(p_sql_connection, DEFAULT_COLLECTION, "Login")?;
+    }
+
+    p_sql_connection.execute(
+        "INSERT OR IGNORE INTO secret_aliases (alias, collection) VALUES ('default', ?);",
+        [DEFAULT_COLLECTION],
+    )?;
+
+    Ok(())
+}
+
+pub fn collections(p_sql_connection: &rusqlite::Connection) -> Result<Vec<CollectionInfo>, Error> {
+    let mut sql_statement = p_sql_connection
+        .prepare("SELECT name, label, created, modified FROM secret_collections ORDER BY name;")?;
+    let collections = sql_statement
+        .query_map([], |row| {
+            Ok(CollectionInfo {
+                name: row.get(0)?,
+                label: row.get(1)?,
+                created: row.get(2)?,
+                modified: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(collections)
+}
+
+pub fn collection(
+    p_sql_connection: &rusqlite::Connection,
+    p_name: &str,
+) -> Result<Option<CollectionInfo>, Error> {
+    Ok(collections(p_sql_connection)?
+        .into_iter()
+        .find(|collection| collection.name == p_name))
+}
+
+/// Creates a collection. Its name is derived from the label, as it has to be
+/// usable in an object path.
+pub fn create_collection(
+    p_sql_connection: &rusqlite::Connection,
+    p_name: &str,
+    p_label: &str,
+) -> Result<String, Error> {
+    let base = p_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let base = if base.is_empty() {
+        "collection".to_string()
+    } else {
+        base
+    };
+
+    let existing = collections(p_sql_connection)?;
+    let mut name = base.clone();
+    let mut counter = 2;
+    while existing.iter().any(|collection| collection.name == name) {
+        name = format!("{}_{}", base, counter);
+        counter += 1;
+    }
+
+    let now = now();
+    p_sql_connection.execute(
+        "INSERT INTO secret_collections (name, label, created, modified) VALUES (?, ?, ?, ?);",
+        (&name, p_label, now, now),
+    )?;
+
+    Ok(name)
+}
+
+pub fn set_collection_label(
+    p_sql_connection: &rusqlite::Connection,
+    p_name: &str,
+    p_label: &str,
+) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "UPDATE secret_collections SET label = ?, modified = ? WHERE name = ?;",
+        (p_label, now(), p_name),
+    )?;
+    Ok(())
+}
+
+pub fn delete_collection(
+    p_sql_connection: &rusqlite::Connection,
+    p_name: &str,
+) -> Result<(), Error> {
+    p_sql_connection.execute("DELETE FROM secret_items WHERE collection = ?;", [p_name])?;
+    p_sql_connection.execute("DELETE FROM secret_aliases WHERE collection = ?;", [p_name])?;
+    p_sql_connection.execute("DELETE FROM secret_collections WHERE name = ?;", [p_name])?;
+    Ok(())
+}
+
+pub fn read_alias(p_sql_connection: &rusqlite::Connection, p_alias: &str) -> Option<String> {
+    p_sql_connection
+        .query_row(
+            "SELECT collection FROM secret_aliases WHERE alias = ?;",
+            [p_alias],
+            |row| row.get(0),
+        )
+        .ok()
+}
+
+/// Every alias, with the collection it points to.
+pub fn aliases(p_sql_connection: &rusqlite::Connection) -> Result<Vec<(String, String)>, Error> {
+    let mut sql_statement =
+        p_sql_connection.prepare("SELECT alias, collection FROM secret_aliases ORDER BY alias;")?;
+    let aliases = sql_statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(aliases)
+}
+
+pub fn set_alias(
+    p_sql_connection: &rusqlite::Connection,
+    p_alias: &str,
+    p_collection: Option<&str>,
+) -> Result<(), Error> {
+    match p_collection {
+        Some(collection) => p_sql_connection.execute(
+            "INSERT OR REPLACE INTO secret_aliases (alias, collection) VALUES (?, ?);",
+            (p_alias, collection),
+        )?,
+        None => {
+            p_sql_connection.execute("DELETE FROM secret_aliases WHERE alias = ?;", [p_alias])?
+        }
+    };
+    Ok(())
+}
+
+fn item_from_row(p_row: &rusqlite::Row) -> rusqlite::Result<Item> {
+    let attributes: String = p_row.get(3)?;
+    Ok(Item {
+        id: p_row.get(0)?,
+        collection: p_row.get(1)?,
+        label: p_row.get(2)?,
+        attributes: serde_json::from_str(&attributes).unwrap_or_default(),
+        created: p_row.get(4)?,
+        modified: p_row.get(5)?,
+    })
+}
+
+/// Every item of a collection, or of all the collections.
+pub fn items(
+    p_sql_connection: &rusqlite::Connection,
+    p_collection: Option<&str>,
+) -> Result<Vec<Item>, Error> {
+    let mut sql_statement = p_sql_connection.prepare(
+        "SELECT id, collection, label, attributes, created, modified FROM secret_items WHERE ?1 IS NULL OR collection = ?1 ORDER BY id;",
+    )?;
+    let items = sql_statement
+        .query_map([p_collection], item_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(items)
+}
+
+pub fn item(p_sql_connection: &rusqlite::Connection, p_id: i64) -> Result<Option<Item>, Error> {
+    Ok(items(p_sql_connection, None)?
+        .into_iter()
+        .find(|item| item.id == p_id))
+}
+
+/// The items whose attributes include all of `p_attributes`.
+pub fn search(
+    p_sql_connection: &rusqlite::Connection,
+    p_collection: Option<&str>,
+    p_attributes: &HashMap<String, String>,
+) -> Result<Vec<Item>, Error> {
+    Ok(items(p_sql_connection, p_collection)?
+        .into_iter()
+        .filter(|item| {
+            p_attributes
+                .iter()
+                .all(|(key, value)| item.attributes.get(key) == Some(value))
+        })
+        .collect())
+}
+
+/// The secret of an item, in the clear.
+pub struct Secret {
+    pub value: Vec<u8>,
+    pub content_type: String,
+}
+
+/// What a secret looks like before being sealed.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SealedSecret {
+    value: String,
+    content_type: String,
+}
+
+fn seal_secret(p_master_key: &str, p_secret: &Secret) -> Vec<u8> {
+    let key = pad_master_key(p_master_key.to_string());
+    let secret = SealedSecret {
+        value: hex::encode(&p_secret.value),
+        content_type: p_secret.content_type.clone(),
+    };
+    let plaintext = serde_json::to_vec(&secret).expect("serializing a secret can't fail");
+    crypto::seal(key.as_bytes(), &plaintext)
+}
+
+/// Creates an item, or replaces the secret and label of the item of the
+/// collection with exactly the same attributes when `p_replace` is set.
+pub fn create_item(
+    p_sql_connection: &rusqlite::Connection,
+    p_master_key: &str,
+    p_collection: &str,
+    p_label: &str,
+    p_attributes: &HashMap<String, String>,
+    p_secret: &Secret,
+    p_replace: bool,
+) -> Result<i64, Error> {
+    let sealed = seal_secret(p_master_key, p_secret);
+    let now = now();
+
+    if p_replace {
+        let existing = items(p_sql_connection, Some(p_collection))?
+            .into_iter()
+            .find(|item| item.attributes == *p_attributes);
+
+        if let Some(existing) = existing {
+            p_sql_connection.execute(
+                "UPDATE secret_items SET label = ?, secret = ?, modified = ? WHERE id = ?;",
+                (p_label, &sealed, now, existing.id),
+            )?;
+            return Ok(existing.id);
+        }
+    }
+
+    p_sql_connection.execute(
+        "INSERT INTO secret_items (collection, label, attributes, secret, created, modified) VALUES (?, ?, ?, ?, ?, ?);",
+        (
+            p_collection,
+            p_label,
+            serde_json::to_string(p_attributes).expect("serializing attributes can't fail"),
+            &sealed,
+            now,
+            now,
+        ),
+    )?;
+
+    Ok(p_sql_connection.last_insert_rowid())
+}
+
+pub fn secret(
+    p_sql_connection: &rusqlite::Connection,
+    p_master_key: &str,
+    p_id: i64,
+) -> Result<Secret, Error> {
+    let sealed = p_sql_connection.query_row(
+        "SELECT secret FROM secret_items WHERE id = ?;",
+        [p_id],
+        |row| row.get::<_, Vec<u8>>(0),
+    )?;
+
+    let key = pad_master_key(p_master_key.to_string());
+    let plaintext = crypto::open(key.as_bytes(), &sealed).ok_or(Error::WrongKeyForData)?;
+    let secret: SealedSecret =
+        serde_json::from_slice(&plaintext).map_err(|_| Error::WrongKeyForData)?;
+    let value = hex::decode(&secret.value).map_err(|_| Error::WrongKeyForData)?;
+
+    Ok(Secret {
+        value,
+        content_type: secret.content_type,
+    })
+}
+
+pub fn set_secret(
+    p_sql_connection: &rusqlite::Connection,
+    p_master_key: &str,
+    p_id: i64,
+    p_secret: &Secret,
+) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "UPDATE secret_items SET secret = ?, modified = ? WHERE id = ?;",
+        (seal_secret(p_master_key, p_secret), now(), p_id),
+    )?;
+    Ok(())
+}
+
+pub fn set_label(
+    p_sql_connection: &rusqlite::Connection,
+    p_id: i64,
+    p_label: &str,
+) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "UPDATE secret_items SET label = ?, modified = ? WHERE id = ?;",
+        (p_label, now(), p_id),
+    )?;
+    Ok(())
+}
+
+pub fn set_attributes(
+    p_sql_connection: &rusqlite::Connection,
+    p_id: i64,
+    p_attributes: &HashMap<String, String>,
+) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "UPDATE secret_items SET attributes = ?, modified = ? WHERE id = ?;",
+        (
+            serde_json::to_string(p_attributes).expect("serializing attributes can't fail"),
+            now(),
+            p_id,
+        ),
+    )?;
+    Ok(())
+}
+
+pub fn delete_item(p_sql_connection: &rusqlite::Connection, p_id: i64) -> Result<(), Error> {
+    p_sql_connection.execute("DELETE FROM secret_items WHERE id = ?;", [p_id])?;
+    Ok(())
+}
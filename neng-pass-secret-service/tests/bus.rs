@@ -0,0 +1,227 @@
+//! Talks to the service over a private bus, started with `dbus-daemon` for
+//! each test. The tests are skipped where `dbus-daemon` isn't installed.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use zbus::{
+    blocking::Connection,
+    zvariant::{OwnedObjectPath, Value},
+};
+
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const SERVICE: &str = "org.freedesktop.Secret.Service";
+
+/// What a secret looks like on the bus: its session, its parameters, its
+/// value and its content type.
+type Secret = (OwnedObjectPath, Vec<u8>, Vec<u8>, String);
+
+struct PrivateBus {
+    daemon: Child,
+    address: String,
+    _config: tempfile::TempDir,
+}
+
+impl PrivateBus {
+    fn start() -> Option<PrivateBus> {
+        let config = tempfile::tempdir().unwrap();
+        let config_file = config.path().join("bus.conf");
+        std::fs::write(
+            &config_file,
+            format!(
+                "<busconfig>
+                    <type>session</type>
+                    <listen>unix:dir={}</listen>
+                    <auth>EXTERNAL</auth>
+                    <policy context=\"default\">
+                        <allow send_destination=\"*\" eavesdrop=\"true\"/>
+                        <allow eavesdrop=\"true\"/>
+                        <allow own=\"*\"/>
+                    </policy>
+                </busconfig>",
+                config.path().display()
+            ),
+        )
+        .unwrap();
+
+        let mut daemon = match Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config_file.display()))
+            .args(["--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(_) => {
+                eprintln!("dbus-daemon isn't installed, skipping");
+                return None;
+            }
+        };
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+
+        Some(PrivateBus {
+            daemon,
+            address: address.trim().to_string(),
+            _config: config,
+        })
+    }
+
+    fn connect(&self) -> Connection {
+        zbus::blocking::connection::Builder::address(self.address.as_str())
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+fn serve(p_bus: &PrivateBus, p_master_key: Option<&str>) -> neng_pass_secret_service::Server {
+    let master_key = p_master_key.map(str::to_string);
+    neng_pass_secret_service::serve(
+        p_bus.connect(),
+        rusqlite::Connection::open_in_memory().unwrap(),
+        move || master_key.clone(),
+        || false,
+        || {},
+    )
+    .unwrap()
+}
+
+fn call<R>(
+    p_client: &Connection,
+    p_path: &str,
+    p_interface: &str,
+    p_method: &str,
+    p_body: &(impl serde::Serialize + zbus::zvariant::DynamicType),
+) -> zbus::Result<R>
+where
+    R: for<'d> zbus::zvariant::DynamicDeserialize<'d>,
+{
+    p_client
+        .call_method(
+            Some(neng_pass_secret_service::BUS_NAME),
+            p_path,
+            Some(p_interface),
+            p_method,
+            p_body,
+        )?
+        .body()
+        .deserialize()
+}
+
+fn open_session(p_client: &Connection) -> OwnedObjectPath {
+    let (_, session): (zbus::zvariant::OwnedValue, OwnedObjectPath) = call(
+        p_client,
+        SERVICE_PATH,
+        SERVICE,
+        "OpenSession",
+        &("plain", Value::from("")),
+    )
+    .unwrap();
+    session
+}
+
+fn session_exists(p_client: &Connection, p_session: &OwnedObjectPath) -> bool {
+    call::<HashMap<String, zbus::zvariant::OwnedValue>>(
+        p_client,
+        p_session.as_str(),
+        "org.freedesktop.DBus.Properties",
+        "GetAll",
+        &("org.freedesktop.Secret.Session",),
+    )
+    .is_ok()
+}
+
+#[test]
+fn stores_secrets_over_a_private_bus() {
+    let bus = match PrivateBus::start() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let _server = serve(&bus, Some("hunter2"));
+
+    let client = bus.connect();
+    let session = open_session(&client);
+
+    let properties = HashMap::from([
+        ("org.freedesktop.Secret.Item.Label", Value::from("GitHub")),
+        (
+            "org.freedesktop.Secret.Item.Attributes",
+            Value::from(HashMap::from([("service", "github.com")])),
+        ),
+    ]);
+    let secret: Secret = (
+        session.clone(),
+        Vec::new(),
+        b"hunter3".to_vec(),
+        "text/plain".to_string(),
+    );
+    let (item, _): (OwnedObjectPath, OwnedObjectPath) = call(
+        &client,
+        "/org/freedesktop/secrets/aliases/default",
+        "org.freedesktop.Secret.Collection",
+        "CreateItem",
+        &(properties, secret, false),
+    )
+    .unwrap();
+
+    let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = call(
+        &client,
+        SERVICE_PATH,
+        SERVICE,
+        "SearchItems",
+        &(HashMap::from([("service", "github.com")]),),
+    )
+    .unwrap();
+    assert_eq!(unlocked, std::slice::from_ref(&item));
+    assert!(locked.is_empty());
+
+    let secrets: HashMap<OwnedObjectPath, Secret> = call(
+        &client,
+        SERVICE_PATH,
+        SERVICE,
+        "GetSecrets",
+        &(vec![item.clone()], session),
+    )
+    .unwrap();
+    assert_eq!(secrets[&item].2, b"hunter3");
+}
+
+#[test]
+fn sessions_end_with_their_client() {
+    let bus = match PrivateBus::start() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let _server = serve(&bus, None);
+
+    let observer = bus.connect();
+    let client = bus.connect();
+    let session = open_session(&client);
+    let other_session = open_session(&observer);
+    assert!(session_exists(&observer, &session));
+
+    drop(client);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while session_exists(&observer, &session) {
+        assert!(Instant::now() < deadline, "the session outlived its client");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert!(session_exists(&observer, &other_session));
+}
@@ -0,0 +1,270 @@
+//! A small server that stores the sealed entries of neng-pass vaults, so that
+//! they can be synced between machines. It never sees a master key or any
+//! plaintext: entries arrive already encrypted, under identifiers derived
+//! from the master key. It also keeps the salt those keys are derived with,
+//! so that every machine derives the same ones.
+
+use std::io::Read;
+
+use neng_pass::sync::{BlobList, KeyParams, PutBlob, PutResponse, RemoteBlob};
+use tiny_http::{Header, Method, Request, Response};
+
+/// Uploads larger than this are refused.
+pub const MAX_BODY_LEN: usize = 1024 * 1024;
+
+pub struct Server {
+    database: rusqlite::Connection,
+    token: Option<String>,
+}
+
+type HttpResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+fn json_response(p_status: u16, p_body: &impl serde::Serialize) -> HttpResponse {
+    Response::from_data(serde_json::to_vec(p_body).unwrap())
+        .with_status_code(p_status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn error_response(p_status: u16, p_message: &str) -> HttpResponse {
+    Response::from_string(p_message).with_status_code(p_status)
+}
+
+/// Reads the body of a request, or returns the response to send if it can't
+/// be read or is too large.
+fn read_body(p_request: &mut Request) -> Result<Vec<u8>, HttpResponse> {
+    let mut body = Vec::new();
+    if p_request
+        .as_reader()
+        .take(MAX_BODY_LEN as u64 + 1)
+        .read_to_end(&mut body)
+        .is_err()
+    {
+        return Err(error_response(400, "Failed to read the request body"));
+    }
+    if body.len() > MAX_BODY_LEN {
+        return Err(error_response(413, "The request body is too large"));
+    }
+
+    Ok(body)
+}
+
+fn is_valid_id(p_id: &str) -> bool {
+    p_id.len() == 64 && p_id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl Server {
+    /// Opens (or creates) the database the blobs are kept in. `p_token` is
+    /// the bearer token clients have to present, if any.
+    pub fn open(p_database_file: &str, p_token: Option<String>) -> Result<Server, rusqlite::Error> {
+        Self::from_connection(rusqlite::Connection::open(p_database_file)?, p_token)
+    }
+
+    pub fn from_connection(
+        p_database: rusqlite::Connection,
+        p_token: Option<String>,
+    ) -> Result<Server, rusqlite::Error> {
+        p_database.execute(
+            "CREATE TABLE IF NOT EXISTS blobs (id TEXT PRIMARY KEY, revision INTEGER NOT NULL, data TEXT NOT NULL);",
+            (),
+        )?;
+        p_database.execute(
+            "CREATE TABLE IF NOT EXISTS key_params (id INTEGER PRIMARY KEY CHECK (id = 0), salt TEXT NOT NULL, memory INTEGER NOT NULL, iterations INTEGER NOT NULL, parallelism INTEGER NOT NULL);",
+            (),
+        )?;
+
+        Ok(Server {
+            database: p_database,
+            token: p_token,
+        })
+    }
+
+    /// Answers requests until the HTTP server is shut down.
+    pub fn serve(&self, p_http_server: &tiny_http::Server) {
+        for request in p_http_server.incoming_requests() {
+            self.respond(request);
+        }
+    }
+
+    pub fn respond(&self, mut p_request: Request) {
+        let response = self.handle(&mut p_request).unwrap_or_else(|err| {
+            eprintln!("[ERROR]: {}", err);
+            error_response(500, "Internal server error")
+        });
+
+        if let Err(err) = p_request.respond(response) {
+            eprintln!("[ERROR]: Failed to send a response: {}", err);
+        }
+    }
+
+    fn is_authorized(&self, p_request: &Request) -> bool {
+        let token = match &self.token {
+            Some(token) => token,
+            None => return true,
+        };
+
+        p_request.headers().iter().any(|header| {
+            header.field.equiv("Authorization")
+                && header.value.as_str() == format!("Bearer {}", token)
+        })
+    }
+
+    fn handle(&self, p_request: &mut Request) -> Result<HttpResponse, rusqlite::Error> {
+        if !self.is_authorized(p_request) {
+            return Ok(error_response(401, "Unauthorized"));
+        }
+
+        let url = p_request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+        if path == "/v1/key" {
+            return match p_request.method() {
+                Method::Get => Ok(match self.key_params()? {
+                    Some(params) => json_response(200, &params),
+                    None => error_response(404, "No key parameters yet"),
+                }),
+                Method::Put => match read_body(p_request) {
+                    Ok(body) => match serde_json::from_slice::<KeyParams>(&body) {
+                        Ok(params) if params.is_valid() => self.put_key_params(&params),
+                        _ => Ok(error_response(400, "Invalid key parameters")),
+                    },
+                    Err(response) => Ok(response),
+                },
+                _ => Ok(error_response(404, "Not found")),
+            };
+        }
+
+        match (p_request.method(), path.strip_prefix("/v1/blobs")) {
+            (Method::Get, Some("")) => {
+                let since = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("since="))
+                    .map(|since| since.parse::<u64>());
+
+                match since {
+                    Some(Ok(since)) => self.list(since).map(|list| json_response(200, &list)),
+                    None => self.list(0).map(|list| json_response(200, &list)),
+                    Some(Err(_)) => Ok(error_response(400, "Invalid revision")),
+                }
+            }
+            (Method::Put, Some(id)) => {
+                let id = id.trim_start_matches('/');
+                if !is_valid_id(id) {
+                    return Ok(error_response(400, "Invalid blob identifier"));
+                }
+
+                let body = match read_body(p_request) {
+                    Ok(body) => body,
+                    Err(response) => return Ok(response),
+                };
+
+                match serde_json::from_slice::<PutBlob>(&body) {
+                    Ok(put) => self.put(id, put),
+                    Err(_) => Ok(error_response(400, "Invalid request body")),
+                }
+            }
+            _ => Ok(error_response(404, "Not found")),
+        }
+    }
+
+    fn key_params(&self) -> Result<Option<KeyParams>, rusqlite::Error> {
+        let params = self.database.query_row(
+            "SELECT salt, memory, iterations, parallelism FROM key_params;",
+            [],
+            |row| {
+                Ok(KeyParams {
+                    salt: row.get(0)?,
+                    memory: row.get(1)?,
+                    iterations: row.get(2)?,
+                    parallelism: row.get(3)?,
+                })
+            },
+        );
+
+        match params {
+            Ok(params) => Ok(Some(params)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Keeps the first key parameters a client sends, and answers every
+    /// client with the ones that are kept.
+    fn put_key_params(&self, p_params: &KeyParams) -> Result<HttpResponse, rusqlite::Error> {
+        self.database.execute(
+            "INSERT OR IGNORE INTO key_params (id, salt, memory, iterations, parallelism) VALUES (0, ?, ?, ?, ?);",
+            (
+                &p_params.salt,
+                p_params.memory,
+                p_params.iterations,
+                p_params.parallelism,
+            ),
+        )?;
+
+        let params = self.key_params()?.expect("the parameters were just stored");
+        Ok(json_response(200, &params))
+    }
+
+    fn current_revision(&self) -> Result<u64, rusqlite::Error> {
+        self.database
+            .query_row("SELECT COALESCE(MAX(revision), 0) FROM blobs;", [], |row| row.get(0))
+    }
+
+    fn list(&self, p_since: u64) -> Result<BlobList, rusqlite::Error> {
+        let mut sql_statement = self
+            .database
+            .prepare("SELECT id, revision, data FROM blobs WHERE revision > ? ORDER BY revision;")?;
+        let blobs = sql_statement
+            .query_map([p_since], |row| {
+                Ok(RemoteBlob {
+                    id: row.get(0)?,
+                    revision: row.get(1)?,
+                    data: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(BlobList {
+            revision: self.current_revision()?,
+            blobs,
+        })
+    }
+
+    /// Stores a blob, unless it was changed since the revision the client
+    /// based its change on.
+    fn put(&self, p_id: &str, p_put: PutBlob) -> Result<HttpResponse, rusqlite::Error> {
+        let transaction = self.database.unchecked_transaction()?;
+
+        let current = transaction
+            .query_row(
+                "SELECT revision, data FROM blobs WHERE id = ?;",
+                [p_id],
+                |row| {
+                    Ok(RemoteBlob {
+                        id: p_id.to_string(),
+                        revision: row.get(0)?,
+                        data: row.get(1)?,
+                    })
+                },
+            )
+            .ok();
+
+        let current_revision = current.as_ref().map(|blob| blob.revision).unwrap_or(0);
+        if current_revision != p_put.base_revision {
+            let current = current.unwrap_or(RemoteBlob {
+                id: p_id.to_string(),
+                revision: 0,
+                data: String::new(),
+            });
+            return Ok(json_response(409, &current));
+        }
+
+        let revision = self.current_revision()? + 1;
+        transaction.execute(
+            "INSERT OR REPLACE INTO blobs (id, revision, data) VALUES (?, ?, ?);",
+            (p_id, revision, &p_put.data),
+        )?;
+        transaction.commit()?;
+
+        Ok(json_response(200, &PutResponse { revision }))
+    }
+}
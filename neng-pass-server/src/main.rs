@@ -0,0 +1,51 @@
+use clap::{arg, Command};
+
+fn cli() -> Command {
+    Command::new("neng-pass-server")
+        .about("Stores encrypted neng-pass vaults so that they can be synced between machines.")
+        .arg(
+            arg!(-a --address <ADDRESS> "The address to listen on.")
+                .default_value("127.0.0.1:8080"),
+        )
+        .arg(
+            arg!(-d --database <FILE> "The file the encrypted entries are stored in.")
+                .default_value("neng-pass-server.db"),
+        )
+        .arg(
+            arg!(-t --token <TOKEN> "The access token clients have to present. Can also be set with NENG_PASS_SERVER_TOKEN.")
+                .required(false),
+        )
+}
+
+fn main() {
+    let cli_matches = cli().get_matches();
+    let address = cli_matches.get_one::<String>("address").unwrap();
+    let database_file = cli_matches.get_one::<String>("database").unwrap();
+    let token = cli_matches
+        .get_one::<String>("token")
+        .cloned()
+        .or_else(|| std::env::var("NENG_PASS_SERVER_TOKEN").ok());
+
+    if token.is_none() {
+        eprintln!("[WARNING]: No access token was set, so anybody who can reach the server can use it.");
+    }
+
+    let server = match neng_pass_server::Server::open(database_file, token) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("[ERROR]: Failed to open {}: {}", database_file, err);
+            std::process::exit(1);
+        }
+    };
+
+    let http_server = match tiny_http::Server::http(address) {
+        Ok(http_server) => http_server,
+        Err(err) => {
+            eprintln!("[ERROR]: Failed to listen on {}: {}", address, err);
+            std::process::exit(1);
+        }
+    };
+
+    eprintln!("[INFO]: Listening on {}", address);
+    server.serve(&http_server);
+}
@@ -0,0 +1,184 @@
+//! Syncs two vaults through a server running on a free local port.
+
+use std::{sync::Arc, thread, time::Duration};
+
+use neng_pass::{
+    merge::Resolution,
+    sync::{self, KeyParams, PutOutcome, SyncClient, SyncKeys},
+    Error,
+};
+
+const MASTER_KEY: &str = "hunter2";
+
+struct LocalServer {
+    http_server: Arc<tiny_http::Server>,
+    thread: Option<thread::JoinHandle<()>>,
+    url: String,
+}
+
+impl LocalServer {
+    fn start() -> LocalServer {
+        let http_server = Arc::new(tiny_http::Server::http("127.0.0.1:0").unwrap());
+        let url = format!("http://{}", http_server.server_addr());
+
+        let thread = thread::spawn({
+            let http_server = Arc::clone(&http_server);
+            move || {
+                let database = rusqlite::Connection::open_in_memory().unwrap();
+                let server = neng_pass_server::Server::from_connection(database, None).unwrap();
+                server.serve(&http_server);
+            }
+        });
+
+        LocalServer {
+            http_server,
+            thread: Some(thread),
+            url,
+        }
+    }
+}
+
+impl Drop for LocalServer {
+    fn drop(&mut self) {
+        self.http_server.unblock();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+fn vault() -> rusqlite::Connection {
+    let sql_connection = rusqlite::Connection::open_in_memory().unwrap();
+    neng_pass::prepare_database(&sql_connection).unwrap();
+    sql_connection
+}
+
+/// Changes a password, making sure the change gets a timestamp of its own.
+fn set_password(p_vault: &rusqlite::Connection, p_name: &str, p_password: &str) {
+    thread::sleep(Duration::from_millis(2));
+    neng_pass::delete_password(p_name, p_vault).unwrap();
+    neng_pass::create_password(MASTER_KEY.to_string(), p_name, p_password, p_vault).unwrap();
+}
+
+fn get_password(p_vault: &rusqlite::Connection, p_name: &str) -> String {
+    neng_pass::get_password(MASTER_KEY.to_string(), p_name, p_vault)
+        .unwrap()
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+#[test]
+fn syncs_two_vaults() {
+    let server = LocalServer::start();
+    let client = SyncClient::new(&server.url, None);
+    let keys = SyncKeys::fetch(&client, MASTER_KEY).unwrap();
+
+    let laptop = vault();
+    let desktop = vault();
+
+    set_password(&laptop, "github", "first");
+    let report = sync::push(&client, &keys, &laptop).unwrap();
+    assert_eq!(report.pushed, ["github"]);
+
+    let report = sync::pull(&client, &keys, &desktop, |_| None).unwrap();
+    assert_eq!(report.added, ["github"]);
+    assert_eq!(get_password(&desktop, "github"), "first");
+
+    // Both change the password; the second upload is based on an old
+    // revision, so the server refuses it.
+    set_password(&laptop, "github", "laptop");
+    set_password(&desktop, "github", "desktop");
+    assert_eq!(
+        sync::push(&client, &keys, &laptop).unwrap().pushed,
+        ["github"]
+    );
+    assert_eq!(
+        sync::push(&client, &keys, &desktop).unwrap().rejected,
+        ["github"]
+    );
+
+    // That's a 409 from the server, with the blob as it is now.
+    let blob = client.fetch(0).unwrap().blobs.remove(0);
+    match client
+        .put(&blob.id, blob.revision - 1, blob.data.clone())
+        .unwrap()
+    {
+        PutOutcome::Rejected(current) => assert_eq!(current.revision, blob.revision),
+        PutOutcome::Stored(_) => panic!("an upload based on an old revision was stored"),
+    }
+
+    let mut conflicts = Vec::new();
+    let report = sync::pull(&client, &keys, &desktop, |conflict| {
+        conflicts.push(conflict.name.clone());
+        Some(Resolution::KeepLocal)
+    })
+    .unwrap();
+    assert_eq!(conflicts, ["github"]);
+    assert!(report.unresolved.is_empty());
+
+    // The conflict was settled in favour of the desktop, which can now upload
+    // its password on top of the laptop's.
+    assert_eq!(
+        sync::push(&client, &keys, &desktop).unwrap().pushed,
+        ["github"]
+    );
+    let report = sync::pull(&client, &keys, &laptop, |_| None).unwrap();
+    assert_eq!(report.updated, ["github"]);
+    assert_eq!(get_password(&laptop, "github"), "desktop");
+
+    // Deletions go through too.
+    thread::sleep(Duration::from_millis(2));
+    neng_pass::delete_password("github", &laptop).unwrap();
+    assert_eq!(
+        sync::push(&client, &keys, &laptop).unwrap().pushed,
+        ["github"]
+    );
+    let report = sync::pull(&client, &keys, &desktop, |_| None).unwrap();
+    assert_eq!(report.deleted, ["github"]);
+    assert!(neng_pass::list_passwords(&desktop).unwrap().is_empty());
+}
+
+#[test]
+fn the_server_sees_no_secrets() {
+    let server = LocalServer::start();
+    let client = SyncClient::new(&server.url, None);
+
+    // Every machine gets the salt the first one stored.
+    let params = client.put_key_params(&KeyParams::generate()).unwrap();
+    assert_eq!(
+        client.put_key_params(&KeyParams::generate()).unwrap(),
+        params
+    );
+    assert_eq!(client.key_params().unwrap(), Some(params));
+
+    let keys = SyncKeys::fetch(&client, MASTER_KEY).unwrap();
+    let laptop = vault();
+    set_password(&laptop, "github", "secret");
+    sync::push(&client, &keys, &laptop).unwrap();
+
+    let blobs = client.fetch(0).unwrap().blobs;
+    assert_eq!(blobs.len(), 1);
+    for secret in ["github", MASTER_KEY] {
+        let encoded = hex::encode(secret);
+        assert!(!blobs[0].id.contains(&encoded) && !blobs[0].data.contains(&encoded));
+    }
+
+    // Another master key gives other keys, which can't open the entries.
+    let other_keys = SyncKeys::fetch(&client, "not hunter2").unwrap();
+    assert!(matches!(
+        sync::pull(&client, &other_keys, &vault(), |_| None),
+        Err(Error::WrongKeyForData)
+    ));
+}
+
+#[test]
+fn invalid_key_params_are_refused() {
+    let server = LocalServer::start();
+    let client = SyncClient::new(&server.url, None);
+
+    let mut params = KeyParams::generate();
+    params.iterations = u32::MAX;
+    assert!(client.put_key_params(&params).is_err());
+    assert!(SyncKeys::derive(MASTER_KEY, &params).is_err());
+    assert_eq!(client.key_params().unwrap(), None);
+}
@@ -0,0 +1,261 @@
+//! The vault, for Kotlin, Swift and Python. UniFFI generates the bindings from
+//! the functions and types exported here, so every language gets the same
+//! interface, with errors as exceptions of one `VaultException` hierarchy.
+//!
+//! A `Vault` is a `neng_pass::session::Session` behind a lock, so that it can
+//! be shared between threads.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use neng_pass::session::Session;
+use zeroize::Zeroizing;
+
+uniffi::setup_scaffolding!();
+
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum VaultError {
+    /// The master key is wrong, or the data wasn't encrypted with it.
+    WrongMasterKey(String),
+    /// No master key was set yet.
+    NoMasterKey(String),
+    /// The vault has to be unlocked first.
+    Locked,
+    EntryExists(String),
+    EntryNotFound(String),
+    /// The input isn't valid, for example because it is too long.
+    InvalidInput(String),
+    /// The vault couldn't be read or written.
+    Storage(String),
+    Other(String),
+}
+
+impl std::fmt::Display for VaultError {
+    fn fmt(&self, p_formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VaultError::Locked => write!(p_formatter, "The vault is locked, unlock it first."),
+            VaultError::WrongMasterKey(message)
+            | VaultError::NoMasterKey(message)
+            | VaultError::EntryExists(message)
+            | VaultError::EntryNotFound(message)
+            | VaultError::InvalidInput(message)
+            | VaultError::Storage(message)
+            | VaultError::Other(message) => write!(p_formatter, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for VaultError {}
+
+impl From<neng_pass::Error> for VaultError {
+    fn from(p_error: neng_pass::Error) -> VaultError {
+        let message = p_error.get_message();
+        match p_error {
+            neng_pass::Error::MasterKeyDoesntExist => VaultError::NoMasterKey(message),
+            neng_pass::Error::WrongKeyForData => VaultError::WrongMasterKey(message),
+            neng_pass::Error::VaultLocked => VaultError::Locked,
+            _ => match p_error.exit_code() {
+                3 => VaultError::WrongMasterKey(message),
+                4 => VaultError::EntryNotFound(message),
+                5 => VaultError::EntryExists(message),
+                6 => VaultError::InvalidInput(message),
+                7 => VaultError::Storage(message),
+                _ => VaultError::Other(message),
+            },
+        }
+    }
+}
+
+/// A login to offer for a page or an app.
+#[derive(uniffi::Record)]
+pub struct Login {
+    pub name: String,
+    pub username: Option<String>,
+    pub password: String,
+}
+
+impl From<neng_pass::session::Login> for Login {
+    fn from(p_login: neng_pass::session::Login) -> Login {
+        Login {
+            name: p_login.name,
+            username: p_login.username,
+            password: p_login.password,
+        }
+    }
+}
+
+#[derive(uniffi::Object)]
+pub struct Vault {
+    session: Mutex<Session>,
+}
+
+impl Vault {
+    fn session(&self) -> MutexGuard<'_, Session> {
+        // A panic while the vault was in use leaves nothing half done, SQLite
+        // rolls unfinished transactions back.
+        self.session
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[uniffi::export]
+impl Vault {
+    /// Opens the vault in a data directory, creating it if needed. The vault
+    /// is locked until `unlock` is called.
+    #[uniffi::constructor]
+    pub fn open(data_dir: String) -> Result<Arc<Vault>, VaultError> {
+        Ok(Arc::new(Vault {
+            session: Mutex::new(Session::open(data_dir)?),
+        }))
+    }
+
+    pub fn is_master_key_set(&self) -> bool {
+        self.session().is_master_key_set()
+    }
+
+    /// Sets the master key, which must not have been set yet, and unlocks the
+    /// vault with it.
+    pub fn set_master_key(&self, new_master_key: String) -> Result<(), VaultError> {
+        let new_master_key = Zeroizing::new(new_master_key);
+        Ok(self.session().set_master_key(&new_master_key)?)
+    }
+
+    /// Checks the master key, and keeps it until the vault is locked.
+    pub fn unlock(&self, master_key: String) -> Result<(), VaultError> {
+        let master_key = Zeroizing::new(master_key);
+        Ok(self.session().unlock(&master_key)?)
+    }
+
+    pub fn lock(&self) {
+        self.session().lock();
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.session().is_unlocked()
+    }
+
+    pub fn list_entries(&self) -> Result<Vec<String>, VaultError> {
+        Ok(self.session().list_entries()?)
+    }
+
+    pub fn get_entry(&self, name: String) -> Result<String, VaultError> {
+        Ok(self.session().get_entry(&name)?)
+    }
+
+    pub fn save_entry(&self, name: String, password: String) -> Result<(), VaultError> {
+        let password = Zeroizing::new(password);
+        Ok(self.session().save_entry(&name, &password)?)
+    }
+
+    pub fn delete_entry(&self, name: String) -> Result<(), VaultError> {
+        Ok(self.session().delete_entry(&name)?)
+    }
+
+    /// The logins to offer for a page, the best matches first.
+    pub fn logins_for_url(&self, url: String) -> Result<Vec<Login>, VaultError> {
+        let session = self.session();
+        let candidates = neng_pass::autofill::find_entries(&url, session.sql_connection())?;
+        Ok(session
+            .logins(candidates)?
+            .into_iter()
+            .map(Login::from)
+            .collect())
+    }
+
+    /// The logins to offer for an Android app, the best matches first.
+    pub fn logins_for_app(&self, package_name: String) -> Result<Vec<Login>, VaultError> {
+        let session = self.session();
+        let candidates =
+            neng_pass::autofill::find_app_entries(&package_name, session.sql_connection())?;
+        Ok(session
+            .logins(candidates)?
+            .into_iter()
+            .map(Login::from)
+            .collect())
+    }
+
+    /// Saves a login that was just used on a page or in an app, replacing the
+    /// one with the same site and user name. Returns the name of its entry.
+    pub fn save_login(
+        &self,
+        url: String,
+        username: Option<String>,
+        password: String,
+    ) -> Result<String, VaultError> {
+        let password = Zeroizing::new(password);
+        Ok(self
+            .session()
+            .save_login(&url, username.as_deref(), &password)?)
+    }
+}
+
+#[uniffi::export]
+pub fn generate_password() -> String {
+    String::from_utf8_lossy(&neng_pass::generate_password()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vaults_lock_and_unlock() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_dir = data_dir.path().to_string_lossy().to_string();
+
+        let vault = Vault::open(data_dir.clone()).unwrap();
+        assert!(matches!(
+            vault.unlock("hunter2".to_string()),
+            Err(VaultError::NoMasterKey(_))
+        ));
+        vault.set_master_key("hunter2".to_string()).unwrap();
+        assert!(vault.is_unlocked());
+
+        let password = "hunter4".to_string();
+        vault
+            .save_entry("github".to_string(), password.clone())
+            .unwrap();
+        vault
+            .save_login(
+                "https://www.example.com/login".to_string(),
+                Some("octocat".to_string()),
+                "hunter3".to_string(),
+            )
+            .unwrap();
+
+        vault.lock();
+        assert!(matches!(
+            vault.get_entry("github".to_string()),
+            Err(VaultError::Locked)
+        ));
+        assert!(matches!(
+            vault.logins_for_url("https://example.com".to_string()),
+            Err(VaultError::Locked)
+        ));
+
+        let vault = Vault::open(data_dir).unwrap();
+        assert!(vault.is_master_key_set());
+        assert!(matches!(
+            vault.unlock("hunter3".to_string()),
+            Err(VaultError::WrongMasterKey(_))
+        ));
+        vault.unlock("hunter2".to_string()).unwrap();
+        assert_eq!(vault.get_entry("github".to_string()).unwrap(), password);
+        assert!(matches!(
+            vault.save_entry("github".to_string(), "x".to_string()),
+            Err(VaultError::EntryExists(_))
+        ));
+
+        let logins = vault
+            .logins_for_url("https://example.com/".to_string())
+            .unwrap();
+        assert_eq!(logins.len(), 1);
+        assert_eq!(logins[0].name, "octocat@example.com");
+        assert_eq!(logins[0].username.as_deref(), Some("octocat"));
+        assert_eq!(logins[0].password, "hunter3");
+
+        vault.delete_entry("github".to_string()).unwrap();
+        assert_eq!(vault.list_entries().unwrap(), ["octocat@example.com"]);
+    }
+}
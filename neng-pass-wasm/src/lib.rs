@@ -0,0 +1,65 @@
+//! The parts of neng-pass that work in a browser, for the browser extension
+//! and the web viewer: opening exports of a vault, generating passwords and
+//! estimating their strength. Nothing here touches a vault on disk.
+
+use wasm_bindgen::prelude::*;
+
+/// An entry of an opened export.
+#[wasm_bindgen(getter_with_clone)]
+pub struct ExportedEntry {
+    pub name: String,
+    pub password: String,
+    /// When the entry was last changed, in milliseconds since the Unix epoch,
+    /// like `Date.now()`.
+    pub modified: f64,
+}
+
+/// Decrypts an export made with `neng-pass export`, with the master key it
+/// was made with.
+#[wasm_bindgen(js_name = openExport)]
+pub fn open_export(passphrase: &str, export: &[u8]) -> Result<Vec<ExportedEntry>, JsError> {
+    let entries = neng_pass::export::open_export(passphrase, export)
+        .map_err(|err| JsError::new(&err.get_message()))?;
+
+    Ok(entries
+        .iter()
+        .map(|entry| ExportedEntry {
+            name: entry.name.clone(),
+            password: entry.password.clone(),
+            modified: entry.modified as f64,
+        })
+        .collect())
+}
+
+#[wasm_bindgen(js_name = generatePassword)]
+pub fn generate_password() -> String {
+    String::from_utf8_lossy(&neng_pass::generate_password()).to_string()
+}
+
+#[wasm_bindgen]
+pub struct Strength(neng_pass::strength::Strength);
+
+#[wasm_bindgen]
+impl Strength {
+    /// The estimated entropy, in bits.
+    #[wasm_bindgen(getter)]
+    pub fn entropy(&self) -> f64 {
+        self.0.entropy
+    }
+
+    /// From 0 (very weak) to 4 (very strong).
+    #[wasm_bindgen(getter)]
+    pub fn score(&self) -> u8 {
+        self.0.score
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn label(&self) -> String {
+        self.0.label().to_string()
+    }
+}
+
+#[wasm_bindgen(js_name = estimateStrength)]
+pub fn estimate_strength(password: &str) -> Strength {
+    Strength(neng_pass::strength::estimate(password))
+}
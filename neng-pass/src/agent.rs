@@ -0,0 +1,329 @@
+//! A background process that keeps the master key after it has been entered
+//! once, so that it doesn't have to be typed (and checked with Argon2) for
+//! every command.
+//!
+//! The agent listens on a Unix socket that only its owner can open, and
+//! additionally checks that whoever connects runs as the same user. The key
+//! is kept in memory that is locked against being swapped out, and is wiped
+//! after a period without use.
+//!
+//! The protocol is line based: `UNLOCK <hex key>`, `GET`, `LOCK` and `STOP`,
+//! each answered with `OK`, `KEY <hex key>`, `LOCKED` or `ERROR <message>`.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        io::AsRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use zeroize::Zeroize;
+
+use crate::{Error, MAX_MASTER_KEY_LEN};
+
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// A buffer for the master key that is locked in memory and wiped on drop.
+struct LockedKey {
+    bytes: Box<[u8; MAX_MASTER_KEY_LEN]>,
+    len: usize,
+}
+
+impl LockedKey {
+    fn new(p_key: &[u8]) -> LockedKey {
+        let mut bytes = Box::new([0u8; MAX_MASTER_KEY_LEN]);
+        // If locking fails (because of RLIMIT_MEMLOCK, for example) the key is
+        // still usable, it may just end up in swap.
+        unsafe {
+            libc::mlock(bytes.as_ptr() as *const libc::c_void, MAX_MASTER_KEY_LEN);
+        }
+        bytes[..p_key.len()].copy_from_slice(p_key);
+
+        LockedKey {
+            bytes,
+            len: p_key.len(),
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl Drop for LockedKey {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+        unsafe {
+            libc::munlock(self.bytes.as_ptr() as *const libc::c_void, MAX_MASTER_KEY_LEN);
+        }
+    }
+}
+
+struct AgentState {
+    key: Option<LockedKey>,
+    last_used: Instant,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn peer_uid(p_stream: &UnixStream) -> Option<u32> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            p_stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    (result == 0).then_some(credentials.uid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn peer_uid(p_stream: &UnixStream) -> Option<u32> {
+    let mut uid = 0;
+    let mut gid = 0;
+    let result = unsafe { libc::getpeereid(p_stream.as_raw_fd(), &mut uid, &mut gid) };
+
+    (result == 0).then_some(uid)
+}
+
+fn agent_error(p_message: impl std::fmt::Display) -> Error {
+    Error::AgentError(p_message.to_string())
+}
+
+/// Binds a socket that only the current user can connect to. A socket left
+/// behind by a process that crashed is replaced.
+pub(crate) fn bind_private_socket(p_socket_path: &Path) -> Result<UnixListener, Error> {
+    let _ = std::fs::remove_file(p_socket_path);
+
+    // Create the socket without any permissions for others from the start,
+    // rather than fixing them after the fact.
+    let old_umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(p_socket_path);
+    unsafe {
+        libc::umask(old_umask);
+    }
+    let listener = listener?;
+    std::fs::set_permissions(p_socket_path, std::fs::Permissions::from_mode(0o600))?;
+
+    Ok(listener)
+}
+
+/// Whether an agent is listening on the socket.
+pub fn is_running(p_socket_path: &Path) -> bool {
+    UnixStream::connect(p_socket_path).is_ok()
+}
+
+/// Runs the agent until it is told to stop. The key is forgotten after
+/// `p_idle_timeout` without any request for it.
+pub fn run_agent(p_socket_path: &Path, p_idle_timeout: Duration) -> Result<(), Error> {
+    if is_running(p_socket_path) {
+        return Err(agent_error("An agent is already running."));
+    }
+
+    let listener = bind_private_socket(p_socket_path)?;
+
+    let state = Arc::new(Mutex::new(AgentState {
+        key: None,
+        last_used: Instant::now(),
+    }));
+
+    {
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(1));
+            let mut state = state.lock().unwrap();
+            if state.key.is_some() && state.last_used.elapsed() >= p_idle_timeout {
+                state.key = None;
+            }
+        });
+    }
+
+    let own_uid = unsafe { libc::getuid() };
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        if peer_uid(&stream) != Some(own_uid) {
+            let _ = stream.write_all(b"ERROR Permission denied\n");
+            continue;
+        }
+
+        // Don't let a client that never sends anything block the agent.
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+
+        let mut request = String::new();
+        if BufReader::new(&stream).read_line(&mut request).is_err() {
+            continue;
+        }
+
+        let (command, argument) = request
+            .trim_end()
+            .split_once(' ')
+            .unwrap_or((request.trim_end(), ""));
+
+        let mut state = state.lock().unwrap();
+        let mut response = match command {
+            "UNLOCK" => match hex::decode(argument) {
+                Ok(mut key) if key.len() <= MAX_MASTER_KEY_LEN => {
+                    state.key = Some(LockedKey::new(&key));
+                    state.last_used = Instant::now();
+                    key.zeroize();
+                    "OK\n".to_string()
+                }
+                _ => "ERROR Invalid key\n".to_string(),
+            },
+            "GET" => match &state.key {
+                Some(key) => {
+                    let response = format!("KEY {}\n", hex::encode(key.as_bytes()));
+                    state.last_used = Instant::now();
+                    response
+                }
+                None => "LOCKED\n".to_string(),
+            },
+            "LOCK" => {
+                state.key = None;
+                "OK\n".to_string()
+            }
+            "STOP" => {
+                state.key = None;
+                let _ = stream.write_all(b"OK\n");
+                break;
+            }
+            _ => "ERROR Unknown command\n".to_string(),
+        };
+
+        let _ = stream.write_all(response.as_bytes());
+        response.zeroize();
+        request.zeroize();
+    }
+
+    let _ = std::fs::remove_file(p_socket_path);
+    Ok(())
+}
+
+fn send(p_socket_path: &Path, p_request: &str) -> Result<String, Error> {
+    let mut stream = UnixStream::connect(p_socket_path)
+        .map_err(|err| agent_error(format!("The agent is not running ({}).", err)))?;
+    stream.write_all(p_request.as_bytes())?;
+
+    let mut response = String::new();
+    BufReader::new(&stream).read_line(&mut response)?;
+
+    match response.trim_end().strip_prefix("ERROR ") {
+        Some(message) => Err(agent_error(message)),
+        None => Ok(response.trim_end().to_string()),
+    }
+}
+
+/// Hands the master key to the agent.
+pub fn unlock(p_socket_path: &Path, p_master_key: &str) -> Result<(), Error> {
+    let mut request = format!("UNLOCK {}\n", hex::encode(p_master_key.as_bytes()));
+    let result = send(p_socket_path, &request);
+    request.zeroize();
+
+    result.map(|_| ())
+}
+
+/// Makes the agent forget the master key.
+pub fn lock(p_socket_path: &Path) -> Result<(), Error> {
+    send(p_socket_path, "LOCK\n").map(|_| ())
+}
+
+/// Stops the agent.
+pub fn stop(p_socket_path: &Path) -> Result<(), Error> {
+    send(p_socket_path, "STOP\n").map(|_| ())
+}
+
+/// Asks the agent for the master key. Returns `None` if no agent is running
+/// or it is locked.
+pub fn get_master_key(p_socket_path: &Path) -> Option<String> {
+    let mut response = send(p_socket_path, "GET\n").ok()?;
+    let key = response
+        .strip_prefix("KEY ")
+        .and_then(|key| hex::decode(key).ok())
+        .and_then(|key| String::from_utf8(key).ok());
+    response.zeroize();
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Starts an agent in the background, and waits until it listens.
+    fn start_agent(p_socket_path: &Path, p_idle_timeout: Duration) -> std::thread::JoinHandle<()> {
+        let socket_path = p_socket_path.to_owned();
+        let agent = std::thread::spawn(move || run_agent(&socket_path, p_idle_timeout).unwrap());
+
+        while !is_running(p_socket_path) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        agent
+    }
+
+    #[test]
+    fn sockets_are_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("agent.sock");
+        let _listener = bind_private_socket(&socket_path).unwrap();
+
+        let mode = std::fs::metadata(&socket_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn keys_are_kept_until_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("agent.sock");
+        let agent = start_agent(&socket_path, DEFAULT_IDLE_TIMEOUT);
+        assert!(run_agent(&socket_path, DEFAULT_IDLE_TIMEOUT).is_err());
+
+        assert_eq!(get_master_key(&socket_path), None);
+        unlock(&socket_path, "hunter2").unwrap();
+        assert_eq!(get_master_key(&socket_path).as_deref(), Some("hunter2"));
+        lock(&socket_path).unwrap();
+        assert_eq!(get_master_key(&socket_path), None);
+
+        stop(&socket_path).unwrap();
+        agent.join().unwrap();
+        assert!(!socket_path.exists());
+        assert_eq!(get_master_key(&socket_path), None);
+    }
+
+    #[test]
+    fn idle_keys_are_forgotten() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("agent.sock");
+        let agent = start_agent(&socket_path, Duration::from_millis(100));
+
+        unlock(&socket_path, "hunter2").unwrap();
+        // The agent looks for idle keys every second.
+        std::thread::sleep(Duration::from_millis(2500));
+        assert_eq!(get_master_key(&socket_path), None);
+
+        stop(&socket_path).unwrap();
+        agent.join().unwrap();
+    }
+}
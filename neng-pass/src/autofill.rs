@@ -0,0 +1,822 @@
+//! Finds the entries to offer for a page, from the URLs recorded for them.
+//!
+//! Every URL of an entry has a match mode. By default it matches any page on
+//! the same registrable domain (`accounts.google.com` and `mail.google.com`
+//! share `google.com`), as told by the embedded Public Suffix List. It can
+//! also be limited to the exact host, to URLs starting with it, or be a
+//! regular expression. Domains that the user declared equivalent, like
+//! `google.com` and `youtube.com`, match each other in the default mode.
+//!
+//! Entries without any URL are matched by their name, if it looks like a
+//! host: `github.com` or `octocat@github.com`.
+//!
+//! Android apps are identified by URLs like `androidapp://com.github.android`,
+//! which only ever match the same app. An app can also be linked to the
+//! domains it shares its logins with, the way Digital Asset Links declare it,
+//! so that it is offered the entries of those domains.
+//!
+//! Browsers also report the domain of the page they show. Any app could claim
+//! to show any page, so that is only believed from the apps on the list of
+//! browsers kept in the vault.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use publicsuffix::Psl;
+
+use crate::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Pages on the same registrable domain, or an equivalent one.
+    Domain,
+    /// Pages on exactly the same host.
+    Host,
+    /// Pages whose URL starts with this one.
+    Prefix,
+    /// Pages whose URL matches this regular expression.
+    Regex,
+}
+
+impl MatchMode {
+    pub const NAMES: &'static [&'static str] = &["domain", "host", "prefix", "regex"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchMode::Domain => "domain",
+            MatchMode::Host => "host",
+            MatchMode::Prefix => "prefix",
+            MatchMode::Regex => "regex",
+        }
+    }
+
+    pub fn parse(p_name: &str) -> Result<MatchMode, Error> {
+        match p_name {
+            "domain" => Ok(MatchMode::Domain),
+            "host" => Ok(MatchMode::Host),
+            "prefix" => Ok(MatchMode::Prefix),
+            "regex" => Ok(MatchMode::Regex),
+            _ => Err(Error::SyntaxError(format!(
+                "'{}' is not a match mode, only {} are",
+                p_name,
+                MatchMode::NAMES.join(", ")
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryUrl {
+    pub name: String,
+    pub url: String,
+    pub mode: MatchMode,
+}
+
+/// How an entry matched a page, from the loosest to the closest match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Match {
+    EquivalentDomain,
+    Domain,
+    Regex,
+    Host,
+    Prefix,
+}
+
+impl Match {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Match::EquivalentDomain => "equivalent_domain",
+            Match::Domain => "domain",
+            Match::Regex => "regex",
+            Match::Host => "host",
+            Match::Prefix => "prefix",
+        }
+    }
+}
+
+/// An Android app that shares its logins with a domain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppLink {
+    pub package: String,
+    pub domain: String,
+}
+
+/// An entry to offer for a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub name: String,
+    /// The URL of the entry that matched.
+    pub url: String,
+    pub matched: Match,
+}
+
+fn public_suffix_list() -> &'static publicsuffix::List {
+    static LIST: OnceLock<publicsuffix::List> = OnceLock::new();
+    LIST.get_or_init(|| {
+        include_str!("public_suffix_list.dat")
+            .parse()
+            .expect("the embedded Public Suffix List is valid")
+    })
+}
+
+/// The part of a host that can be registered, like `example.co.uk` for
+/// `www.example.co.uk`. Hosts without one, like IP addresses and `localhost`,
+/// are returned as they are.
+pub fn registrable_domain(p_host: &str) -> String {
+    let host = p_host.trim_end_matches('.').to_ascii_lowercase();
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return host;
+    }
+
+    public_suffix_list()
+        .domain(host.as_bytes())
+        .and_then(|domain| {
+            std::str::from_utf8(domain.as_bytes())
+                .ok()
+                .map(str::to_string)
+        })
+        .unwrap_or(host)
+}
+
+fn parse_url(p_url: &str) -> Result<url::Url, Error> {
+    url::Url::parse(p_url)
+        .map_err(|err| Error::SyntaxError(format!("'{}' is not a valid URL: {}", p_url, err)))
+}
+
+/// The scheme of the URLs that stand for Android apps.
+pub const APP_SCHEME: &str = "androidapp";
+
+/// The URL that stands for an Android app, to record for an entry or to look
+/// entries up with.
+pub fn app_url(p_package: &str) -> String {
+    format!("{}://{}", APP_SCHEME, p_package.to_ascii_lowercase())
+}
+
+/// The user name in an entry name like `octocat@github.com`, if there is one.
+pub fn entry_username(p_name: &str) -> Option<&str> {
+    p_name.rsplit_once('@').map(|(username, _)| username)
+}
+
+/// The URL an entry is taken to be for when none was recorded, from a name
+/// like `github.com` or `octocat@github.com`.
+fn url_from_name(p_name: &str) -> Option<url::Url> {
+    let host = p_name.rsplit_once('@').map_or(p_name, |(_, host)| host);
+    if !host.contains('.') || host.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let url = url::Url::parse(&format!("https://{}", host)).ok()?;
+    (url.path() == "/" && url.host_str().is_some()).then_some(url)
+}
+
+pub fn add_url(
+    p_name: &str,
+    p_url: &str,
+    p_mode: MatchMode,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<(), Error> {
+    if !crate::list_passwords(p_sql_connection)?
+        .iter()
+        .any(|name| name == p_name)
+    {
+        return Err(Error::PasswordDoesntExist(Box::from(p_name)));
+    }
+
+    // Everything but a regular expression is stored the way the url crate
+    // writes it, so that prefixes compare with what browsers report.
+    let url = match p_mode {
+        MatchMode::Regex => {
+            regex::Regex::new(p_url).map_err(|err| {
+                Error::SyntaxError(format!(
+                    "'{}' is not a valid regular expression: {}",
+                    p_url, err
+                ))
+            })?;
+            p_url.to_string()
+        }
+        _ => {
+            let url = parse_url(p_url)?;
+            if url.host_str().is_none() {
+                return Err(Error::SyntaxError(format!("'{}' has no host", p_url)));
+            }
+            url.to_string()
+        }
+    };
+
+    p_sql_connection.execute(
+        "INSERT OR REPLACE INTO entry_urls (name, url, mode) VALUES (?, ?, ?);",
+        (p_name, &url, p_mode.as_str()),
+    )?;
+
+    Ok(())
+}
+
+pub fn remove_url(
+    p_name: &str,
+    p_url: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<(), Error> {
+    // The URL may have been stored in a normalized form.
+    let normalized = parse_url(p_url)
+        .map(|url| url.to_string())
+        .unwrap_or_default();
+    let removed = p_sql_connection.execute(
+        "DELETE FROM entry_urls WHERE name = ? AND (url = ? OR url = ?);",
+        (p_name, p_url, normalized),
+    )?;
+
+    if removed == 0 {
+        return Err(Error::UrlDoesntExist(Box::from(p_url)));
+    }
+
+    Ok(())
+}
+
+/// The URLs of one entry, or of every entry.
+pub fn list_urls(
+    p_name: Option<&str>,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<Vec<EntryUrl>, Error> {
+    let mut sql_statement = p_sql_connection.prepare(
+        "SELECT name, url, mode FROM entry_urls WHERE ?1 IS NULL OR name = ?1 ORDER BY name, url;",
+    )?;
+    let rows = sql_statement
+        .query_map([p_name], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    rows.into_iter()
+        .map(|(name, url, mode)| {
+            Ok(EntryUrl {
+                name,
+                url,
+                mode: MatchMode::parse(&mode)?,
+            })
+        })
+        .collect()
+}
+
+/// Declares domains equivalent. Groups that some of them already belong to
+/// are merged into one.
+pub fn add_equivalent_domains(
+    p_domains: &[&str],
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<(), Error> {
+    if p_domains.len() < 2 {
+        return Err(Error::SyntaxError(
+            "at least two domains are needed to be equivalent".to_string(),
+        ));
+    }
+
+    let domains = p_domains
+        .iter()
+        .map(|domain| registrable_domain(domain))
+        .collect::<Vec<_>>();
+
+    let transaction = p_sql_connection.unchecked_transaction()?;
+    let group = transaction.query_row(
+        "SELECT COALESCE(MAX(grp), 0) + 1 FROM equivalent_domains;",
+        [],
+        |row| row.get::<_, i64>(0),
+    )?;
+
+    for domain in &domains {
+        transaction.execute(
+            "UPDATE equivalent_domains SET grp = ?1 WHERE grp = (SELECT grp FROM equivalent_domains WHERE domain = ?2);",
+            (group, domain),
+        )?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO equivalent_domains (domain, grp) VALUES (?, ?);",
+            (domain, group),
+        )?;
+    }
+
+    transaction.commit()?;
+    Ok(())
+}
+
+pub fn remove_equivalent_domain(
+    p_domain: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "DELETE FROM equivalent_domains WHERE domain = ?;",
+        [registrable_domain(p_domain)],
+    )?;
+
+    // A domain can't be equivalent to nothing else.
+    p_sql_connection.execute(
+        "DELETE FROM equivalent_domains WHERE grp IN (SELECT grp FROM equivalent_domains GROUP BY grp HAVING COUNT(*) < 2);",
+        (),
+    )?;
+
+    Ok(())
+}
+
+/// Every group of equivalent domains.
+pub fn equivalent_domains(
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<Vec<Vec<String>>, Error> {
+    let mut sql_statement = p_sql_connection
+        .prepare("SELECT grp, domain FROM equivalent_domains ORDER BY grp, domain;")?;
+    let rows = sql_statement
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut groups: Vec<(i64, Vec<String>)> = Vec::new();
+    for (group, domain) in rows {
+        match groups.last_mut() {
+            Some((last, domains)) if *last == group => domains.push(domain),
+            _ => groups.push((group, vec![domain])),
+        }
+    }
+
+    Ok(groups.into_iter().map(|(_, domains)| domains).collect())
+}
+
+/// The host in a domain as the user typed it, which may be a whole URL.
+fn domain_host(p_domain: &str) -> Result<String, Error> {
+    let url = if p_domain.contains("://") {
+        parse_url(p_domain)?
+    } else {
+        parse_url(&format!("https://{}", p_domain))?
+    };
+
+    url.host_str()
+        .map(|host| host.trim_end_matches('.').to_string())
+        .ok_or_else(|| Error::SyntaxError(format!("'{}' has no host", p_domain)))
+}
+
+/// The package name of an Android app, as it is stored.
+fn package_name(p_package: &str) -> Result<String, Error> {
+    if p_package.is_empty() || p_package.contains(|c: char| c == '/' || c.is_whitespace()) {
+        return Err(Error::SyntaxError(format!(
+            "'{}' is not a valid package name",
+            p_package
+        )));
+    }
+
+    Ok(p_package.to_ascii_lowercase())
+}
+
+/// Links an Android app to a domain, so that the app is offered the entries
+/// for that domain.
+pub fn add_app_link(
+    p_package: &str,
+    p_domain: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "INSERT OR REPLACE INTO app_links (package, domain) VALUES (?, ?);",
+        (package_name(p_package)?, domain_host(p_domain)?),
+    )?;
+
+    Ok(())
+}
+
+pub fn remove_app_link(
+    p_package: &str,
+    p_domain: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "DELETE FROM app_links WHERE package = ? AND domain = ?;",
+        (p_package.to_ascii_lowercase(), domain_host(p_domain)?),
+    )?;
+
+    Ok(())
+}
+
+/// The domains linked to one app, or to every app.
+pub fn app_links(
+    p_package: Option<&str>,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<Vec<AppLink>, Error> {
+    let mut sql_statement = p_sql_connection.prepare(
+        "SELECT package, domain FROM app_links WHERE ?1 IS NULL OR package = ?1 ORDER BY package, domain;",
+    )?;
+    let links = sql_statement
+        .query_map([p_package.map(str::to_ascii_lowercase)], |row| {
+            Ok(AppLink {
+                package: row.get(0)?,
+                domain: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(links)
+}
+
+/// The browsers that a new vault trusts to report the domain of the page
+/// they show.
+pub const DEFAULT_BROWSERS: &[&str] = &[
+    "com.android.chrome",
+    "com.brave.browser",
+    "com.duckduckgo.mobile.android",
+    "com.microsoft.emmx",
+    "com.opera.browser",
+    "com.sec.android.app.sbrowser",
+    "com.vivaldi.browser",
+    "org.chromium.chrome",
+    "org.mozilla.fenix",
+    "org.mozilla.firefox",
+    "org.mozilla.focus",
+];
+
+/// Trusts an Android app to report the domain of the page it shows.
+pub fn add_browser(p_package: &str, p_sql_connection: &rusqlite::Connection) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "INSERT OR IGNORE INTO browsers (package) VALUES (?);",
+        [package_name(p_package)?],
+    )?;
+
+    Ok(())
+}
+
+pub fn remove_browser(
+    p_package: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "DELETE FROM browsers WHERE package = ?;",
+        [p_package.to_ascii_lowercase()],
+    )?;
+
+    Ok(())
+}
+
+/// The package names of the browsers.
+pub fn browsers(p_sql_connection: &rusqlite::Connection) -> Result<Vec<String>, Error> {
+    let mut sql_statement =
+        p_sql_connection.prepare("SELECT package FROM browsers ORDER BY package;")?;
+    let browsers = sql_statement
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(browsers)
+}
+
+pub fn is_browser(p_package: &str, p_sql_connection: &rusqlite::Connection) -> Result<bool, Error> {
+    Ok(browsers(p_sql_connection)?.contains(&p_package.to_ascii_lowercase()))
+}
+
+/// The regular expression of an entry URL. Every page is matched against the
+/// same few expressions, so they are only compiled once. Invalid ones are
+/// remembered as `None`.
+fn compiled_regex(p_pattern: &str) -> Option<regex::Regex> {
+    static REGEXES: OnceLock<Mutex<HashMap<String, Option<regex::Regex>>>> = OnceLock::new();
+    let mut regexes = REGEXES.get_or_init(Default::default).lock().unwrap();
+    regexes
+        .entry(p_pattern.to_string())
+        .or_insert_with(|| regex::Regex::new(p_pattern).ok())
+        .clone()
+}
+
+/// How a URL of an entry matches a page, if it does.
+fn match_url(
+    p_url: &str,
+    p_mode: MatchMode,
+    p_page: &url::Url,
+    p_page_domain: &str,
+    p_equivalents: &[String],
+) -> Option<Match> {
+    match p_mode {
+        MatchMode::Regex => {
+            let regex = compiled_regex(p_url)?;
+            regex.is_match(p_page.as_str()).then_some(Match::Regex)
+        }
+        MatchMode::Prefix => p_page.as_str().starts_with(p_url).then_some(Match::Prefix),
+        MatchMode::Host => {
+            let url = url::Url::parse(p_url).ok()?;
+            (url.host_str()? == p_page.host_str()?).then_some(Match::Host)
+        }
+        MatchMode::Domain => {
+            let url = url::Url::parse(p_url).ok()?;
+
+            // Apps have no domain, only the app itself matches.
+            if url.scheme() == APP_SCHEME || p_page.scheme() == APP_SCHEME {
+                return (url.scheme() == p_page.scheme()
+                    && url.host_str()? == p_page.host_str()?)
+                .then_some(Match::Host);
+            }
+
+            let domain = registrable_domain(url.host_str()?);
+            if domain == p_page_domain {
+                Some(Match::Domain)
+            } else if p_equivalents.contains(&domain) {
+                Some(Match::EquivalentDomain)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// The entries to offer for a page, the closest matches first. An entry is
+/// only listed once, for the URL of it that matches best.
+pub fn find_entries(
+    p_page_url: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<Vec<Candidate>, Error> {
+    let page = parse_url(p_page_url)?;
+    let page_host = page
+        .host_str()
+        .ok_or_else(|| Error::SyntaxError(format!("'{}' has no host", p_page_url)))?;
+    let page_domain = registrable_domain(page_host);
+
+    let equivalents = equivalent_domains(p_sql_connection)?
+        .into_iter()
+        .find(|group| group.contains(&page_domain))
+        .unwrap_or_default();
+
+    let names = crate::list_passwords(p_sql_connection)?;
+    let urls = list_urls(None, p_sql_connection)?;
+
+    let mut candidates = Vec::new();
+    for name in names {
+        let mut entry_urls = urls
+            .iter()
+            .filter(|url| url.name == name)
+            .map(|url| (url.url.clone(), url.mode))
+            .collect::<Vec<_>>();
+
+        if entry_urls.is_empty() {
+            if let Some(url) = url_from_name(&name) {
+                entry_urls.push((url.to_string(), MatchMode::Domain));
+            }
+        }
+
+        let best = entry_urls
+            .into_iter()
+            .filter_map(|(url, mode)| {
+                match_url(&url, mode, &page, &page_domain, &equivalents)
+                    .map(|matched| (matched, url))
+            })
+            .max_by(|(a_match, a_url), (b_match, b_url)| {
+                a_match.cmp(b_match).then(a_url.len().cmp(&b_url.len()))
+            });
+
+        if let Some((matched, url)) = best {
+            candidates.push(Candidate { name, url, matched });
+        }
+    }
+
+    sort_candidates(&mut candidates);
+    Ok(candidates)
+}
+
+/// The closest matches first, and among equally close ones, the longest URL,
+/// since it is the most specific.
+fn sort_candidates(p_candidates: &mut [Candidate]) {
+    p_candidates.sort_by(|a, b| {
+        b.matched
+            .cmp(&a.matched)
+            .then(b.url.len().cmp(&a.url.len()))
+            .then(a.name.cmp(&b.name))
+    });
+}
+
+/// The entries to offer for an Android app: those recorded for the app
+/// itself, then those for the domains linked to it.
+pub fn find_app_entries(
+    p_package: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<Vec<Candidate>, Error> {
+    let mut candidates = find_entries(&app_url(p_package), p_sql_connection)?;
+
+    for link in app_links(Some(p_package), p_sql_connection)? {
+        for candidate in find_entries(&format!("https://{}/", link.domain), p_sql_connection)? {
+            match candidates
+                .iter_mut()
+                .find(|other| other.name == candidate.name)
+            {
+                Some(other) if other.matched < candidate.matched => *other = candidate,
+                Some(_) => {}
+                None => candidates.push(candidate),
+            }
+        }
+    }
+
+    sort_candidates(&mut candidates);
+    Ok(candidates)
+}
+
+/// The entries to offer when an Android app is filled. The domain of the page
+/// is only used if the app is a browser, other apps are only offered their
+/// own entries and those of the domains linked to them.
+pub fn find_autofill_entries(
+    p_package: &str,
+    p_web_domain: Option<&str>,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<Vec<Candidate>, Error> {
+    match p_web_domain {
+        Some(web_domain) if !web_domain.is_empty() && is_browser(p_package, p_sql_connection)? => {
+            find_entries(&format!("https://{}/", web_domain), p_sql_connection)
+        }
+        _ => find_app_entries(p_package, p_sql_connection),
+    }
+}
+
+/// Saves a login that was just used on a page or in an app, replacing the
+/// entry with the same site and user name. The entry is named like
+/// `octocat@github.com`, and the URL is recorded for it unless it already has
+/// some. Returns the name of the entry.
+pub fn save_login(
+    p_master_key: &crate::MasterKey,
+    p_url: &str,
+    p_username: Option<&str>,
+    p_password: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<String, Error> {
+    let url = parse_url(p_url)?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| Error::SyntaxError(format!("'{}' has no host", p_url)))?
+        .to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    let name = match p_username {
+        Some(username) if !username.is_empty() => format!("{}@{}", username, host),
+        _ => host.to_string(),
+    };
+
+    let (url, mode) = if url.scheme() == APP_SCHEME {
+        (app_url(host), MatchMode::Host)
+    } else {
+        (url.origin().ascii_serialization(), MatchMode::Domain)
+    };
+
+    // URLs recorded for the entry before, with their match modes, are kept.
+    let transaction = p_sql_connection.unchecked_transaction()?;
+    crate::delete_password(&name, &transaction)?;
+    crate::create_password_with_key(p_master_key, &name, p_password, &transaction)?;
+    if list_urls(Some(&name), &transaction)?.is_empty() {
+        add_url(&name, &url, mode, &transaction)?;
+    }
+    transaction.commit()?;
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault(p_names: &[&str]) -> rusqlite::Connection {
+        let sql_connection = rusqlite::Connection::open_in_memory().unwrap();
+        crate::prepare_database(&sql_connection).unwrap();
+        for name in p_names {
+            crate::create_password("hunter2".to_string(), name, "hunter3", &sql_connection)
+                .unwrap();
+        }
+        sql_connection
+    }
+
+    fn found(p_page_url: &str, p_sql_connection: &rusqlite::Connection) -> Vec<(String, Match)> {
+        find_entries(p_page_url, p_sql_connection)
+            .unwrap()
+            .into_iter()
+            .map(|candidate| (candidate.name, candidate.matched))
+            .collect()
+    }
+
+    #[test]
+    fn closest_matches_come_first() {
+        let vault = vault(&["domain", "host", "prefix", "regex", "other"]);
+        add_url("domain", "https://example.com/", MatchMode::Domain, &vault).unwrap();
+        add_url(
+            "host",
+            "https://login.example.com/",
+            MatchMode::Host,
+            &vault,
+        )
+        .unwrap();
+        add_url(
+            "prefix",
+            "https://login.example.com/sso",
+            MatchMode::Prefix,
+            &vault,
+        )
+        .unwrap();
+        add_url(
+            "regex",
+            r"^https://[a-z]+\.example\.com/",
+            MatchMode::Regex,
+            &vault,
+        )
+        .unwrap();
+        add_url("other", "https://example.org/", MatchMode::Domain, &vault).unwrap();
+
+        assert_eq!(
+            found("https://login.example.com/sso/start", &vault),
+            [
+                ("prefix".to_string(), Match::Prefix),
+                ("host".to_string(), Match::Host),
+                ("regex".to_string(), Match::Regex),
+                ("domain".to_string(), Match::Domain),
+            ]
+        );
+
+        // The same expressions keep matching once they are compiled.
+        assert_eq!(
+            found("https://www.example.com/", &vault),
+            [
+                ("regex".to_string(), Match::Regex),
+                ("domain".to_string(), Match::Domain),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_browsers_are_believed() {
+        let vault = vault(&["bank.com", "octocat@github.com"]);
+        add_url(
+            "octocat@github.com",
+            &app_url("com.github.android"),
+            MatchMode::Domain,
+            &vault,
+        )
+        .unwrap();
+
+        let names = |p_package: &str, p_web_domain: Option<&str>| {
+            find_autofill_entries(p_package, p_web_domain, &vault)
+                .unwrap()
+                .into_iter()
+                .map(|candidate| candidate.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names("com.android.chrome", Some("www.bank.com")),
+            ["bank.com"]
+        );
+        assert_eq!(
+            names("com.github.android", Some("www.bank.com")),
+            ["octocat@github.com"]
+        );
+        assert!(names("com.example.game", Some("bank.com")).is_empty());
+
+        remove_browser("com.android.chrome", &vault).unwrap();
+        assert!(names("com.android.chrome", Some("bank.com")).is_empty());
+
+        add_browser("com.example.Browser", &vault).unwrap();
+        assert!(is_browser("com.example.browser", &vault).unwrap());
+        assert_eq!(names("com.example.browser", Some("bank.com")), ["bank.com"]);
+        assert!(add_browser("not a package", &vault).is_err());
+
+        // The browsers that were removed stay removed.
+        crate::prepare_database(&vault).unwrap();
+        assert!(!is_browser("com.android.chrome", &vault).unwrap());
+    }
+
+    #[test]
+    fn public_suffixes_are_not_shared() {
+        assert_eq!(registrable_domain("www.example.co.uk"), "example.co.uk");
+        assert_eq!(registrable_domain("Mail.Google.COM."), "google.com");
+        assert_eq!(registrable_domain("192.168.1.1"), "192.168.1.1");
+        assert_eq!(registrable_domain("::1"), "::1");
+
+        let vault = vault(&["example.co.uk", "other.co.uk", "192.168.1.1", "192.168.1.2"]);
+        assert_eq!(
+            found("https://login.example.co.uk/", &vault),
+            [("example.co.uk".to_string(), Match::Domain)]
+        );
+        assert!(found("https://co.uk/", &vault).is_empty());
+
+        // IP addresses only match themselves, not their neighbours.
+        assert_eq!(
+            found("http://192.168.1.1:8080/", &vault),
+            [("192.168.1.1".to_string(), Match::Domain)]
+        );
+    }
+
+    #[test]
+    fn equivalent_domains_match_each_other() {
+        let vault = vault(&["octocat@google.com", "youtube.com", "example.com"]);
+        add_equivalent_domains(&["google.com", "www.youtube.com"], &vault).unwrap();
+
+        assert_eq!(
+            found("https://www.youtube.com/watch", &vault),
+            [
+                ("youtube.com".to_string(), Match::Domain),
+                ("octocat@google.com".to_string(), Match::EquivalentDomain),
+            ]
+        );
+
+        remove_equivalent_domain("youtube.com", &vault).unwrap();
+        assert!(equivalent_domains(&vault).unwrap().is_empty());
+        assert_eq!(
+            found("https://www.youtube.com/watch", &vault),
+            [("youtube.com".to_string(), Match::Domain)]
+        );
+    }
+}
@@ -0,0 +1,683 @@
+//! The configuration file, `config.toml` in the configuration directory of the
+//! user. It names vaults, so that they can be picked by their name, and holds
+//! settings, which any vault can override:
+//!
+//! ```toml
+//! default = "personal"
+//! format = "text"
+//! backups = 3
+//!
+//! [generator]
+//! length = 16
+//! symbols = false
+//!
+//! [vaults.work]
+//! path = "~/Sync/work.nengvault"
+//! lock_timeout = 300
+//!
+//! [vaults.personal]
+//! path = "~/Documents/passwords"
+//! ```
+//!
+//! Paths starting with `~` are in the home directory, and other relative
+//! paths are relative to the configuration file. The vault named `default` is
+//! the one in `data_dir`, unless the file says otherwise.
+//!
+//! The file is checked when it is read, and errors name the key that is
+//! wrong, like `vaults.work.generator.length`.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use directories::{BaseDirs, ProjectDirs};
+use toml::{value::Table, Value};
+
+use crate::{vault::Location, Error, GeneratorOptions, MAX_PASSWORD_LEN};
+
+pub const DEFAULT_VAULT: &str = "default";
+
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("io", "earthtraveller1", "neng-pass")
+}
+
+/// Where the default vault is, unless the configuration says otherwise.
+pub fn default_data_dir() -> PathBuf {
+    match project_dirs() {
+        Some(project_dirs) => project_dirs.data_dir().to_owned(),
+        None => PathBuf::from("."),
+    }
+}
+
+pub fn config_file() -> PathBuf {
+    match project_dirs() {
+        Some(project_dirs) => project_dirs.config_dir().join("config.toml"),
+        None => PathBuf::from("config.toml"),
+    }
+}
+
+/// The values a setting accepts.
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Bool,
+    /// A whole number between these two, included.
+    Integer(i64, i64),
+    /// One of these strings.
+    Choice(&'static [&'static str]),
+}
+
+impl Kind {
+    fn check(&self, p_value: &Value) -> Result<(), String> {
+        match (self, p_value) {
+            (Kind::Bool, Value::Boolean(_)) => Ok(()),
+            (Kind::Integer(min, max), Value::Integer(value)) if (min..=max).contains(&value) => {
+                Ok(())
+            }
+            (Kind::Choice(choices), Value::String(value)) if choices.contains(&value.as_str()) => {
+                Ok(())
+            }
+            _ => Err(format!("expected {}, not {}", self.describe(), p_value)),
+        }
+    }
+
+    /// Reads a value typed on the command line.
+    fn parse(&self, p_value: &str) -> Result<Value, String> {
+        let value = match self {
+            Kind::Bool => p_value.parse().map(Value::Boolean).ok(),
+            Kind::Integer(..) => p_value.parse().map(Value::Integer).ok(),
+            Kind::Choice(_) => Some(Value::String(p_value.to_string())),
+        };
+
+        let value =
+            value.ok_or_else(|| format!("expected {}, not {}", self.describe(), p_value))?;
+        self.check(&value)?;
+        Ok(value)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Kind::Bool => "true or false".to_string(),
+            Kind::Integer(min, i64::MAX) => format!("a whole number from {}", min),
+            Kind::Integer(min, max) => format!("a whole number from {} to {}", min, max),
+            Kind::Choice(choices) => format!("one of {}", choices.join(", ")),
+        }
+    }
+}
+
+/// A setting that can be set for every vault, and overridden for one.
+struct Setting {
+    key: &'static str,
+    kind: Kind,
+    /// The value when it isn't set, as it would be typed on the command line.
+    default: &'static str,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "format",
+        kind: Kind::Choice(&["text", "json"]),
+        default: "text",
+    },
+    Setting {
+        key: "generator.length",
+        kind: Kind::Integer(1, MAX_PASSWORD_LEN as i64),
+        default: "16",
+    },
+    Setting {
+        key: "generator.lowercase",
+        kind: Kind::Bool,
+        default: "true",
+    },
+    Setting {
+        key: "generator.uppercase",
+        kind: Kind::Bool,
+        default: "true",
+    },
+    Setting {
+        key: "generator.digits",
+        kind: Kind::Bool,
+        default: "true",
+    },
+    Setting {
+        key: "generator.symbols",
+        kind: Kind::Bool,
+        default: "true",
+    },
+    // In seconds.
+    Setting {
+        key: "lock_timeout",
+        kind: Kind::Integer(1, i64::MAX),
+        default: "900",
+    },
+    // In seconds, 0 to leave the clipboard alone.
+    Setting {
+        key: "clipboard_clear_delay",
+        kind: Kind::Integer(0, i64::MAX),
+        default: "30",
+    },
+    Setting {
+        key: "backups",
+        kind: Kind::Integer(0, 1000),
+        default: "0",
+    },
+];
+
+fn find_setting(p_key: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|setting| setting.key == p_key)
+}
+
+/// The settings of a vault, with the defaults filled in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// `text` or `json`.
+    pub format: String,
+    pub generator: GeneratorOptions,
+    /// How long the agent keeps the master key without it being used.
+    pub lock_timeout: Duration,
+    /// How long a copied password stays in the clipboard, zero for as long as
+    /// nothing else is copied.
+    pub clipboard_clear_delay: Duration,
+    /// How many previous versions of a single-file vault are kept.
+    pub backups: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Config::default().settings(None)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    file: PathBuf,
+    table: Table,
+}
+
+impl Config {
+    /// Reads a configuration file. A file that doesn't exist is an empty
+    /// configuration.
+    pub fn load(p_file: &Path) -> Result<Config, Error> {
+        let content = match std::fs::read_to_string(p_file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let invalid = |err: String| Error::ConfigError(format!("{}: {}", p_file.display(), err));
+
+        let table: Table = toml::from_str(&content).map_err(|err| invalid(err.to_string()))?;
+        let config = Config {
+            file: p_file.to_owned(),
+            table,
+        };
+        config.validate().map_err(invalid)?;
+
+        Ok(config)
+    }
+
+    /// Where the configuration was read from, and is written to.
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(parent) = self.file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let content =
+            toml::to_string(&self.table).map_err(|err| Error::ConfigError(err.to_string()))?;
+        crate::write_private_file(&self.file, content.as_bytes())?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (key, value) in &self.table {
+            match key.as_str() {
+                "default" | "data_dir" => {
+                    if !value.is_str() {
+                        return Err(format!("{}: expected a string, not {}", key, value));
+                    }
+                }
+                "vaults" => {
+                    let vaults = value
+                        .as_table()
+                        .ok_or_else(|| format!("vaults: expected a table, not {}", value))?;
+
+                    for (name, vault) in vaults {
+                        // Keys like vaults.NAME.path are split at the dots.
+                        if name.contains('.') {
+                            return Err(format!("vaults.{}: vault names can't contain dots", name));
+                        }
+
+                        let vault = vault.as_table().ok_or_else(|| {
+                            format!("vaults.{}: expected a table, not {}", name, vault)
+                        })?;
+
+                        if name != DEFAULT_VAULT && !vault.contains_key("path") {
+                            return Err(format!("vaults.{}.path: missing", name));
+                        }
+
+                        for (key, value) in vault {
+                            let prefix = format!("vaults.{}.", name);
+                            match key.as_str() {
+                                "path" if !value.is_str() => {
+                                    return Err(format!(
+                                        "{}path: expected a string, not {}",
+                                        prefix, value
+                                    ));
+                                }
+                                "path" => {}
+                                _ => validate_setting(&prefix, key, value)?,
+                            }
+                        }
+                    }
+                }
+                _ => validate_setting("", key, value)?,
+            }
+        }
+
+        let default = self.default_vault();
+        if !self.vaults().contains_key(default) {
+            return Err(format!("default: there is no vault named {}", default));
+        }
+
+        Ok(())
+    }
+
+    fn expand_path(&self, p_path: &str) -> PathBuf {
+        if let Ok(rest) = Path::new(p_path).strip_prefix("~") {
+            if let Some(base_dirs) = BaseDirs::new() {
+                return base_dirs.home_dir().join(rest);
+            }
+        }
+
+        self.file.parent().unwrap_or(Path::new(".")).join(p_path)
+    }
+
+    /// Where the default vault is.
+    pub fn data_dir(&self) -> PathBuf {
+        match self.table.get("data_dir").and_then(Value::as_str) {
+            Some(data_dir) => self.expand_path(data_dir),
+            None => default_data_dir(),
+        }
+    }
+
+    /// Every vault that can be picked by its name, the default one included.
+    pub fn vaults(&self) -> BTreeMap<String, Location> {
+        let mut vaults: BTreeMap<_, _> = self
+            .table
+            .get("vaults")
+            .and_then(Value::as_table)
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, vault)| {
+                let path = vault.get("path").and_then(Value::as_str)?;
+                Some((name.clone(), Location::from_path(self.expand_path(path))))
+            })
+            .collect();
+
+        vaults
+            .entry(DEFAULT_VAULT.to_string())
+            .or_insert_with(|| Location::Directory(self.data_dir()));
+
+        vaults
+    }
+
+    /// The name of the vault used when none is picked.
+    pub fn default_vault(&self) -> &str {
+        self.table
+            .get("default")
+            .and_then(Value::as_str)
+            .unwrap_or(DEFAULT_VAULT)
+    }
+
+    /// Finds a vault by its name, or by its path when it isn't the name of
+    /// one. Without either, the default vault is used.
+    pub fn resolve(&self, p_vault: Option<&str>) -> Result<Location, Error> {
+        let vault = p_vault.unwrap_or(self.default_vault());
+
+        if let Some(location) = self.vaults().remove(vault) {
+            return Ok(location);
+        }
+
+        let path = Path::new(vault);
+        if path.components().count() > 1 || path.exists() || crate::vault_file::is_vault_file(path)
+        {
+            return Ok(Location::from_path(path));
+        }
+
+        Err(Error::VaultDoesntExist(vault.into()))
+    }
+
+    /// Like `resolve`, for a vault that is about to be created: a name that
+    /// isn't the name of a vault is a directory of that name in the current
+    /// directory.
+    pub fn resolve_new(&self, p_vault: Option<&str>) -> Location {
+        match (self.resolve(p_vault), p_vault) {
+            (Ok(location), _) => location,
+            (Err(_), Some(vault)) => Location::from_path(vault),
+            (Err(_), None) => Location::Directory(self.data_dir()),
+        }
+    }
+
+    /// The value of a setting for a vault: the one set for that vault, or for
+    /// every vault, or the default one.
+    fn setting(&self, p_vault: Option<&str>, p_setting: &Setting) -> Value {
+        let vault = p_vault.unwrap_or(self.default_vault());
+        let overridden = self
+            .table
+            .get("vaults")
+            .and_then(|vaults| vaults.get(vault))
+            .and_then(|vault| lookup(vault, p_setting.key));
+
+        overridden
+            .or_else(|| lookup_table(&self.table, p_setting.key))
+            .cloned()
+            .unwrap_or_else(|| {
+                p_setting
+                    .kind
+                    .parse(p_setting.default)
+                    .expect("defaults are valid")
+            })
+    }
+
+    /// The settings of a vault, picked by its name. Vaults picked by their
+    /// path only have the settings of every vault.
+    pub fn settings(&self, p_vault: Option<&str>) -> Settings {
+        let value = |key: &str| self.setting(p_vault, find_setting(key).expect("known setting"));
+        let integer = |key: &str| value(key).as_integer().expect("validated") as u64;
+        let boolean = |key: &str| value(key).as_bool().expect("validated");
+
+        Settings {
+            format: value("format").as_str().expect("validated").to_string(),
+            generator: GeneratorOptions {
+                length: integer("generator.length") as usize,
+                lowercase: boolean("generator.lowercase"),
+                uppercase: boolean("generator.uppercase"),
+                digits: boolean("generator.digits"),
+                symbols: boolean("generator.symbols"),
+            },
+            lock_timeout: Duration::from_secs(integer("lock_timeout")),
+            clipboard_clear_delay: Duration::from_secs(integer("clipboard_clear_delay")),
+            backups: integer("backups") as usize,
+        }
+    }
+
+    /// The value of a key as it applies, defaults included: `default`,
+    /// `data_dir`, a setting, or `vaults.NAME.path` and `vaults.NAME.SETTING`.
+    pub fn get(&self, p_key: &str) -> Result<Value, Error> {
+        let unknown = || Error::ConfigError(format!("{}: unknown setting", p_key));
+
+        match p_key {
+            "default" => return Ok(Value::String(self.default_vault().to_string())),
+            "data_dir" => return Ok(Value::String(self.data_dir().display().to_string())),
+            _ => {}
+        }
+
+        if let Some((vault, key)) = split_vault_key(p_key) {
+            let location = self
+                .vaults()
+                .remove(vault)
+                .ok_or_else(|| Error::VaultDoesntExist(vault.into()))?;
+
+            return match key {
+                "path" => Ok(Value::String(location.path().display().to_string())),
+                _ => Ok(self.setting(Some(vault), find_setting(key).ok_or_else(unknown)?)),
+            };
+        }
+
+        Ok(self.setting(None, find_setting(p_key).ok_or_else(unknown)?))
+    }
+
+    /// Sets a key, after checking the value. The configuration isn't saved.
+    pub fn set(&mut self, p_key: &str, p_value: &str) -> Result<(), Error> {
+        let invalid = |err: String| Error::ConfigError(format!("{}: {}", p_key, err));
+
+        let value = match p_key {
+            "default" => {
+                if !self.vaults().contains_key(p_value) {
+                    return Err(invalid(format!("there is no vault named {}", p_value)));
+                }
+                Value::String(p_value.to_string())
+            }
+            "data_dir" => Value::String(p_value.to_string()),
+            _ => {
+                let key = match split_vault_key(p_key) {
+                    Some((_, "path")) => None,
+                    Some((vault, key)) if self.vaults().contains_key(vault) => Some(key),
+                    Some((vault, _)) => return Err(Error::VaultDoesntExist(vault.into())),
+                    None => Some(p_key),
+                };
+
+                match key {
+                    Some(key) => {
+                        let setting = find_setting(key)
+                            .ok_or_else(|| invalid("unknown setting".to_string()))?;
+                        setting.kind.parse(p_value).map_err(invalid)?
+                    }
+                    None => Value::String(p_value.to_string()),
+                }
+            }
+        };
+
+        let path: Vec<&str> = match split_vault_key(p_key) {
+            Some((vault, key)) => ["vaults", vault]
+                .into_iter()
+                .chain(key.split('.'))
+                .collect(),
+            None => p_key.split('.').collect(),
+        };
+        let (last, tables) = path.split_last().expect("keys aren't empty");
+
+        // The tables on the way, like [vaults.work] or [generator], are
+        // created if needed.
+        let mut table = &mut self.table;
+        for part in tables {
+            table = table
+                .entry(part.to_string())
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| invalid(format!("{} is not a table", part)))?;
+        }
+        table.insert(last.to_string(), value);
+
+        self.validate().map_err(Error::ConfigError)
+    }
+
+    /// Every key that applies to a vault, with its value: where the vaults
+    /// are, and the settings of that vault.
+    pub fn list(&self, p_vault: Option<&str>) -> Vec<(String, Value)> {
+        let mut keys = vec![
+            (
+                "default".to_string(),
+                Value::String(self.default_vault().to_string()),
+            ),
+            (
+                "data_dir".to_string(),
+                Value::String(self.data_dir().display().to_string()),
+            ),
+        ];
+
+        keys.extend(
+            SETTINGS
+                .iter()
+                .map(|setting| (setting.key.to_string(), self.setting(p_vault, setting))),
+        );
+
+        keys.extend(self.vaults().into_iter().map(|(name, location)| {
+            (
+                format!("vaults.{}.path", name),
+                Value::String(location.path().display().to_string()),
+            )
+        }));
+
+        keys
+    }
+}
+
+/// Splits `vaults.NAME.KEY` into the name and the key.
+fn split_vault_key(p_key: &str) -> Option<(&str, &str)> {
+    p_key.strip_prefix("vaults.")?.split_once('.')
+}
+
+/// Checks a setting, which may be a table of settings, like `[generator]`.
+fn validate_setting(p_prefix: &str, p_key: &str, p_value: &Value) -> Result<(), String> {
+    if let Value::Table(table) = p_value {
+        let section = format!("{}.", p_key);
+        if SETTINGS
+            .iter()
+            .any(|setting| setting.key.starts_with(&section))
+        {
+            for (key, value) in table {
+                validate_setting(p_prefix, &format!("{}{}", section, key), value)?;
+            }
+            return Ok(());
+        }
+    }
+
+    let setting =
+        find_setting(p_key).ok_or_else(|| format!("{}{}: unknown setting", p_prefix, p_key))?;
+    setting
+        .kind
+        .check(p_value)
+        .map_err(|err| format!("{}{}: {}", p_prefix, p_key, err))
+}
+
+/// Finds a dotted key, like `generator.length`, in a table.
+fn lookup_table<'a>(p_table: &'a Table, p_key: &str) -> Option<&'a Value> {
+    let (first, rest) = match p_key.split_once('.') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (p_key, None),
+    };
+
+    let value = p_table.get(first)?;
+    match rest {
+        Some(rest) => lookup(value, rest),
+        None => Some(value),
+    }
+}
+
+fn lookup<'a>(p_value: &'a Value, p_key: &str) -> Option<&'a Value> {
+    lookup_table(p_value.as_table()?, p_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_vaults_can_be_named() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("config.toml");
+        std::fs::write(&file, "[vaults.work]\npath = \"work\"\n").unwrap();
+        let config = Config::load(&file).unwrap();
+
+        assert_eq!(
+            config.resolve(Some("work")).unwrap(),
+            Location::Directory(dir.path().join("work"))
+        );
+        assert!(matches!(
+            config.resolve(Some("newvault")),
+            Err(Error::VaultDoesntExist(_))
+        ));
+        assert_eq!(
+            config.resolve_new(Some("newvault")),
+            Location::Directory(PathBuf::from("newvault"))
+        );
+        assert_eq!(
+            config.resolve_new(Some("work")),
+            Location::Directory(dir.path().join("work"))
+        );
+    }
+
+    fn load(p_dir: &Path, p_content: &str) -> Result<Config, Error> {
+        let file = p_dir.join("config.toml");
+        std::fs::write(&file, p_content).unwrap();
+        Config::load(&file)
+    }
+
+    fn message(p_result: Result<Config, Error>) -> String {
+        match p_result {
+            Err(Error::ConfigError(message)) => message,
+            other => panic!(
+                "expected a configuration error, got {:?}",
+                other.map(|_| ())
+            ),
+        }
+    }
+
+    #[test]
+    fn errors_point_to_the_key() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(message(load(dir.path(), "lock_timeout = \"soon\"\n"))
+            .contains("lock_timeout: expected "));
+        assert!(
+            message(load(dir.path(), "[generator]\nlength = 40\n")).contains("generator.length:")
+        );
+        assert!(
+            message(load(dir.path(), "[vaults.work]\nlock_timeout = 60\n"))
+                .ends_with("vaults.work.path: missing")
+        );
+        assert!(message(load(
+            dir.path(),
+            "[vaults.work]\npath = \"work\"\ncolour = 1\n"
+        ))
+        .ends_with("vaults.work.colour: unknown setting"));
+        assert!(message(load(dir.path(), "default = \"work\"\n"))
+            .ends_with("default: there is no vault named work"));
+        assert!(
+            message(load(dir.path(), "[vaults.\"my.vault\"]\npath = \"work\"\n"))
+                .ends_with("vaults.my.vault: vault names can't contain dots")
+        );
+    }
+
+    #[test]
+    fn vaults_override_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = load(dir.path(), "").unwrap();
+        assert_eq!(config.get("generator.length").unwrap(), Value::Integer(16));
+
+        config.set("generator.length", "12").unwrap();
+        config.set("vaults.work.path", "work").unwrap();
+        config.set("vaults.work.generator.length", "8").unwrap();
+        config.set("default", "work").unwrap();
+
+        assert_eq!(config.get("generator.length").unwrap(), Value::Integer(8));
+        assert_eq!(
+            config.get("vaults.default.generator.length").unwrap(),
+            Value::Integer(12)
+        );
+        assert_eq!(
+            config.get("vaults.work.generator.length").unwrap(),
+            Value::Integer(8)
+        );
+        assert_eq!(
+            config.get("vaults.work.lock_timeout").unwrap(),
+            Value::Integer(900)
+        );
+        assert_eq!(config.settings(None).generator.length, 8);
+        assert_eq!(config.settings(Some("default")).generator.length, 12);
+
+        assert!(config.set("generator.length", "40").is_err());
+        assert!(config.set("default", "home").is_err());
+        assert!(matches!(
+            config.set("vaults.home.lock_timeout", "60"),
+            Err(Error::VaultDoesntExist(_))
+        ));
+        assert!(matches!(
+            config.get("vaults.home.path"),
+            Err(Error::VaultDoesntExist(_))
+        ));
+        assert!(matches!(config.get("colour"), Err(Error::ConfigError(_))));
+
+        config.save().unwrap();
+        let saved = Config::load(config.file()).unwrap();
+        assert_eq!(
+            saved.get("vaults.work.generator.length").unwrap(),
+            Value::Integer(8)
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Git's credential helper protocol, so that git can take its passwords and
+//! tokens from the vault.
+//!
+//! Git writes `key=value` lines describing the credential it needs, and
+//! expects the same format back. Every credential is a regular entry, named
+//! after what it is for: `git:https://user@example.com/path`. Tokens are
+//! usually longer than `MAX_PASSWORD_LEN`, so these entries are sealed with
+//! the master key instead of being encrypted like the other passwords.
+
+use std::io::{BufRead, Write};
+
+use crate::{crypto, entry::Entry, pad_master_key, storage::Storage, Error};
+
+/// The prefix of the names of the entries made by the credential helper.
+pub const ENTRY_PREFIX: &str = "git:";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Credential {
+    pub protocol: Option<String>,
+    pub host: Option<String>,
+    pub path: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Credential {
+    /// Reads a credential description, up to an empty line or the end of the
+    /// input. Attributes that the vault doesn't store are ignored.
+    pub fn read(p_input: impl BufRead) -> Result<Credential, Error> {
+        let mut credential = Credential::default();
+
+        for line in p_input.lines() {
+            let line = line?;
+            if line.is_empty() {
+                break;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                Error::SyntaxError(format!("'{}' is not a credential attribute", line))
+            })?;
+            let value = Some(value.to_string());
+
+            match key {
+                "protocol" => credential.protocol = value,
+                "host" => credential.host = value,
+                "path" => credential.path = value,
+                "username" => credential.username = value,
+                "password" => credential.password = value,
+                _ => {}
+            }
+        }
+
+        Ok(credential)
+    }
+
+    /// Writes the credential in the format git expects.
+    pub fn write(&self, mut p_output: impl Write) -> Result<(), Error> {
+        let attributes = [
+            ("protocol", &self.protocol),
+            ("host", &self.host),
+            ("path", &self.path),
+            ("username", &self.username),
+            ("password", &self.password),
+        ];
+
+        for (key, value) in attributes {
+            if let Some(value) = value {
+                writeln!(p_output, "{}={}", key, value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The name of the entry for this credential, with or without the user
+    /// name.
+    fn entry_name(&self, p_username: Option<&str>) -> Result<String, Error> {
+        let (protocol, host) = match (&self.protocol, &self.host) {
+            (Some(protocol), Some(host)) => (protocol, host),
+            _ => {
+                return Err(Error::SyntaxError(
+                    "git didn't say which protocol and host the credential is for".to_string(),
+                ))
+            }
+        };
+
+        let mut name = format!("{}{}://", ENTRY_PREFIX, protocol);
+        if let Some(username) = p_username {
+            name.push_str(&encode_username(username));
+            name.push('@');
+        }
+        name.push_str(host);
+        if let Some(path) = &self.path {
+            name.push('/');
+            name.push_str(path);
+        }
+
+        Ok(name)
+    }
+}
+
+/// User names can be e-mail addresses, so the characters that separate the
+/// parts of an entry name are escaped.
+fn encode_username(p_username: &str) -> String {
+    p_username
+        .replace('%', "%25")
+        .replace('@', "%40")
+        .replace('/', "%2F")
+}
+
+fn decode_username(p_username: &str) -> String {
+    p_username
+        .replace("%2F", "/")
+        .replace("%40", "@")
+        .replace("%25", "%")
+}
+
+/// Splits the user name out of an entry name, if the rest of it matches
+/// `p_name_without_user`.
+fn username_of(p_entry_name: &str, p_name_without_user: &str) -> Option<String> {
+    let (scheme, rest) = p_entry_name.split_once("://")?;
+    let (username, location) = rest.split_once('@')?;
+    (format!("{}://{}", scheme, location) == p_name_without_user).then(|| decode_username(username))
+}
+
+/// Finds the stored credential that matches what git asked for. When git
+/// doesn't give a user name, a credential stored without one is preferred,
+/// then any credential for the same location.
+pub fn get(
+    p_master_key: &str,
+    p_credential: &Credential,
+    p_storage: &dyn Storage,
+) -> Result<Option<Credential>, Error> {
+    let names = crate::list_passwords(p_storage)?;
+
+    let found = match &p_credential.username {
+        Some(username) => {
+            let name = p_credential.entry_name(Some(username))?;
+            names.contains(&name).then(|| (name, Some(username.clone())))
+        }
+        None => {
+            let name = p_credential.entry_name(None)?;
+            if names.contains(&name) {
+                Some((name, None))
+            } else {
+                names.iter().find_map(|entry_name| {
+                    username_of(entry_name, &name).map(|username| (entry_name.clone(), Some(username)))
+                })
+            }
+        }
+    };
+
+    let (name, username) = match found {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+
+    let password = crate::get_password(p_master_key.to_string(), &name, p_storage)?;
+
+    Ok(Some(Credential {
+        username,
+        password: Some(password.trim_end_matches('\0').to_string()),
+        ..p_credential.clone()
+    }))
+}
+
+/// Stores a credential that worked, replacing the one stored for the same
+/// location and user name, if any.
+pub fn store(
+    p_master_key: &str,
+    p_credential: &Credential,
+    p_storage: &dyn Storage,
+) -> Result<(), Error> {
+    let password = match &p_credential.password {
+        Some(password) => password,
+        None => return Ok(()),
+    };
+
+    let name = p_credential.entry_name(p_credential.username.as_deref())?;
+    let key = pad_master_key(p_master_key.to_string());
+    p_storage.put(&Entry {
+        name,
+        password: crypto::seal(key.as_bytes(), password.as_bytes()),
+        modified: crate::current_timestamp(),
+        deleted: false,
+    })
+}
+
+/// Removes a credential that git found to be wrong. The master key is checked
+/// against the stored credential, so that it can't be erased without it.
+pub fn erase(
+    p_master_key: &str,
+    p_credential: &Credential,
+    p_storage: &dyn Storage,
+) -> Result<(), Error> {
+    let name = p_credential.entry_name(p_credential.username.as_deref())?;
+    if !crate::list_passwords(p_storage)?.contains(&name) {
+        return Ok(());
+    }
+
+    crate::get_password(p_master_key.to_string(), &name, p_storage)?;
+    crate::delete_password(&name, p_storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault() -> rusqlite::Connection {
+        let sql_connection = rusqlite::Connection::open_in_memory().unwrap();
+        crate::prepare_database(&sql_connection).unwrap();
+        sql_connection
+    }
+
+    fn github(p_password: Option<&str>) -> Credential {
+        Credential {
+            protocol: Some("https".to_string()),
+            host: Some("github.com".to_string()),
+            username: Some("someone@example.com".to_string()),
+            password: p_password.map(str::to_string),
+            ..Credential::default()
+        }
+    }
+
+    #[test]
+    fn stores_long_tokens() {
+        let vault = vault();
+        let token = "ghp_".to_string() + &"x".repeat(36);
+        store("hunter2", &github(Some(&token)), &vault).unwrap();
+
+        let asked = Credential {
+            username: None,
+            ..github(None)
+        };
+        let found = get("hunter2", &asked, &vault).unwrap().unwrap();
+        assert_eq!(found.username.as_deref(), Some("someone@example.com"));
+        assert_eq!(found.password.as_deref(), Some(token.as_str()));
+        assert_eq!(
+            crate::list_passwords(&vault).unwrap(),
+            ["git:https://someone%40example.com@github.com"]
+        );
+
+        // Storing it again replaces it.
+        store("hunter2", &github(Some("new token")), &vault).unwrap();
+        let found = get("hunter2", &github(None), &vault).unwrap().unwrap();
+        assert_eq!(found.password.as_deref(), Some("new token"));
+    }
+
+    #[test]
+    fn erasing_needs_the_master_key() {
+        let vault = vault();
+        store("hunter2", &github(Some("token")), &vault).unwrap();
+
+        assert!(matches!(
+            erase("not hunter2", &github(None), &vault),
+            Err(Error::WrongKeyForData)
+        ));
+        assert_eq!(crate::list_passwords(&vault).unwrap().len(), 1);
+
+        erase("hunter2", &github(None), &vault).unwrap();
+        assert!(get("hunter2", &github(None), &vault).unwrap().is_none());
+    }
+}
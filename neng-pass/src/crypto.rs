@@ -1,9 +1,15 @@
 use aes::cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit};
 use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
-use rand::SeedableRng;
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use hmac::{Hmac, Mac};
+use rand::{RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
+use sha2::Sha256;
 
-use crate::{MAX_MASTER_KEY_LEN, MAX_PASSWORD_LEN};
+use crate::{Error, MAX_MASTER_KEY_LEN, MAX_PASSWORD_LEN};
 
 pub fn hash(p_input: &str) -> argon2::password_hash::Result<Vec<u8>> {
     let mut cha_cha_rng = ChaCha20Rng::from_entropy();
@@ -47,3 +53,201 @@ pub fn decrypt(p_key: &[u8], p_data: &[u8]) -> [u8; MAX_PASSWORD_LEN] {
 
     data
 }
+
+/// The length of the random salt used to derive a key from a passphrase.
+pub const SALT_LEN: usize = 16;
+
+/// How much work deriving a key from a passphrase takes. They are stored next
+/// to the data the key encrypts, so that they can be raised later without
+/// breaking old data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// The memory used, in KiB.
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> KdfParams {
+        KdfParams {
+            memory: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    /// The most memory a key derivation may use, in KiB. Parameters are often
+    /// read from files or servers that could have been tampered with, and
+    /// keys are also derived in browsers.
+    pub const MAX_MEMORY: u32 = 1024 * 1024;
+    pub const MAX_ITERATIONS: u32 = 64;
+    pub const MAX_PARALLELISM: u32 = 16;
+
+    /// Whether Argon2 accepts these parameters, and deriving a key with them
+    /// won't take an unreasonable amount of time or memory.
+    pub fn is_acceptable(&self) -> bool {
+        self.memory <= KdfParams::MAX_MEMORY
+            && self.iterations <= KdfParams::MAX_ITERATIONS
+            && self.parallelism <= KdfParams::MAX_PARALLELISM
+            && argon2::Params::new(self.memory, self.iterations, self.parallelism, None).is_ok()
+    }
+}
+
+pub fn random_salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut salt);
+    salt
+}
+
+/// Derives a key for `seal` and `open` from a passphrase, with Argon2id.
+pub fn derive_key(
+    p_passphrase: &str,
+    p_salt: &[u8],
+    p_params: KdfParams,
+) -> Result<[u8; MAX_MASTER_KEY_LEN], Error> {
+    let params = argon2::Params::new(
+        p_params.memory,
+        p_params.iterations,
+        p_params.parallelism,
+        Some(MAX_MASTER_KEY_LEN),
+    )
+    .map_err(|err| Error::EncryptionError(format!("Invalid key derivation parameters: {}", err)))?;
+
+    let mut key = [0u8; MAX_MASTER_KEY_LEN];
+    Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(p_passphrase.as_bytes(), p_salt, &mut key)
+        .map_err(|err| Error::EncryptionError(err.to_string()))?;
+
+    Ok(key)
+}
+
+/// The start of a file encrypted with a key derived from a passphrase: four
+/// bytes telling what kind of file it is, the version of its format, and the
+/// Argon2id parameters and salt of the key, each parameter as a little-endian
+/// 32-bit integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHeader {
+    pub magic: [u8; 4],
+    pub version: u8,
+    pub params: KdfParams,
+    pub salt: [u8; SALT_LEN],
+}
+
+impl FileHeader {
+    pub const LEN: usize = 4 + 1 + 3 * 4 + SALT_LEN;
+
+    /// A header with the default parameters and a new salt.
+    pub fn new(p_magic: &[u8; 4], p_version: u8) -> FileHeader {
+        FileHeader {
+            magic: *p_magic,
+            version: p_version,
+            params: KdfParams::default(),
+            salt: random_salt(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.magic.to_vec();
+        bytes.push(self.version);
+        bytes.extend(self.params.memory.to_le_bytes());
+        bytes.extend(self.params.iterations.to_le_bytes());
+        bytes.extend(self.params.parallelism.to_le_bytes());
+        bytes.extend(self.salt);
+        bytes
+    }
+
+    /// Reads the header at the start of `p_data`, if it starts with `p_magic`.
+    /// The version isn't checked, that's up to each format.
+    pub fn parse(p_magic: &[u8; 4], p_data: &[u8]) -> Option<FileHeader> {
+        if p_data.len() < FileHeader::LEN || !p_data.starts_with(p_magic) {
+            return None;
+        }
+
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(p_data[offset..offset + 4].try_into().unwrap());
+
+        Some(FileHeader {
+            magic: *p_magic,
+            version: p_data[4],
+            params: KdfParams {
+                memory: read_u32(5),
+                iterations: read_u32(9),
+                parallelism: read_u32(13),
+            },
+            salt: p_data[17..FileHeader::LEN].try_into().unwrap(),
+        })
+    }
+
+    /// Derives the key of the file from `p_passphrase`.
+    pub fn derive_key(&self, p_passphrase: &str) -> Result<[u8; MAX_MASTER_KEY_LEN], Error> {
+        derive_key(p_passphrase, &self.salt, self.params)
+    }
+}
+
+/// The length of the random nonce stored in front of sealed data.
+pub const NONCE_LEN: usize = 24;
+
+/// Encrypts and authenticates data of any length. Unlike `encrypt`, the output
+/// is different every time, and tampering is detected by `open`.
+pub fn seal(p_key: &[u8], p_data: &[u8]) -> Vec<u8> {
+    seal_with_header(p_key, &[], p_data)
+}
+
+/// Like `seal`, but also authenticates `p_header`, which is kept in the clear
+/// next to the sealed data, so that it can't be changed either.
+pub fn seal_with_header(p_key: &[u8], p_header: &[u8], p_data: &[u8]) -> Vec<u8> {
+    let mut key = [0u8; MAX_MASTER_KEY_LEN];
+    p_key.iter().enumerate().for_each(|(i, v)| key[i] = *v);
+    let cipher = XChaCha20Poly1305::new(&GenericArray::from(key));
+
+    let mut nonce = [0u8; NONCE_LEN];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut nonce);
+
+    let payload = Payload {
+        msg: p_data,
+        aad: p_header,
+    };
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), payload)
+        .expect("encrypting in memory can't fail");
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    sealed
+}
+
+/// Reverses `seal`. Returns `None` if the key is wrong or the data was
+/// tampered with.
+pub fn open(p_key: &[u8], p_data: &[u8]) -> Option<Vec<u8>> {
+    open_with_header(p_key, &[], p_data)
+}
+
+/// Reverses `seal_with_header`. Returns `None` if the key is wrong, or if the
+/// header or the data were tampered with.
+pub fn open_with_header(p_key: &[u8], p_header: &[u8], p_data: &[u8]) -> Option<Vec<u8>> {
+    if p_data.len() < NONCE_LEN {
+        return None;
+    }
+
+    let mut key = [0u8; MAX_MASTER_KEY_LEN];
+    p_key.iter().enumerate().for_each(|(i, v)| key[i] = *v);
+    let cipher = XChaCha20Poly1305::new(&GenericArray::from(key));
+
+    let (nonce, ciphertext) = p_data.split_at(NONCE_LEN);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: p_header,
+    };
+    cipher.decrypt(XNonce::from_slice(nonce), payload).ok()
+}
+
+/// A keyed hash of the data, used to derive identifiers that can't be linked
+/// back to the data without the key.
+pub fn keyed_hash(p_key: &[u8], p_data: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(p_key).expect("HMAC accepts keys of any length");
+    mac.update(p_data);
+    mac.finalize().into_bytes().into()
+}
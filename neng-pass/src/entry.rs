@@ -0,0 +1,19 @@
+//! The entries of a vault, as they are stored, merged and synced.
+
+/// A single row of the passwords table, including tombstones. The password is
+/// kept encrypted, so merging never needs the master key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub password: Vec<u8>,
+    pub modified: i64,
+    pub deleted: bool,
+}
+
+impl Entry {
+    /// Whether two versions of an entry hold the same thing, regardless of when
+    /// they were written.
+    pub fn same_content(&self, p_other: &Entry) -> bool {
+        self.deleted == p_other.deleted && (self.deleted || self.password == p_other.password)
+    }
+}
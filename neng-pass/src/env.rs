@@ -0,0 +1,191 @@
+//! Environment variables that refer to passwords in the vault, for running
+//! programs with their secrets without writing them anywhere.
+//!
+//! Variables are given either as `VAR=name` on the command line, or in a
+//! dotenv file where vault references are written `VAR=neng://name` and any
+//! other value is passed through as it is.
+
+use crate::{storage::Storage, Error};
+
+/// The prefix of a value in a dotenv file that refers to a password.
+pub const REFERENCE_PREFIX: &str = "neng://";
+
+/// The value of a variable, before it is resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Literal(String),
+    /// The name of a password in the vault.
+    Reference(String),
+}
+
+fn is_valid_variable_name(p_name: &str) -> bool {
+    let mut chars = p_name.chars();
+    matches!(chars.next(), Some(first) if first == '_' || first.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+fn split_assignment(p_assignment: &str) -> Result<(String, &str), String> {
+    let (variable, value) = p_assignment
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' should look like VARIABLE=value", p_assignment))?;
+    let variable = variable.trim();
+
+    if !is_valid_variable_name(variable) {
+        return Err(format!("'{}' is not a valid environment variable name", variable));
+    }
+
+    Ok((variable.to_string(), value))
+}
+
+/// Parses a `VAR=name` assignment from the command line, where `name` is
+/// always the name of a password.
+pub fn parse_assignment(p_assignment: &str) -> Result<(String, Value), Error> {
+    let (variable, name) = split_assignment(p_assignment).map_err(Error::SyntaxError)?;
+    let name = name.strip_prefix(REFERENCE_PREFIX).unwrap_or(name);
+
+    Ok((variable, Value::Reference(name.to_string())))
+}
+
+/// Parses a dotenv file. Blank lines and lines starting with `#` are ignored,
+/// a leading `export` is allowed, and values may be quoted.
+pub fn parse_dotenv(p_content: &str) -> Result<Vec<(String, Value)>, Error> {
+    p_content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (variable, value) = split_assignment(line)
+                .map_err(|err| Error::SyntaxError(format!("line {}: {}", line_number, err)))?;
+
+            let value = value.trim();
+            let value = ['"', '\'']
+                .iter()
+                .find_map(|quote| {
+                    value
+                        .strip_prefix(*quote)
+                        .and_then(|value| value.strip_suffix(*quote))
+                })
+                .unwrap_or(value);
+
+            let value = match value.strip_prefix(REFERENCE_PREFIX) {
+                Some(name) => Value::Reference(name.to_string()),
+                None => Value::Literal(value.to_string()),
+            };
+
+            Ok((variable, value))
+        })
+        .collect()
+}
+
+/// Looks up every referenced password. Fails on the first reference to a
+/// password that doesn't exist, so that a program is never started with a
+/// secret missing.
+pub fn resolve_variables(
+    p_master_key: &str,
+    p_variables: &[(String, Value)],
+    p_storage: &dyn Storage,
+) -> Result<Vec<(String, String)>, Error> {
+    p_variables
+        .iter()
+        .map(|(variable, value)| {
+            let value = match value {
+                Value::Literal(value) => value.clone(),
+                Value::Reference(name) => {
+                    crate::get_password(p_master_key.to_string(), name, p_storage)?
+                        .trim_end_matches('\0')
+                        .to_string()
+                }
+            };
+
+            Ok((variable.clone(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(p_variable: &str, p_value: &str) -> (String, Value) {
+        (p_variable.to_string(), Value::Literal(p_value.to_string()))
+    }
+
+    fn reference(p_variable: &str, p_name: &str) -> (String, Value) {
+        (p_variable.to_string(), Value::Reference(p_name.to_string()))
+    }
+
+    #[test]
+    fn dotenv_files_are_parsed() {
+        let content = "
+# The database.
+DATABASE_URL=postgres://localhost/app
+export DATABASE_PASSWORD=neng://postgres
+
+  GREETING = \"hello world\"
+SINGLE='neng://not a reference?'
+QUOTED=\"neng://github\"
+EMPTY=
+HALF_QUOTED=\"open
+EQUALS=a=b
+";
+
+        assert_eq!(
+            parse_dotenv(content).unwrap(),
+            [
+                literal("DATABASE_URL", "postgres://localhost/app"),
+                reference("DATABASE_PASSWORD", "postgres"),
+                literal("GREETING", "hello world"),
+                reference("SINGLE", "not a reference?"),
+                reference("QUOTED", "github"),
+                literal("EMPTY", ""),
+                literal("HALF_QUOTED", "\"open"),
+                literal("EQUALS", "a=b"),
+            ]
+        );
+
+        // Only `export ` with a space is a prefix.
+        assert_eq!(
+            parse_dotenv("export=github").unwrap(),
+            [literal("export", "github")]
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_refused() {
+        let error = |p_content: &str| match parse_dotenv(p_content) {
+            Err(Error::SyntaxError(message)) => message,
+            other => panic!("expected a syntax error, got {:?}", other),
+        };
+
+        assert!(error("A=1\nJUST_A_NAME\n").starts_with("line 2: "));
+        assert!(error("1PASSWORD=github").contains("'1PASSWORD' is not a valid"));
+        assert!(error("MY-VAR=github").contains("'MY-VAR' is not a valid"));
+        assert!(error("=github").contains("'' is not a valid"));
+    }
+
+    #[test]
+    fn assignments_always_refer_to_passwords() {
+        assert_eq!(
+            parse_assignment("TOKEN=github").unwrap(),
+            reference("TOKEN", "github")
+        );
+        assert_eq!(
+            parse_assignment("TOKEN=neng://github").unwrap(),
+            reference("TOKEN", "github")
+        );
+        assert_eq!(
+            parse_assignment("TOKEN=a=b").unwrap(),
+            reference("TOKEN", "a=b")
+        );
+        assert!(matches!(
+            parse_assignment("TOKEN"),
+            Err(Error::SyntaxError(_))
+        ));
+        assert!(matches!(
+            parse_assignment("TO KEN=github"),
+            Err(Error::SyntaxError(_))
+        ));
+    }
+}
@@ -0,0 +1,250 @@
+//! Exports of the vault: one file with every entry, encrypted with a
+//! passphrase, that can be imported into another vault, or read without one
+//! by the web viewer.
+//!
+//! An export starts with a header: `NPEX`, the version of the format, and the
+//! Argon2id parameters and salt the key was derived with, each parameter as a
+//! little-endian 32-bit integer. The rest is the list of entries as JSON,
+//! sealed with that key. Passwords are in plain text inside, so an export
+//! doesn't depend on the master key of the vault it came from.
+
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{
+    crypto::{self, FileHeader},
+    Error,
+};
+
+pub const MAGIC: &[u8; 4] = b"NPEX";
+pub const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedEntry {
+    pub name: String,
+    pub password: String,
+    pub modified: i64,
+}
+
+impl Drop for ExportedEntry {
+    fn drop(&mut self) {
+        self.password.zeroize();
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Body {
+    entries: Vec<ExportedEntry>,
+}
+
+/// Encrypts entries into an export, with a key derived from `p_passphrase`.
+pub fn seal_export(p_passphrase: &str, p_entries: Vec<ExportedEntry>) -> Result<Vec<u8>, Error> {
+    let header = FileHeader::new(MAGIC, VERSION);
+    let key = Zeroizing::new(header.derive_key(p_passphrase)?);
+
+    let body = Zeroizing::new(
+        serde_json::to_vec(&Body { entries: p_entries }).expect("serializing entries can't fail"),
+    );
+
+    let mut export = header.to_bytes();
+    export.extend(crypto::seal(key.as_ref(), &body));
+
+    Ok(export)
+}
+
+/// Decrypts an export made by `seal_export`.
+pub fn open_export(p_passphrase: &str, p_export: &[u8]) -> Result<Vec<ExportedEntry>, Error> {
+    let header = FileHeader::parse(MAGIC, p_export).ok_or_else(|| {
+        Error::SyntaxError("This isn't an export of a neng-pass vault.".to_string())
+    })?;
+
+    if header.version != VERSION {
+        return Err(Error::SyntaxError(format!(
+            "The export is in version {} of the format, which this version of neng-pass can't read.",
+            header.version
+        )));
+    }
+
+    // Exports are opened in browsers too, so a tampered header must not be
+    // able to ask for more time or memory than that.
+    if !header.params.is_acceptable() {
+        return Err(Error::SyntaxError(
+            "The export asks for key derivation parameters that neng-pass doesn't accept."
+                .to_string(),
+        ));
+    }
+
+    // The header isn't sealed, but changing any of it changes the key, so the
+    // body can't be opened anymore.
+    let key = Zeroizing::new(header.derive_key(p_passphrase)?);
+    let body = Zeroizing::new(
+        crypto::open(key.as_ref(), &p_export[FileHeader::LEN..]).ok_or(Error::WrongKeyForData)?,
+    );
+    let body: Body = serde_json::from_slice(&body).map_err(|_| Error::WrongKeyForData)?;
+
+    Ok(body.entries)
+}
+
+/// What happened to the entries of an export when importing it.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub added: Vec<String>,
+    /// Entries that already exist with another password, which were left as
+    /// they are.
+    pub skipped: Vec<String>,
+}
+
+/// Exports every entry of the vault, except for the deleted ones.
+#[cfg(feature = "native")]
+pub fn export_vault(
+    p_master_key: &str,
+    p_passphrase: &str,
+    p_storage: &dyn crate::storage::Storage,
+) -> Result<Vec<u8>, Error> {
+    let master_key = crate::MasterKey::new(p_master_key)?;
+
+    let entries = crate::merge::read_entries(p_storage)?
+        .into_values()
+        .filter(|entry| !entry.deleted)
+        .map(|entry| {
+            let password = Zeroizing::new(crate::get_password_with_key(
+                &master_key,
+                &entry.name,
+                p_storage,
+            )?);
+            Ok(ExportedEntry {
+                password: password.trim_end_matches('\0').to_string(),
+                name: entry.name,
+                modified: entry.modified,
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+
+    seal_export(p_passphrase, entries)
+}
+
+/// Adds the entries of an export to the vault. An entry that already exists
+/// is never overwritten.
+#[cfg(feature = "native")]
+pub fn import_vault(
+    p_master_key: &str,
+    p_passphrase: &str,
+    p_export: &[u8],
+    p_storage: &dyn crate::storage::Storage,
+) -> Result<ImportReport, Error> {
+    let master_key = crate::MasterKey::new(p_master_key)?;
+    let entries = open_export(p_passphrase, p_export)?;
+    let mut report = ImportReport::default();
+
+    p_storage.transaction(&mut || {
+        for entry in &entries {
+            match crate::get_password_with_key(&master_key, &entry.name, p_storage) {
+                Ok(mut existing) => {
+                    if existing.trim_end_matches('\0') != entry.password {
+                        report.skipped.push(entry.name.clone());
+                    }
+                    existing.zeroize();
+                }
+                Err(Error::PasswordDoesntExist(_))
+                    if entry.password.len() > crate::MAX_PASSWORD_LEN =>
+                {
+                    // Passwords that are too long for `create_password`, like
+                    // the tokens of the git credential helper, are sealed
+                    // instead.
+                    p_storage.put(&crate::entry::Entry {
+                        name: entry.name.clone(),
+                        password: crypto::seal(master_key.as_bytes(), entry.password.as_bytes()),
+                        modified: crate::current_timestamp(),
+                        deleted: false,
+                    })?;
+                    report.added.push(entry.name.clone());
+                }
+                Err(Error::PasswordDoesntExist(_)) => {
+                    crate::create_password_with_key(
+                        &master_key,
+                        &entry.name,
+                        &entry.password,
+                        p_storage,
+                    )?;
+                    report.added.push(entry.name.clone());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(p_name: &str, p_password: &str) -> ExportedEntry {
+        ExportedEntry {
+            name: p_name.to_string(),
+            password: p_password.to_string(),
+            modified: 1,
+        }
+    }
+
+    #[test]
+    fn expensive_headers_are_refused() {
+        let export = seal_export("hunter2", vec![entry("github", "hunter3")]).unwrap();
+
+        // The memory, the iterations and the parallelism follow the magic and
+        // the version.
+        for offset in [5, 9, 13] {
+            let mut tampered = export.clone();
+            tampered[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(matches!(
+                open_export("hunter2", &tampered),
+                Err(Error::SyntaxError(_))
+            ));
+        }
+
+        assert!(matches!(
+            open_export("hunter2", b"not an export"),
+            Err(Error::SyntaxError(_))
+        ));
+        assert_eq!(
+            open_export("hunter2", &export).unwrap(),
+            [entry("github", "hunter3")]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn long_passwords_are_imported() {
+        let sql_connection = rusqlite::Connection::open_in_memory().unwrap();
+        crate::prepare_database(&sql_connection).unwrap();
+        crate::create_password("hunter2".to_string(), "github", "hunter3", &sql_connection)
+            .unwrap();
+
+        let token = "ghp_".to_string() + &"x".repeat(36);
+        let export = seal_export(
+            "passphrase",
+            vec![
+                entry("github", "other"),
+                entry("gitlab", &token),
+                entry("gitea", "hunter4"),
+            ],
+        )
+        .unwrap();
+
+        let report = import_vault("hunter2", "passphrase", &export, &sql_connection).unwrap();
+        assert_eq!(report.added, ["gitlab", "gitea"]);
+        assert_eq!(report.skipped, ["github"]);
+
+        let exported = export_vault("hunter2", "passphrase", &sql_connection).unwrap();
+        let mut entries = open_export("passphrase", &exported).unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let passwords: Vec<_> = entries
+            .iter()
+            .map(|entry| entry.password.as_str())
+            .collect();
+        assert_eq!(passwords, ["hunter4", "hunter3", token.as_str()]);
+    }
+}
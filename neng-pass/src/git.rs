@@ -0,0 +1,473 @@
+//! Keeps a copy of the vault in a git repository, as one encrypted file per
+//! entry, so that the history of the vault is recorded and it can be synced
+//! through any git remote.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    process::Command,
+};
+
+use crate::{
+    merge::{self, Conflict, Entry, MergeReport, Resolution},
+    storage::Storage,
+    Error, MasterKey,
+};
+
+/// The directory inside the repository that holds the entry files.
+pub const ENTRIES_DIR: &str = "entries";
+
+pub const DEFAULT_BRANCH: &str = "main";
+
+fn run_git(p_repo: &Path, p_args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(p_repo)
+        .args(p_args)
+        .output()
+        .map_err(|err| Error::GitError(format!("Failed to run git: {}", err)))?;
+
+    if !output.status.success() {
+        return Err(Error::GitError(format!(
+            "'git {}' failed: {}",
+            p_args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Whether the vault in the data directory has git history enabled.
+pub fn is_enabled(p_repo: &Path) -> bool {
+    p_repo.join(".git").exists()
+}
+
+/// Creates the repository, optionally pointing its `origin` remote at
+/// `p_remote`, and commits the current content of the vault.
+pub fn init(
+    p_repo: &Path,
+    p_remote: Option<&str>,
+    p_storage: &dyn Storage,
+) -> Result<(), Error> {
+    std::fs::create_dir_all(p_repo)?;
+    run_git(p_repo, &["init", "--quiet"])?;
+    run_git(
+        p_repo,
+        &["symbolic-ref", "HEAD", &format!("refs/heads/{}", DEFAULT_BRANCH)],
+    )?;
+
+    // Commits are made by the tool, so don't fail on machines where git has
+    // no identity configured.
+    if run_git(p_repo, &["config", "user.name"]).is_err() {
+        run_git(p_repo, &["config", "user.name", "neng-pass"])?;
+    }
+    if run_git(p_repo, &["config", "user.email"]).is_err() {
+        run_git(p_repo, &["config", "user.email", "neng-pass@localhost"])?;
+    }
+
+    if let Some(remote) = p_remote {
+        run_git(p_repo, &["remote", "add", "origin", remote])?;
+    }
+
+    commit(p_repo, p_storage, "Initialize the vault")?;
+
+    Ok(())
+}
+
+fn entry_file_name(p_name: &str) -> String {
+    // Names can contain anything, including slashes, so they are hex encoded.
+    hex::encode(p_name.as_bytes())
+}
+
+fn serialize_entry(p_entry: &Entry) -> String {
+    format!(
+        "modified {}\ndeleted {}\npassword {}\n",
+        p_entry.modified,
+        p_entry.deleted as u8,
+        hex::encode(&p_entry.password)
+    )
+}
+
+fn deserialize_entry(p_file_name: &str, p_content: &str) -> Result<Entry, Error> {
+    let invalid = || Error::GitError(format!("The entry file {} is malformed.", p_file_name));
+
+    let name = hex::decode(p_file_name).map_err(|_| invalid())?;
+    let mut entry = Entry {
+        name: String::from_utf8(name)?,
+        password: Vec::new(),
+        modified: 0,
+        deleted: false,
+    };
+
+    for line in p_content.lines() {
+        let (key, value) = line.split_once(' ').ok_or_else(invalid)?;
+        match key {
+            "modified" => entry.modified = value.parse().map_err(|_| invalid())?,
+            "deleted" => entry.deleted = value == "1",
+            "password" => entry.password = hex::decode(value).map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(entry)
+}
+
+/// Writes every entry of the database (tombstones included) into the
+/// working tree, and removes the files of entries that no longer exist.
+pub fn export_entries(p_repo: &Path, p_storage: &dyn Storage) -> Result<(), Error> {
+    let entries_dir = p_repo.join(ENTRIES_DIR);
+    std::fs::create_dir_all(&entries_dir)?;
+
+    let entries = merge::read_entries(p_storage)?;
+    let file_names = entries
+        .values()
+        .map(|entry| {
+            let file_name = entry_file_name(&entry.name);
+            std::fs::write(entries_dir.join(&file_name), serialize_entry(entry))?;
+            Ok(file_name)
+        })
+        .collect::<Result<BTreeSet<_>, Error>>()?;
+
+    for file in std::fs::read_dir(&entries_dir)? {
+        let file = file?;
+        if !file_names.contains(file.file_name().to_string_lossy().as_ref()) {
+            std::fs::remove_file(file.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the entries stored in a commit.
+fn read_entries_at(p_repo: &Path, p_revision: &str) -> Result<BTreeMap<String, Entry>, Error> {
+    let files = run_git(
+        p_repo,
+        &["ls-tree", "--name-only", &format!("{}:{}", p_revision, ENTRIES_DIR)],
+    )
+    .unwrap_or_default();
+
+    files
+        .lines()
+        .map(|file_name| {
+            let content = run_git(
+                p_repo,
+                &["show", &format!("{}:{}/{}", p_revision, ENTRIES_DIR, file_name)],
+            )?;
+            let entry = deserialize_entry(file_name, &content)?;
+            Ok((entry.name.clone(), entry))
+        })
+        .collect()
+}
+
+/// Exports the database into the working tree and commits it, if anything
+/// changed.
+pub fn commit(
+    p_repo: &Path,
+    p_storage: &dyn Storage,
+    p_message: &str,
+) -> Result<(), Error> {
+    export_entries(p_repo, p_storage)?;
+    run_git(p_repo, &["add", "--all", ENTRIES_DIR])?;
+
+    if run_git(p_repo, &["status", "--porcelain"])?.trim().is_empty() {
+        return Ok(());
+    }
+
+    run_git(p_repo, &["commit", "--quiet", "-m", p_message])?;
+    Ok(())
+}
+
+fn is_ancestor(p_repo: &Path, p_ancestor: &str, p_descendant: &str) -> bool {
+    run_git(
+        p_repo,
+        &["merge-base", "--is-ancestor", p_ancestor, p_descendant],
+    )
+    .is_ok()
+}
+
+/// Fetches `p_branch` from `p_remote` and merges it into the vault. Entry
+/// files are never merged as text: the common ancestor commit provides the
+/// base version of every entry and the vault's own merge logic decides, with
+/// `p_resolve` being asked about the true conflicts. When conflicts are left
+/// unresolved, no merge commit is made, so the next pull will ask again.
+/// Remotes pushed to with another master key are refused.
+pub fn pull(
+    p_master_key: &MasterKey,
+    p_repo: &Path,
+    p_sql_connection: &rusqlite::Connection,
+    p_remote: &str,
+    p_branch: &str,
+    p_resolve: impl FnMut(&Conflict) -> Option<Resolution>,
+) -> Result<MergeReport, Error> {
+    commit(p_repo, p_sql_connection, "Save local changes before pulling")?;
+    run_git(p_repo, &["fetch", "--quiet", p_remote, p_branch])?;
+    let peer = format!("git:{}/{}", p_remote, p_branch);
+
+    let head = run_git(p_repo, &["rev-parse", "--verify", "--quiet", "HEAD"]).ok();
+    let remote_head = run_git(p_repo, &["rev-parse", "FETCH_HEAD"])?.trim().to_string();
+
+    let head = match head {
+        Some(head) => head.trim().to_string(),
+        None => {
+            // Nothing was ever committed here, so just take the remote history.
+            let remote_entries = read_entries_at(p_repo, &remote_head)?;
+            merge::check_master_key(p_master_key, remote_entries.values())?;
+            run_git(p_repo, &["reset", "--quiet", "--hard", &remote_head])?;
+            return merge::apply_merge(
+                p_sql_connection,
+                &peer,
+                remote_entries
+                    .iter()
+                    .map(|(name, entry)| (name.clone(), None, None, Some(entry))),
+                |_| Some(Resolution::KeepOther),
+            );
+        }
+    };
+
+    if is_ancestor(p_repo, &remote_head, &head) {
+        return Ok(MergeReport::default());
+    }
+
+    let base = run_git(p_repo, &["merge-base", &head, &remote_head])
+        .ok()
+        .map(|base| base.trim().to_string());
+    let base_entries = match &base {
+        Some(base) => read_entries_at(p_repo, base)?,
+        None => BTreeMap::new(),
+    };
+    let local_entries = merge::read_entries(p_sql_connection)?;
+    let remote_entries = read_entries_at(p_repo, &remote_head)?;
+    merge::check_master_key(p_master_key, remote_entries.values())?;
+
+    let names = local_entries
+        .keys()
+        .chain(remote_entries.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    let report = merge::apply_merge(
+        p_sql_connection,
+        &peer,
+        names.into_iter().map(|name| {
+            let entry_base = base_entries.get(&name).map(|entry| entry.modified);
+            let local = local_entries.get(&name);
+            let remote = remote_entries.get(&name);
+            (name, entry_base, local, remote)
+        }),
+        p_resolve,
+    )?;
+
+    if !report.unresolved.is_empty() {
+        commit(p_repo, p_sql_connection, "Merge the non-conflicting remote changes")?;
+        return Ok(report);
+    }
+
+    // The content of the merge commit comes from the database, git is only
+    // used to record that both histories were joined.
+    let mut merge_args = vec!["merge", "--quiet", "--no-commit", "--no-ff"];
+    if base.is_none() {
+        merge_args.push("--allow-unrelated-histories");
+    }
+    merge_args.extend(["-s", "ours", &remote_head]);
+    run_git(p_repo, &merge_args)?;
+
+    export_entries(p_repo, p_sql_connection)?;
+    run_git(p_repo, &["add", "--all", ENTRIES_DIR])?;
+    run_git(
+        p_repo,
+        &[
+            "commit",
+            "--quiet",
+            "-m",
+            &format!("Merge {} from {}", p_branch, p_remote),
+        ],
+    )?;
+
+    Ok(report)
+}
+
+/// Commits any pending changes and pushes the current branch to `p_branch`
+/// on `p_remote`.
+pub fn push(
+    p_repo: &Path,
+    p_storage: &dyn Storage,
+    p_remote: &str,
+    p_branch: &str,
+) -> Result<(), Error> {
+    commit(p_repo, p_storage, "Save local changes before pushing")?;
+    run_git(
+        p_repo,
+        &["push", "--quiet", p_remote, &format!("HEAD:refs/heads/{}", p_branch)],
+    )
+    .map_err(|err| match err {
+        Error::GitError(message) => Error::GitError(format!(
+            "{} (if the remote has changes you don't have, pull first)",
+            message
+        )),
+        err => err,
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+
+    fn key() -> MasterKey {
+        MasterKey::new("hunter2").unwrap()
+    }
+
+    fn vault() -> rusqlite::Connection {
+        let sql_connection = rusqlite::Connection::open_in_memory().unwrap();
+        crate::prepare_database(&sql_connection).unwrap();
+        sql_connection
+    }
+
+    fn save(p_storage: &dyn Storage, p_name: &str, p_password: &[u8], p_modified: i64) {
+        merge::write_entry(
+            p_storage,
+            &Entry {
+                name: p_name.to_string(),
+                password: crypto::encrypt(key().as_bytes(), p_password).to_vec(),
+                modified: p_modified,
+                deleted: false,
+            },
+        )
+        .unwrap();
+    }
+
+    fn delete(p_storage: &dyn Storage, p_name: &str, p_modified: i64) {
+        merge::write_entry(
+            p_storage,
+            &Entry {
+                name: p_name.to_string(),
+                password: Vec::new(),
+                modified: p_modified,
+                deleted: true,
+            },
+        )
+        .unwrap();
+    }
+
+    fn password(p_storage: &dyn Storage, p_name: &str) -> Option<Vec<u8>> {
+        let entry = p_storage
+            .get(p_name)
+            .unwrap()
+            .filter(|entry| !entry.deleted)?;
+        let password = crypto::decrypt(key().as_bytes(), &entry.password);
+        Some(password.into_iter().take_while(|byte| *byte != 0).collect())
+    }
+
+    #[test]
+    fn diverging_clones_are_merged() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("remote.git");
+        let remote = remote.to_str().unwrap();
+        run_git(dir.path(), &["init", "--quiet", "--bare", remote]).unwrap();
+
+        let laptop_repo = dir.path().join("laptop");
+        let laptop = vault();
+        save(&laptop, "github", b"github", 1);
+        save(&laptop, "gitlab", b"gitlab", 1);
+        save(&laptop, "old", b"old", 1);
+        init(&laptop_repo, Some(remote), &laptop).unwrap();
+        push(&laptop_repo, &laptop, "origin", DEFAULT_BRANCH).unwrap();
+
+        let phone_repo = dir.path().join("phone");
+        let phone = vault();
+        init(&phone_repo, Some(remote), &phone).unwrap();
+        let report = pull(
+            &key(),
+            &phone_repo,
+            &phone,
+            "origin",
+            DEFAULT_BRANCH,
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(report.added, ["github", "gitlab", "old"]);
+
+        // Each side changes a different entry.
+        save(&laptop, "github", b"github 2", 2);
+        push(&laptop_repo, &laptop, "origin", DEFAULT_BRANCH).unwrap();
+        save(&phone, "gitlab", b"gitlab 2", 3);
+        delete(&phone, "old", 3);
+
+        let report = pull(
+            &key(),
+            &phone_repo,
+            &phone,
+            "origin",
+            DEFAULT_BRANCH,
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(report.updated, ["github"]);
+        assert!(report.unresolved.is_empty());
+        push(&phone_repo, &phone, "origin", DEFAULT_BRANCH).unwrap();
+
+        let report = pull(
+            &key(),
+            &laptop_repo,
+            &laptop,
+            "origin",
+            DEFAULT_BRANCH,
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(report.updated, ["gitlab"]);
+        assert_eq!(report.deleted, ["old"]);
+
+        for vault in [&laptop, &phone] {
+            assert_eq!(password(vault, "github").unwrap(), b"github 2");
+            assert_eq!(password(vault, "gitlab").unwrap(), b"gitlab 2");
+            assert_eq!(password(vault, "old"), None);
+        }
+
+        // Both histories were joined, so there is nothing left to pull.
+        let report = pull(
+            &key(),
+            &phone_repo,
+            &phone,
+            "origin",
+            DEFAULT_BRANCH,
+            |_| None,
+        )
+        .unwrap();
+        assert!(report.added.is_empty() && report.updated.is_empty() && report.deleted.is_empty());
+    }
+
+    #[test]
+    fn remotes_with_another_master_key_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("remote.git");
+        let remote = remote.to_str().unwrap();
+        run_git(dir.path(), &["init", "--quiet", "--bare", remote]).unwrap();
+
+        let laptop_repo = dir.path().join("laptop");
+        let laptop = vault();
+        save(&laptop, "github", b"github", 1);
+        init(&laptop_repo, Some(remote), &laptop).unwrap();
+        push(&laptop_repo, &laptop, "origin", DEFAULT_BRANCH).unwrap();
+
+        let phone_repo = dir.path().join("phone");
+        let phone = vault();
+        init(&phone_repo, Some(remote), &phone).unwrap();
+        let other_key = MasterKey::new("hunter3").unwrap();
+        assert!(matches!(
+            pull(
+                &other_key,
+                &phone_repo,
+                &phone,
+                "origin",
+                DEFAULT_BRANCH,
+                |_| None
+            ),
+            Err(Error::WrongKeyForData)
+        ));
+        assert_eq!(phone.get("github").unwrap(), None);
+    }
+}
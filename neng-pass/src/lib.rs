@@ -1,48 +1,128 @@
+use std::string::FromUtf8Error;
+#[cfg(feature = "native")]
 use std::{
     fs::File,
     io::{Read, Write},
-    string::FromUtf8Error,
 };
 
+#[cfg(all(unix, feature = "native"))]
+pub mod agent;
+#[cfg(feature = "native")]
+pub mod autofill;
+#[cfg(feature = "native")]
+pub mod config;
+#[cfg(feature = "native")]
+pub mod credential;
 pub mod crypto;
-
-use argon2::{password_hash::Error as Argon2Error, Argon2, PasswordHash, PasswordVerifier};
+pub mod entry;
+#[cfg(feature = "native")]
+pub mod env;
+pub mod export;
+#[cfg(feature = "native")]
+pub mod git;
+#[cfg(feature = "native")]
+pub mod merge;
+#[cfg(feature = "native")]
+pub mod session;
+#[cfg(feature = "native")]
+pub mod share;
+#[cfg(feature = "native")]
+pub mod ssh;
+pub mod storage;
+pub mod strength;
+#[cfg(feature = "native")]
+pub mod sync;
+#[cfg(feature = "native")]
+pub mod template;
+#[cfg(feature = "native")]
+pub mod vault;
+#[cfg(feature = "native")]
+pub mod vault_file;
+
+use argon2::password_hash::Error as Argon2Error;
+#[cfg(feature = "native")]
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
+#[cfg(feature = "native")]
 use rusqlite::Error as SqliteError;
 use std::io::Error as IOError;
-
-pub use rusqlite;
+use zeroize::Zeroizing;
+#[cfg(feature = "native")]
+use storage::Storage;
+#[cfg(feature = "native")]
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub const MAX_MASTER_KEY_LEN: usize = 32;
 pub const MAX_PASSWORD_LEN: usize = 16;
 
 #[derive(Debug)]
 pub enum Error {
+    AgentError(String),
+    CantRemoveLastMember,
+    CollectionAlreadyExists(Box<str>),
+    CollectionDoesntExist(Box<str>),
+    ConfigError(String),
+    EncryptionError(String),
     HashError(Argon2Error),
+    #[cfg(feature = "native")]
     DatabaseError(SqliteError),
     IOError(IOError),
     FromUtf8Error(FromUtf8Error),
+    GitError(String),
+    InvalidPublicKey(Box<str>),
     MasterKeyDoesntExist,
     MasterKeyTooLong,
     MasterKeyAlreadyExists,
+    MemberAlreadyExists(Box<str>),
+    MemberDoesntExist(Box<str>),
+    NotACollectionMember,
     PasswordAlreadyExists,
     PasswordDoesntExist(Box<str>),
     PasswordTooLong,
+    SshKeyAlreadyExists(Box<str>),
+    SshKeyDoesntExist(Box<str>),
+    SshKeyError(String),
+    SyncError(String),
+    SyntaxError(String),
+    UrlDoesntExist(Box<str>),
+    VaultChanged(Box<str>),
+    VaultDoesntExist(Box<str>),
+    VaultLocked,
+    WrongKeyForData,
     UnknownError,
 }
 
 impl Error {
     pub fn get_message(&self) -> String {
         match self {
+            Error::AgentError(err) => {
+                format!("Agent error: {}", err)
+            }
+            Error::CantRemoveLastMember => {
+                "You can't remove the last member of a collection, nobody would be able to open it anymore.".to_string()
+            }
+            Error::CollectionAlreadyExists(file) => {
+                format!("There is already something at {}", file)
+            }
+            Error::CollectionDoesntExist(file) => {
+                format!("There is no shared collection at {}", file)
+            }
+            Error::ConfigError(err) => {
+                format!("Invalid configuration: {}", err)
+            }
+            Error::EncryptionError(err) => {
+                format!("Failed to encrypt: {}", err)
+            }
             Error::HashError(err) => match err {
                 Argon2Error::Password => {
-                    format!("The password is incorrect.")
+                    "The password is incorrect.".to_string()
                 }
                 _ => {
                     format!("Failed to hash the password: {}", err)
                 }
             },
+            #[cfg(feature = "native")]
             Error::DatabaseError(err) => {
                 format!("SQL error: {}", err)
             }
@@ -52,17 +132,32 @@ impl Error {
             Error::FromUtf8Error(err) => {
                 format!("Invalid UTF-8 string: {}", err)
             }
+            Error::GitError(err) => {
+                format!("Git error: {}", err)
+            }
+            Error::InvalidPublicKey(key) => {
+                format!("'{}' is not a valid public key. Public keys look like age1...", key)
+            }
             Error::MasterKeyTooLong => {
                 format!("Your master key is too long! Master keys can only be up to {} characters long.", MAX_MASTER_KEY_LEN)
             }
             Error::MasterKeyAlreadyExists => {
-                format!("The master key has already been set. Don't try to set it again, as it will break stuff.")
+                "The master key has already been set. Don't try to set it again, as it will break stuff.".to_string()
             }
             Error::MasterKeyDoesntExist => {
                 "It looks like you didn't set a master key yet! Use the set-master command to do so.".to_string()
             }
+            Error::MemberAlreadyExists(key) => {
+                format!("{} is already a member of the collection.", key)
+            }
+            Error::MemberDoesntExist(key) => {
+                format!("{} is not a member of the collection.", key)
+            }
+            Error::NotACollectionMember => {
+                "You are not a member of this collection.".to_string()
+            }
             Error::PasswordAlreadyExists => {
-                format!("A password with that name already exists!")
+                "A password with that name already exists!".to_string()
             },
             Error::PasswordDoesntExist(name) => {
                 format!("There appears to be no password saved that is named {}", name)
@@ -70,11 +165,130 @@ impl Error {
             Error::PasswordTooLong => {
                 format!("Your password is too long! Passwords can only be up to {} characters long.", MAX_PASSWORD_LEN)
             }
+            Error::SshKeyAlreadyExists(name) => {
+                format!("There is already an SSH key named {}", name)
+            }
+            Error::SshKeyDoesntExist(name) => {
+                format!("There is no SSH key named {}", name)
+            }
+            Error::SshKeyError(err) => {
+                format!("Invalid SSH key: {}", err)
+            }
+            Error::SyncError(err) => {
+                format!("Failed to sync: {}", err)
+            }
+            Error::SyntaxError(err) => {
+                format!("Syntax error: {}", err)
+            }
+            Error::UrlDoesntExist(url) => {
+                format!("The entry has no URL {}", url)
+            }
+            Error::VaultChanged(path) => {
+                format!("{} was changed by another program since it was opened, so it wasn't overwritten. Run the command again.", path)
+            }
+            Error::VaultDoesntExist(path) if std::path::Path::new(&**path).components().count() > 1 => {
+                format!("There is no vault at {}", path)
+            }
+            Error::VaultDoesntExist(name) => {
+                format!("There is no vault named {}", name)
+            }
+            Error::VaultLocked => {
+                "The vault is locked, unlock it first.".to_string()
+            }
+            Error::WrongKeyForData => {
+                "The data couldn't be decrypted. Either it was encrypted with another master key, or it has been tampered with.".to_string()
+            }
             Error::UnknownError => {
                 "Sorry, but something went wrong.".to_string()
             }
         }
     }
+
+    /// A stable, machine-readable identifier of the kind of error, for
+    /// scripts. These never change once released.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::AgentError(_) => "agent_error",
+            Error::CantRemoveLastMember => "cant_remove_last_member",
+            Error::CollectionAlreadyExists(_) => "collection_already_exists",
+            Error::CollectionDoesntExist(_) => "collection_not_found",
+            Error::ConfigError(_) => "config_error",
+            Error::EncryptionError(_) => "encryption_error",
+            Error::HashError(Argon2Error::Password) => "wrong_master_key",
+            Error::HashError(_) => "hash_error",
+            #[cfg(feature = "native")]
+            Error::DatabaseError(_) => "database_error",
+            Error::IOError(_) => "io_error",
+            Error::FromUtf8Error(_) => "invalid_utf8",
+            Error::GitError(_) => "git_error",
+            Error::InvalidPublicKey(_) => "invalid_public_key",
+            Error::MasterKeyDoesntExist => "master_key_not_set",
+            Error::MasterKeyTooLong => "master_key_too_long",
+            Error::MasterKeyAlreadyExists => "master_key_already_set",
+            Error::MemberAlreadyExists(_) => "member_already_exists",
+            Error::MemberDoesntExist(_) => "member_not_found",
+            Error::NotACollectionMember => "not_a_member",
+            Error::PasswordAlreadyExists => "password_already_exists",
+            Error::PasswordDoesntExist(_) => "password_not_found",
+            Error::PasswordTooLong => "password_too_long",
+            Error::SshKeyAlreadyExists(_) => "ssh_key_already_exists",
+            Error::SshKeyDoesntExist(_) => "ssh_key_not_found",
+            Error::SshKeyError(_) => "invalid_ssh_key",
+            Error::SyncError(_) => "sync_error",
+            Error::SyntaxError(_) => "syntax_error",
+            Error::UrlDoesntExist(_) => "url_not_found",
+            Error::VaultChanged(_) => "vault_changed",
+            Error::VaultDoesntExist(_) => "vault_not_found",
+            Error::VaultLocked => "locked",
+            Error::WrongKeyForData => "wrong_key_for_data",
+            Error::UnknownError => "unknown_error",
+        }
+    }
+
+    /// The exit code the CLI uses for this kind of error:
+    ///
+    /// - 1: anything else
+    /// - 3: the master key is wrong, missing, or doesn't give access
+    /// - 4: something that was asked for doesn't exist
+    /// - 5: something that was to be created already exists
+    /// - 6: the input is invalid
+    /// - 7: reading or writing the vault failed
+    /// - 8: an external program or service (git, a sync server, the agent) failed
+    /// - 9: data couldn't be encrypted or decrypted
+    ///
+    /// Exit code 2 is used by the CLI for unresolved merge conflicts.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::HashError(Argon2Error::Password)
+            | Error::MasterKeyDoesntExist
+            | Error::NotACollectionMember
+            | Error::VaultLocked => 3,
+            Error::CollectionDoesntExist(_)
+            | Error::MemberDoesntExist(_)
+            | Error::PasswordDoesntExist(_)
+            | Error::SshKeyDoesntExist(_)
+            | Error::UrlDoesntExist(_)
+            | Error::VaultDoesntExist(_) => 4,
+            Error::CollectionAlreadyExists(_)
+            | Error::MasterKeyAlreadyExists
+            | Error::MemberAlreadyExists(_)
+            | Error::PasswordAlreadyExists
+            | Error::SshKeyAlreadyExists(_) => 5,
+            Error::CantRemoveLastMember
+            | Error::ConfigError(_)
+            | Error::InvalidPublicKey(_)
+            | Error::MasterKeyTooLong
+            | Error::PasswordTooLong
+            | Error::SshKeyError(_)
+            | Error::SyntaxError(_) => 6,
+            #[cfg(feature = "native")]
+            Error::DatabaseError(_) => 7,
+            Error::IOError(_) | Error::FromUtf8Error(_) | Error::VaultChanged(_) => 7,
+            Error::AgentError(_) | Error::GitError(_) | Error::SyncError(_) => 8,
+            Error::EncryptionError(_) | Error::HashError(_) | Error::WrongKeyForData => 9,
+            Error::UnknownError => 1,
+        }
+    }
 }
 
 impl From<Argon2Error> for Error {
@@ -83,6 +297,7 @@ impl From<Argon2Error> for Error {
     }
 }
 
+#[cfg(feature = "native")]
 impl From<SqliteError> for Error {
     fn from(value: SqliteError) -> Self {
         Self::DatabaseError(value)
@@ -107,6 +322,7 @@ impl From<Error> for String {
     }
 }
 
+#[cfg(feature = "native")]
 pub fn set_master_key(p_file: &str, p_new_key: &str) -> Result<(), Error> {
     if File::open(p_file).is_ok() {
         return Err(Error::MasterKeyAlreadyExists);
@@ -123,6 +339,7 @@ pub fn set_master_key(p_file: &str, p_new_key: &str) -> Result<(), Error> {
     Ok(())
 }
 
+#[cfg(feature = "native")]
 pub fn query_master_key(
     p_master_key_file: &str,
     p_inputted_password: &str,
@@ -159,68 +376,301 @@ pub fn generate_password() -> [u8; MAX_PASSWORD_LEN] {
     password
 }
 
-pub fn create_password(
-    mut p_master_key: String,
-    p_name: &str,
-    p_password: &str,
-    p_sql_connection: &rusqlite::Connection,
-) -> Result<(), Error> {
-    let mut sql_statement = p_sql_connection.prepare("SELECT name FROM passwords WHERE name = ?")?;
-    let password_names = sql_statement.query_map([p_name], |row| row.get::<_, String>(0))?;
+/// Which characters generated passwords are made of, and how many.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeneratorOptions {
+    /// Up to `MAX_PASSWORD_LEN`.
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+}
 
-    if password_names.count() > 0 {
-        return Err(Error::PasswordAlreadyExists);
+impl Default for GeneratorOptions {
+    fn default() -> GeneratorOptions {
+        GeneratorOptions {
+            length: MAX_PASSWORD_LEN,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+        }
     }
+}
 
-    if p_password.len() > MAX_PASSWORD_LEN {
-        return Err(Error::PasswordTooLong);
+/// Generates a password with only the characters allowed by the options. If
+/// none are allowed, any printable character is.
+pub fn generate_password_with(p_options: &GeneratorOptions) -> String {
+    let allowed = |character: &u8| match character {
+        b'a'..=b'z' => p_options.lowercase,
+        b'A'..=b'Z' => p_options.uppercase,
+        b'0'..=b'9' => p_options.digits,
+        _ => p_options.symbols,
+    };
+
+    let mut characters: Vec<u8> = (33..127).filter(allowed).collect();
+    if characters.is_empty() {
+        characters = (33..127).collect();
     }
 
-    // Pad the master key
+    let mut password_generator = ChaCha20Rng::from_entropy();
+    (0..p_options.length.min(MAX_PASSWORD_LEN))
+        .map(|_| characters[password_generator.gen_range(0..characters.len())] as char)
+        .collect()
+}
+
+/// The key that the passwords are encrypted with, which is the master key
+/// padded to the length of an encryption key. It is wiped from memory when it
+/// is dropped, so that it can be kept while a vault is unlocked without
+/// leaving copies of the master key behind.
+pub struct MasterKey(Zeroizing<[u8; MAX_MASTER_KEY_LEN]>);
 
+impl MasterKey {
+    pub fn new(p_master_key: &str) -> Result<MasterKey, Error> {
+        if p_master_key.len() > MAX_MASTER_KEY_LEN {
+            return Err(Error::MasterKeyTooLong);
+        }
+
+        let mut key = Zeroizing::new([b' '; MAX_MASTER_KEY_LEN]);
+        key[..p_master_key.len()].copy_from_slice(p_master_key.as_bytes());
+        Ok(MasterKey(key))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+/// Pads the master key to the length of an encryption key.
+pub fn pad_master_key(mut p_master_key: String) -> String {
     while p_master_key.len() < MAX_MASTER_KEY_LEN {
         p_master_key.push(' ');
     }
 
-    let encrypted_password = crypto::encrypt(p_master_key.as_bytes(), &p_password.as_bytes());
+    p_master_key
+}
+
+/// Creates the tables used by neng-pass if they do not exist yet, and upgrades
+/// databases created by older versions to the current layout.
+#[cfg(feature = "native")]
+pub fn prepare_database(p_sql_connection: &rusqlite::Connection) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "CREATE TABLE IF NOT EXISTS passwords (name TEXT, password BLOB, modified INTEGER NOT NULL DEFAULT 0, deleted INTEGER NOT NULL DEFAULT 0);",
+        (),
+    )?;
+
+    // Older databases only have the name and password columns.
+    let mut sql_statement = p_sql_connection.prepare("PRAGMA table_info(passwords);")?;
+    let columns = sql_statement
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !columns.iter().any(|column| column == "modified") {
+        p_sql_connection.execute(
+            "ALTER TABLE passwords ADD COLUMN modified INTEGER NOT NULL DEFAULT 0;",
+            (),
+        )?;
+    }
+
+    if !columns.iter().any(|column| column == "deleted") {
+        p_sql_connection.execute(
+            "ALTER TABLE passwords ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0;",
+            (),
+        )?;
+    }
 
     p_sql_connection.execute(
-        "INSERT INTO passwords VALUES (?, ?)",
-        (p_name, &encrypted_password[..]),
+        "CREATE TABLE IF NOT EXISTS merge_bases (peer TEXT NOT NULL, name TEXT NOT NULL, modified INTEGER NOT NULL, PRIMARY KEY (peer, name));",
+        (),
     )?;
 
-    Ok(()) // Placeholder
+    p_sql_connection.execute("CREATE TABLE IF NOT EXISTS identity (secret BLOB NOT NULL);", ())?;
+
+    p_sql_connection.execute(
+        "CREATE TABLE IF NOT EXISTS sync_servers (url TEXT PRIMARY KEY, revision INTEGER NOT NULL);",
+        (),
+    )?;
+
+    p_sql_connection.execute(
+        "CREATE TABLE IF NOT EXISTS sync_entries (url TEXT, name TEXT, revision INTEGER NOT NULL, modified INTEGER NOT NULL, PRIMARY KEY (url, name));",
+        (),
+    )?;
+
+    p_sql_connection.execute(
+        "CREATE TABLE IF NOT EXISTS entry_urls (name TEXT NOT NULL, url TEXT NOT NULL, mode TEXT NOT NULL, PRIMARY KEY (name, url));",
+        (),
+    )?;
+
+    p_sql_connection.execute(
+        "CREATE TABLE IF NOT EXISTS equivalent_domains (domain TEXT PRIMARY KEY, grp INTEGER NOT NULL);",
+        (),
+    )?;
+
+    p_sql_connection.execute(
+        "CREATE TABLE IF NOT EXISTS app_links (package TEXT NOT NULL, domain TEXT NOT NULL, PRIMARY KEY (package, domain));",
+        (),
+    )?;
+
+    // The common browsers are trusted until the user says otherwise, so they
+    // are only added along with the table.
+    let has_browsers = p_sql_connection.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'browsers';",
+        [],
+        |row| row.get::<_, i64>(0),
+    )? > 0;
+    if !has_browsers {
+        p_sql_connection.execute("CREATE TABLE browsers (package TEXT PRIMARY KEY);", ())?;
+        for browser in autofill::DEFAULT_BROWSERS {
+            autofill::add_browser(browser, p_sql_connection)?;
+        }
+    }
+
+    p_sql_connection.execute(
+        "CREATE TABLE IF NOT EXISTS ssh_keys (name TEXT PRIMARY KEY, public_key TEXT NOT NULL, private_key BLOB NOT NULL, confirm INTEGER NOT NULL DEFAULT 0);",
+        (),
+    )?;
+
+    Ok(())
 }
 
-pub fn get_password(
-    mut p_master_key: String,
+/// The current time in milliseconds since the Unix epoch, used to stamp
+/// modifications of entries.
+#[cfg(feature = "native")]
+pub fn current_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Writes a file that only the current user can read. The content is
+/// written to a temporary file first, so the file is never left half
+/// written, and is never readable by others, even for a moment.
+#[cfg(feature = "native")]
+pub fn write_private_file(p_path: &std::path::Path, p_content: &[u8]) -> std::io::Result<()> {
+    let file_name = p_path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary_path =
+        p_path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let result = options
+        .open(&temporary_path)
+        .and_then(|mut file| {
+            file.write_all(p_content)?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temporary_path, p_path));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary_path);
+    }
+
+    result
+}
+
+#[cfg(feature = "native")]
+pub fn create_password(
+    p_master_key: String,
     p_name: &str,
-    p_sql_connection: &rusqlite::Connection,
-) -> Result<String, Error> {
-    while p_master_key.len() < MAX_MASTER_KEY_LEN {
-        p_master_key.push(' ');
+    p_password: &str,
+    p_storage: &dyn Storage,
+) -> Result<(), Error> {
+    let master_key = MasterKey::new(&Zeroizing::new(p_master_key))?;
+    create_password_with_key(&master_key, p_name, p_password, p_storage)
+}
+
+/// Like `create_password`, for callers that keep the master key around.
+#[cfg(feature = "native")]
+pub fn create_password_with_key(
+    p_master_key: &MasterKey,
+    p_name: &str,
+    p_password: &str,
+    p_storage: &dyn Storage,
+) -> Result<(), Error> {
+    if p_storage.get(p_name)?.is_some_and(|entry| !entry.deleted) {
+        return Err(Error::PasswordAlreadyExists);
+    }
+
+    if p_password.len() > MAX_PASSWORD_LEN {
+        return Err(Error::PasswordTooLong);
     }
 
-    /* let sql_query = "SELECT * FROM passwords WHERE name = ?;";
-    let mut sql_statement = p_sql_connection.prepare(sql_query)?;
-    sql_statement.bind((1, p_name))?; */
+    let encrypted_password = crypto::encrypt(p_master_key.as_bytes(), p_password.as_bytes());
 
-    let mut sql_statement = p_sql_connection.prepare("SELECT * FROM passwords WHERE name = ?;")?;
-    let mut password_names = sql_statement.query_map([p_name], |row| row.get::<_, Vec<u8>>(1))?;
+    // This replaces the tombstone left behind by a previous deletion, if any.
+    p_storage.put(&entry::Entry {
+        name: p_name.to_string(),
+        password: encrypted_password.to_vec(),
+        modified: current_timestamp(),
+        deleted: false,
+    })
+}
 
-    let row = match password_names.next() {
-        Some(row) => row,
-        None => return Err(Error::PasswordDoesntExist(Box::from(p_name))),
-    }?;
+#[cfg(feature = "native")]
+pub fn get_password(
+    p_master_key: String,
+    p_name: &str,
+    p_storage: &dyn Storage,
+) -> Result<String, Error> {
+    let master_key = MasterKey::new(&Zeroizing::new(p_master_key))?;
+    get_password_with_key(&master_key, p_name, p_storage)
+}
+
+/// Like `get_password`, for callers that keep the master key around.
+#[cfg(feature = "native")]
+pub fn get_password_with_key(
+    p_master_key: &MasterKey,
+    p_name: &str,
+    p_storage: &dyn Storage,
+) -> Result<String, Error> {
+    let password = match p_storage.get(p_name)? {
+        Some(entry) if !entry.deleted => entry.password,
+        _ => return Err(Error::PasswordDoesntExist(Box::from(p_name))),
+    };
+
+    // Passwords longer than `MAX_PASSWORD_LEN`, like the tokens stored by the
+    // git credential helper, are sealed instead, which makes them longer.
+    if password.len() != MAX_PASSWORD_LEN {
+        let decrypted_password =
+            crypto::open(p_master_key.as_bytes(), &password).ok_or(Error::WrongKeyForData)?;
+        return Ok(String::from_utf8_lossy(&decrypted_password).to_string());
+    }
 
-    let password = row;
     let decrypted_password = crypto::decrypt(p_master_key.as_bytes(), &password);
 
     Ok(String::from_utf8_lossy(&decrypted_password).to_string())
 }
 
-pub fn delete_password(p_name: &str, p_sql_connection: &rusqlite::Connection) -> Result<(), Error> {
-    let mut sql_statement = p_sql_connection.prepare("DELETE FROM passwords WHERE name = ?;")?;
-    sql_statement.execute([p_name])?;
-    Ok(())
+/// Returns the names of all the passwords that have not been deleted.
+#[cfg(feature = "native")]
+pub fn list_passwords(p_storage: &dyn Storage) -> Result<Vec<String>, Error> {
+    let names = p_storage
+        .list()?
+        .into_iter()
+        .filter(|entry| !entry.deleted)
+        .map(|entry| entry.name)
+        .collect();
+
+    Ok(names)
+}
+
+/// Deletes a password. The record is kept as a tombstone (without the
+/// encrypted password) so that merging with another copy of the vault doesn't
+/// bring the password back.
+#[cfg(feature = "native")]
+pub fn delete_password(p_name: &str, p_storage: &dyn Storage) -> Result<(), Error> {
+    match p_storage.get(p_name)? {
+        Some(entry) if !entry.deleted => p_storage.put(&entry::Entry {
+            password: Vec::new(),
+            modified: current_timestamp(),
+            deleted: true,
+            ..entry
+        }),
+        _ => Ok(()),
+    }
 }
@@ -1,13 +1,33 @@
-use std::{io::Write, path::Path};
+use std::{
+    io::{IsTerminal, Read, Write},
+    path::Path,
+    sync::OnceLock,
+};
 
-use clap::{arg, Arg, ArgAction, Command};
-use directories::ProjectDirs;
+use clap::{arg, Arg, ArgAction, ArgMatches, Command};
+use serde_json::json;
 
 fn cli() -> Command {
-    Command::new("neng-pass")
+    let command = Command::new("neng-pass")
         .about("Basic password manager written in Rust (btw)")
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(
+            arg!(--format <FORMAT> "How to print the results. With json, the results (and errors) are printed as JSON on the standard output. Defaults to the format setting of the configuration file.")
+                .global(true)
+                .value_parser(["text", "json"]),
+        )
+        .arg(
+            arg!(--"master-key-file" <PATH> "Read the master key from the first line of this file instead of asking for it.")
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            arg!(--vault <VAULT> "The vault to use: the name of one in the configuration file, or the path of a vault directory or of a .nengvault file.")
+                .global(true)
+                .required(false)
+                .env("NENG_PASS_VAULT"),
+        )
         .subcommand(Command::new("set-master").about("Sets the master key"))
         .subcommand(
             Command::new("new")
@@ -18,6 +38,10 @@ fn cli() -> Command {
             Command::new("save")
                 .about("Saves a new password with the specified name and value.")
                 .arg(arg!(<NAME> "The name to assign to the password."))
+                .arg(
+                    arg!(--"password-file" <PATH> "Read the password from the first line of this file instead of asking for it.")
+                        .required(false),
+                )
         )
         .subcommand(
             Command::new("get")
@@ -40,141 +64,1786 @@ fn cli() -> Command {
                 .about("Deletes a specific password from your password list. Remember that this is not reversible!!!!")
                 .arg(arg!(<NAME> "The name of the password that you want to delete."))
             )
+        .subcommand(
+            Command::new("merge")
+                .about("Merges the passwords from another copy of the vault into this one.")
+                .arg(arg!(<OTHER_DB> "The passwords.db file of the other copy."))
+                .arg(
+                    Arg::new("no-interactive")
+                        .long("no-interactive")
+                        .action(ArgAction::SetTrue)
+                        .help("Don't ask about conflicts, only report them."),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Exports all the passwords into one file, encrypted with the master key.")
+                .arg(arg!(<FILE> "Where to write the export.")),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Imports the passwords from an export. Existing passwords are never overwritten.")
+                .arg(arg!(<FILE> "The export.")),
+        )
+        .subcommand(
+            Command::new("git")
+                .about("Keeps the history of the vault in a git repository and syncs it with git remotes.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("init")
+                        .about("Starts recording the history of the vault in a git repository.")
+                        .arg(arg!([REMOTE] "The URL or path of a remote to sync with.")),
+                )
+                .subcommand(
+                    Command::new("pull")
+                        .about("Fetches the vault from a remote and merges it into this one.")
+                        .arg(arg!([REMOTE] "The remote to pull from.").default_value("origin"))
+                        .arg(arg!([BRANCH] "The branch to pull.").default_value(neng_pass::git::DEFAULT_BRANCH)),
+                )
+                .subcommand(
+                    Command::new("push")
+                        .about("Pushes the history of the vault to a remote.")
+                        .arg(arg!([REMOTE] "The remote to push to.").default_value("origin"))
+                        .arg(arg!([BRANCH] "The branch to push to.").default_value(neng_pass::git::DEFAULT_BRANCH)),
+                ),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Syncs the vault with a neng-pass-server.")
+                .arg(arg!(<URL> "The URL of the server, such as http://localhost:8080."))
+                .arg(
+                    arg!(-t --token <TOKEN> "The access token of the server. Can also be set with NENG_PASS_SYNC_TOKEN.")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("share")
+                .about("Manages collections of passwords that are shared with other people.")
+                .subcommand_required(true)
+                .subcommand(Command::new("identity").about("Shows your public key, which others need to share collections with you."))
+                .subcommand(
+                    Command::new("create")
+                        .about("Creates a new shared collection, with you as its only member.")
+                        .arg(arg!(<COLLECTION> "The file to create the collection in.")),
+                )
+                .subcommand(
+                    Command::new("add-member")
+                        .about("Gives someone access to a shared collection.")
+                        .arg(arg!(<COLLECTION> "The file of the collection."))
+                        .arg(arg!(<PUBLIC_KEY> "The public key of the new member.")),
+                )
+                .subcommand(
+                    Command::new("remove-member")
+                        .about("Takes away someone's access to a shared collection. The collection key is replaced, so they can't read anything saved afterwards.")
+                        .arg(arg!(<COLLECTION> "The file of the collection."))
+                        .arg(arg!(<PUBLIC_KEY> "The public key of the member to remove.")),
+                )
+                .subcommand(
+                    Command::new("members")
+                        .about("Lists the members of a shared collection.")
+                        .arg(arg!(<COLLECTION> "The file of the collection.")),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("Lists the passwords in a shared collection.")
+                        .arg(arg!(<COLLECTION> "The file of the collection.")),
+                )
+                .subcommand(
+                    Command::new("save")
+                        .about("Saves a password into a shared collection.")
+                        .arg(arg!(<COLLECTION> "The file of the collection."))
+                        .arg(arg!(<NAME> "The name to assign to the password."))
+                        .arg(
+                            arg!(--"password-file" <PATH> "Read the password from the first line of this file instead of asking for it.")
+                                .required(false),
+                        ),
+                )
+                .subcommand(
+                    Command::new("get")
+                        .about("Gets a password from a shared collection.")
+                        .arg(arg!(<COLLECTION> "The file of the collection."))
+                        .arg(arg!(<NAME> "The name of the password that you want to get."))
+                        .arg(
+                            Arg::new("raw")
+                                .short('r')
+                                .long("raw")
+                                .action(ArgAction::SetTrue)
+                                .help("Output as a raw output, to be piped into other commands."),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("share-entry")
+                .about("Encrypts a password for someone else, as a blob that they can receive.")
+                .arg(arg!(<NAME> "The name of the password to share."))
+                .arg(arg!(--to <PUBLIC_KEY> "The public key of the person to share it with.")),
+        )
+        .subcommand(
+            Command::new("receive")
+                .about("Saves a password that someone shared with you.")
+                .arg(arg!(<BLOB> "The file containing the blob, or - to read it from the standard input."))
+                .arg(arg!(--as <NAME> "The name to save the password under, instead of the one it was shared with.").required(false)),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Runs a command with passwords from the vault in its environment.")
+                .arg(
+                    arg!(-e --env <ASSIGNMENT> "Sets the variable to a password, as VARIABLE=name. Can be given several times.")
+                        .required(false)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    arg!(--dotenv <FILE> "Reads the variables from a dotenv file, where passwords are referred to as VARIABLE=neng://name.")
+                        .required(false)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("COMMAND")
+                        .help("The command to run, and its arguments.")
+                        .required(true)
+                        .num_args(1..)
+                        .last(true),
+                ),
+        )
+        .subcommand(
+            Command::new("inject")
+                .about("Renders a template, replacing references like {{ neng \"name\" \"password\" }} with values from the vault.")
+                .arg(arg!(-i --input <FILE> "The template, or - to read it from the standard input."))
+                .arg(arg!(-o --output <FILE> "Where to write the result, readable only by you. Printed to the standard output if not given.").required(false)),
+        )
+        .subcommand(
+            Command::new("git-credential")
+                .about("Git credential helper, use it with git config credential.helper '!neng-pass git-credential'.")
+                .arg(arg!(<OPERATION> "The operation git asks for: get, store or erase.")),
+        )
+        .subcommand(
+            Command::new("url")
+                .about("Manages the URLs that entries are offered for when autofilling.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Records a URL for an entry.")
+                        .arg(arg!(<NAME> "The name of the entry."))
+                        .arg(arg!(<URL> "The URL, or a regular expression with --mode regex."))
+                        .arg(
+                            arg!(-m --mode <MODE> "Which pages the URL matches.")
+                                .value_parser(neng_pass::autofill::MatchMode::NAMES.to_vec())
+                                .default_value("domain"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Removes a URL from an entry.")
+                        .arg(arg!(<NAME> "The name of the entry."))
+                        .arg(arg!(<URL> "The URL to remove.")),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("Lists the URLs of an entry, or of every entry.")
+                        .arg(arg!([NAME] "The name of the entry.")),
+                )
+                .subcommand(
+                    Command::new("match")
+                        .about("Lists the entries that would be offered for a page, the best matches first.")
+                        .arg(arg!(<URL> "The URL of the page.")),
+                ),
+        )
+        .subcommand(
+            Command::new("equivalent-domains")
+                .about("Manages the groups of domains that share their logins, like google.com and youtube.com.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Makes domains equivalent to each other.")
+                        .arg(arg!(<DOMAINS> ... "The domains.")),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Removes a domain from its group.")
+                        .arg(arg!(<DOMAIN> "The domain.")),
+                )
+                .subcommand(Command::new("list").about("Lists the groups of equivalent domains.")),
+        )
+        .subcommand(
+            Command::new("app-link")
+                .about("Manages the Android apps that share their logins with a domain.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Offers an app the entries for a domain.")
+                        .arg(arg!(<PACKAGE> "The package name of the app, like com.github.android."))
+                        .arg(arg!(<DOMAIN> "The domain.")),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Unlinks an app from a domain.")
+                        .arg(arg!(<PACKAGE> "The package name of the app."))
+                        .arg(arg!(<DOMAIN> "The domain.")),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("Lists the domains linked to an app, or to every app.")
+                        .arg(arg!([PACKAGE] "The package name of the app.")),
+                ),
+        )
+        .subcommand(
+            Command::new("browser")
+                .about("Manages the Android apps trusted to report the domain of the page they show.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Trusts a browser.")
+                        .arg(arg!(<PACKAGE> "The package name of the browser, like org.mozilla.firefox.")),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Stops trusting a browser.")
+                        .arg(arg!(<PACKAGE> "The package name of the browser.")),
+                )
+                .subcommand(Command::new("list").about("Lists the trusted browsers.")),
+        )
+        .subcommand(
+            Command::new("ssh-key")
+                .about("Manages the SSH keys stored in the vault.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Stores an OpenSSH private key in the vault.")
+                        .arg(arg!(<NAME> "The name of the key."))
+                        .arg(arg!(<FILE> "The private key file, such as ~/.ssh/id_ed25519."))
+                        .arg(arg!(--confirm "Ask for confirmation every time the key is used.")),
+                )
+                .subcommand(Command::new("list").about("Lists the SSH keys and their public keys."))
+                .subcommand(
+                    Command::new("delete")
+                        .about("Deletes an SSH key from the vault.")
+                        .arg(arg!(<NAME> "The name of the key.")),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Reads and changes the configuration file.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("get")
+                        .about("Shows the value of a setting, like generator.length or vaults.work.lock_timeout.")
+                        .arg(arg!(<KEY> "The setting.")),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Changes a setting, like generator.length or vaults.work.lock_timeout.")
+                        .arg(arg!(<KEY> "The setting."))
+                        .arg(arg!(<VALUE> "The new value.")),
+                )
+                .subcommand(Command::new("list").about("Lists the vaults, and the settings of the vault picked with --vault.")),
+        );
+
+    // Passing secrets through file descriptors only works where they exist.
+    #[cfg(unix)]
+    let command = command
+        .arg(
+            arg!(--"master-key-fd" <FD> "Read the master key from this file descriptor instead of asking for it.")
+                .global(true)
+                .required(false)
+                // 0, 1 and 2 are the standard streams.
+                .value_parser(clap::value_parser!(i32).range(3..)),
+        )
+        .mut_subcommand("save", |save| {
+            save.arg(
+                arg!(--"password-fd" <FD> "Read the password from this file descriptor instead of asking for it.")
+                    .required(false)
+                    .value_parser(clap::value_parser!(i32).range(3..)),
+            )
+        });
+
+    // The agents listen on Unix sockets.
+    #[cfg(unix)]
+    let command = command
+        .subcommand(
+            Command::new("ssh-agent")
+                .about("Runs an SSH agent with the keys in the vault, usable while the vault is unlocked.")
+                .arg(arg!(-s --socket <PATH> "Where to create the socket, the data directory by default.").required(false)),
+        )
+        .subcommand(
+            Command::new("agent")
+                .about("Runs the agent that remembers the master key, in the foreground.")
+                .arg(arg!(--timeout <SECONDS> "Forget the master key after this many seconds without use.").required(false).value_parser(clap::value_parser!(u64))),
+        )
+        .subcommand(
+            Command::new("unlock")
+                .about("Enters the master key once, and keeps it in the agent for the next commands.")
+                .arg(arg!(--timeout <SECONDS> "Forget the master key after this many seconds without use.").required(false).value_parser(clap::value_parser!(u64))),
+        )
+        .subcommand(Command::new("lock").about("Makes the agent forget the master key."));
+
+    #[cfg(feature = "clipboard")]
+    let command = command.mut_subcommand("get", |get| {
+        get.arg(
+            Arg::new("copy")
+                .short('c')
+                .long("copy")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("raw")
+                .help("Copy the password to the clipboard instead, and clear it after clipboard_clear_delay seconds."),
+        )
+    });
+
+    command
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+static OUTPUT_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+/// The master key a single-file vault was opened with. Those have to be opened
+/// before anything else, so the key isn't asked for again.
+static VAULT_FILE_KEY: OnceLock<String> = OnceLock::new();
+
+/// Commands that need the files of a vault directory, so they don't work with
+/// single-file vaults.
+const DIRECTORY_ONLY_COMMANDS: &[&str] = &["git", "ssh-agent", "agent", "unlock", "lock"];
+
+fn is_json_output() -> bool {
+    OUTPUT_FORMAT.get() == Some(&OutputFormat::Json)
+}
+
+/// Prints an error that didn't come from the library, and exits.
+fn fail_with(p_code: &str, p_message: &str, p_exit_code: i32) -> ! {
+    if is_json_output() {
+        println!(
+            "{}",
+            json!({ "error": { "code": p_code, "message": p_message } })
+        );
+    } else {
+        eprintln!("[ERROR]: {}", p_message);
+    }
+
+    std::process::exit(p_exit_code);
+}
+
+/// Prints an error, and exits with the exit code for its kind.
+fn fail(p_error: &neng_pass::Error) -> ! {
+    fail_with(p_error.code(), &p_error.get_message(), p_error.exit_code());
+}
+
+/// Prints the result of a command when the output format is JSON. In text
+/// mode, commands print their own human readable messages instead.
+fn print_json(p_value: serde_json::Value) {
+    if is_json_output() {
+        println!("{}", p_value);
+    }
+}
+
+/// Passwords are stored padded with zeroes to a fixed length.
+fn trim_password(p_password: &str) -> &str {
+    p_password.trim_end_matches('\0')
+}
+
+/// Where a secret comes from, when it isn't typed in at a prompt. Secrets can
+/// be read from a file descriptor, a file, or the output of a helper command
+/// (named by an environment variable). Otherwise they are asked for, or read
+/// line by line from the standard input when it isn't a terminal.
+struct SecretInput {
+    fd: Option<i32>,
+    file: Option<String>,
+    command: Option<String>,
+    /// Whether the standard input may be read from when it isn't a terminal.
+    stdin: bool,
+}
+
+impl SecretInput {
+    fn from_matches(p_matches: &ArgMatches, p_name: &str, p_command_variable: &str) -> SecretInput {
+        let fd = p_matches
+            .try_get_one::<i32>(&format!("{}-fd", p_name))
+            .ok()
+            .flatten()
+            .copied();
+        let file = p_matches
+            .try_get_one::<String>(&format!("{}-file", p_name))
+            .ok()
+            .flatten()
+            .cloned();
+
+        SecretInput {
+            fd,
+            file,
+            command: std::env::var(p_command_variable).ok(),
+            stdin: true,
+        }
+    }
+
+    /// For commands that use the standard input for something else.
+    fn without_stdin(self) -> SecretInput {
+        SecretInput {
+            stdin: false,
+            ..self
+        }
+    }
+
+    /// Whether the secret was explicitly given a source.
+    fn is_explicit(&self) -> bool {
+        self.fd.is_some() || self.file.is_some() || self.command.is_some()
+    }
+
+    fn try_read(&self, p_prompt: &str) -> Result<String, String> {
+        #[cfg(unix)]
+        if let Some(fd) = self.fd {
+            use std::os::unix::io::FromRawFd;
+
+            // SAFETY: F_GETFD only looks the descriptor up.
+            if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                return Err(format!("File descriptor {} isn't open.", fd));
+            }
+
+            // SAFETY: the descriptor is open, and is never closed here: it
+            // belongs to whoever passed it, and may be read from again.
+            let mut file = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+            let line = read_line_unbuffered(&mut *file)
+                .map_err(|err| format!("Failed to read from file descriptor {}: {}", fd, err))?;
+            return Ok(first_line(&line));
+        }
+
+        if let Some(file) = &self.file {
+            let content = std::fs::read_to_string(file)
+                .map_err(|err| format!("Failed to read {}: {}", file, err))?;
+            return Ok(first_line(&content));
+        }
+
+        if let Some(command) = &self.command {
+            #[cfg(unix)]
+            let output = std::process::Command::new("sh").arg("-c").arg(command).output();
+            #[cfg(not(unix))]
+            let output = std::process::Command::new("cmd").arg("/C").arg(command).output();
+
+            let output = output.map_err(|err| format!("Failed to run '{}': {}", command, err))?;
+            if !output.status.success() {
+                return Err(format!("'{}' failed with {}", command, output.status));
+            }
+
+            return Ok(first_line(&String::from_utf8_lossy(&output.stdout)));
+        }
+
+        if self.stdin && !std::io::stdin().is_terminal() {
+            let mut line = String::new();
+            std::io::stdin()
+                .read_line(&mut line)
+                .map_err(|err| format!("Failed to read from the standard input: {}", err))?;
+            return Ok(first_line(&line));
+        }
+
+        rpassword::prompt_password(p_prompt).map_err(|err| err.to_string())
+    }
+
+    fn read(&self, p_prompt: &str) -> String {
+        match self.try_read(p_prompt) {
+            Ok(secret) => secret,
+            Err(err) => fail_with("input_error", &err, 6),
+        }
+    }
+}
+
+/// Reads one line a byte at a time, so that nothing after it is consumed.
+#[cfg(unix)]
+fn read_line_unbuffered(p_reader: &mut impl Read) -> std::io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    while p_reader.read(&mut byte)? == 1 && byte[0] != b'\n' {
+        line.push(byte[0]);
+    }
+
+    Ok(String::from_utf8_lossy(&line).to_string())
+}
+
+/// Copies a password to the clipboard, and clears it once the delay has
+/// passed, unless something else was copied in the meantime. On Linux the
+/// clipboard only holds what a running program offers, so this returns once
+/// the delay has passed or something else was copied, and with no delay only
+/// once something else was copied.
+#[cfg(feature = "clipboard")]
+fn copy_to_clipboard(p_password: &str, p_delay: std::time::Duration) -> Result<(), arboard::Error> {
+    let mut clipboard = arboard::Clipboard::new()?;
+
+    #[cfg(target_os = "linux")]
+    {
+        use arboard::SetExtLinux;
+
+        let set = clipboard.set();
+        let set = if p_delay.is_zero() {
+            set.wait()
+        } else {
+            set.wait_until(std::time::Instant::now() + p_delay)
+        };
+        set.text(p_password)?;
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        clipboard.set_text(p_password)?;
+        std::thread::sleep(p_delay);
+    }
+
+    if !p_delay.is_zero() && clipboard.get_text().ok().as_deref() == Some(p_password) {
+        clipboard.clear()?;
+    }
+
+    Ok(())
+}
+
+fn first_line(p_text: &str) -> String {
+    p_text.lines().next().unwrap_or("").to_string()
+}
+
+#[cfg(unix)]
+fn agent_socket_path(p_data_dir: &Path) -> std::path::PathBuf {
+    p_data_dir.join("agent.sock")
 }
 
-fn ask_for_password(p_master_key_file: &str) -> String {
-    let user_input_password = rpassword::prompt_password("Enter the master key: ").unwrap();
+fn ask_for_password(p_master_key_file: &str, p_input: &SecretInput) -> String {
+    if let Some(key) = VAULT_FILE_KEY.get() {
+        return key.clone();
+    }
+
+    #[cfg(unix)]
+    if p_input.is_explicit() {
+        // An explicitly given key takes precedence over the agent.
+    } else if let Some(data_dir) = Path::new(p_master_key_file).parent() {
+        if let Some(key) = neng_pass::agent::get_master_key(&agent_socket_path(data_dir)) {
+            return key;
+        }
+    }
+
+    let user_input_password = p_input.read("Enter the master key: ");
     match neng_pass::query_master_key(p_master_key_file, &user_input_password) {
         Ok(key) => key,
-        Err(err) => {
-            eprintln!("[ERROR]: {}", err.get_message());
-            std::process::exit(1);
+        Err(err) => fail(&err),
+    }
+}
+
+fn describe_entry(p_entry: Option<&neng_pass::merge::Entry>) -> String {
+    let entry = match p_entry {
+        Some(entry) => entry,
+        None => return "doesn't exist".to_string(),
+    };
+
+    let minutes_ago = (neng_pass::current_timestamp() - entry.modified) / 60_000;
+    let when = if entry.modified == 0 {
+        "at an unknown time".to_string()
+    } else if minutes_ago < 60 {
+        format!("{} minutes ago", minutes_ago)
+    } else if minutes_ago < 60 * 24 {
+        format!("{} hours ago", minutes_ago / 60)
+    } else {
+        format!("{} days ago", minutes_ago / (60 * 24))
+    };
+
+    if entry.deleted {
+        format!("deleted {}", when)
+    } else {
+        format!("changed {}", when)
+    }
+}
+
+fn ask_for_resolution(p_conflict: &neng_pass::merge::Conflict) -> Option<neng_pass::merge::Resolution> {
+    use neng_pass::merge::Resolution;
+
+    eprintln!("\nConflict on '{}':", p_conflict.name);
+    eprintln!("\t - here: {}", describe_entry(p_conflict.local.as_ref()));
+    eprintln!("\t - other copy: {}", describe_entry(p_conflict.other.as_ref()));
+
+    loop {
+        eprint!("Keep [l]ocal, keep [o]ther, keep [b]oth or [s]kip? ");
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+            return None;
+        }
+
+        match answer.trim() {
+            "l" => return Some(Resolution::KeepLocal),
+            "o" => return Some(Resolution::KeepOther),
+            "b" => return Some(Resolution::KeepBoth),
+            "s" => return None,
+            _ => eprintln!("Please answer with l, o, b or s."),
         }
     }
 }
 
-fn main() {
-    let project_dirs = ProjectDirs::from("io", "earthtraveller1", "neng-pass");
-    let data_dir = match project_dirs.as_ref() {
-        Some(project_dirs) => project_dirs.data_dir(),
-        None => Path::new("."),
+fn merge_report_json(p_report: &neng_pass::merge::MergeReport) -> serde_json::Value {
+    json!({
+        "added": p_report.added,
+        "updated": p_report.updated,
+        "deleted": p_report.deleted,
+        "renamed": p_report
+            .renamed
+            .iter()
+            .map(|(name, new_name)| json!({ "from": name, "to": new_name }))
+            .collect::<Vec<_>>(),
+        "unresolved": p_report
+            .unresolved
+            .iter()
+            .map(|conflict| &conflict.name)
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn print_merge_report(p_report: &neng_pass::merge::MergeReport) {
+    if is_json_output() {
+        return;
+    }
+
+    p_report.added.iter().for_each(|name| eprintln!("Added '{}'", name));
+    p_report.updated.iter().for_each(|name| eprintln!("Updated '{}'", name));
+    p_report.deleted.iter().for_each(|name| eprintln!("Deleted '{}'", name));
+    p_report
+        .renamed
+        .iter()
+        .for_each(|(name, new_name)| eprintln!("Kept the other '{}' as '{}'", name, new_name));
+
+    if !p_report.unresolved.is_empty() {
+        eprintln!("\nThese passwords were changed in both copies and were left as they are here:\n");
+        for conflict in &p_report.unresolved {
+            eprintln!(
+                "\t - {} (here: {}, other copy: {})",
+                conflict.name,
+                describe_entry(conflict.local.as_ref()),
+                describe_entry(conflict.other.as_ref())
+            );
+        }
+    }
+}
+
+/// Asks whether an SSH key may be used, the way OpenSSH's agent does, with the
+/// program in SSH_ASKPASS.
+#[cfg(unix)]
+fn confirm_key_use(p_key: &neng_pass::ssh::StoredKey) -> bool {
+    let askpass = std::env::var("SSH_ASKPASS").unwrap_or_else(|_| "ssh-askpass".to_string());
+
+    std::process::Command::new(askpass)
+        .arg(format!("Allow use of the SSH key '{}' from the vault?", p_key.name))
+        .env("SSH_ASKPASS_PROMPT", "confirm")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+/// Records a change in the git history of the vault, if it has one.
+fn record_change(p_repo: Option<&Path>, p_sql_connection: &rusqlite::Connection, p_message: &str) {
+    let repo = match p_repo {
+        Some(repo) if neng_pass::git::is_enabled(repo) => repo,
+        _ => return,
     };
 
-    let data_dir = data_dir.to_str().unwrap();
+    if let Err(err) = neng_pass::git::commit(repo, p_sql_connection, p_message) {
+        eprintln!(
+            "[WARNING]: The change was saved, but couldn't be committed to git. {}",
+            err.get_message()
+        );
+    }
+}
+
+/// Writes a single-file vault back to its file, if the command changed it.
+fn save_vault_file(p_vault_file: Option<&mut neng_pass::vault_file::VaultFile>) {
+    if let Some(vault_file) = p_vault_file {
+        if let Err(err) = vault_file.save() {
+            fail(&err);
+        }
+    }
+}
+
+/// Prints a value from the configuration, without quotes around strings.
+fn config_value_to_string(p_value: &toml::Value) -> String {
+    match p_value {
+        toml::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn run_config_command(p_config: &mut neng_pass::config::Config, p_vault: Option<&str>, p_matches: &ArgMatches) {
+    match p_matches.subcommand() {
+        Some(("get", get_matches)) => {
+            let key = get_matches.get_one::<String>("KEY").unwrap();
+            let value = match p_config.get(key) {
+                Ok(value) => value,
+                Err(err) => fail(&err),
+            };
+
+            if is_json_output() {
+                print_json(json!({ "key": key, "value": value }));
+            } else {
+                println!("{}", config_value_to_string(&value));
+            }
+        }
+        Some(("set", set_matches)) => {
+            let key = set_matches.get_one::<String>("KEY").unwrap();
+            let value = set_matches.get_one::<String>("VALUE").unwrap();
+
+            if let Err(err) = p_config.set(key, value).and_then(|_| p_config.save()) {
+                fail(&err);
+            }
+
+            print_json(json!({ "key": key, "value": p_config.get(key).ok() }));
+            eprintln!("Set {} in {}", key, p_config.file().display());
+        }
+        Some(("list", _)) => {
+            let keys = p_config.list(p_vault);
+
+            if is_json_output() {
+                let keys = keys
+                    .iter()
+                    .map(|(key, value)| json!({ "key": key, "value": value }))
+                    .collect::<Vec<_>>();
+                print_json(json!({ "file": p_config.file(), "settings": keys }));
+                return;
+            }
+
+            eprintln!("Here are the settings from {}\n", p_config.file().display());
+
+            keys.iter().for_each(|(key, value)| {
+                println!("{} = {}", key, value);
+            });
+        }
+        _ => unreachable!(),
+    }
+}
 
+fn main() {
     let cli_matches = cli().get_matches();
-    // Ensure that the data directory exists in the first place.
-    if let Err(err) = std::fs::create_dir_all(data_dir) {
-        eprintln!("[ERROR]: Failed to create the data directory. {}", err);
-        std::process::exit(1);
+    let vault = cli_matches.get_one::<String>("vault").map(|vault| vault.as_str());
+
+    // The output format can be set in the configuration, so it is read first,
+    // but its errors are only shown once the format is known.
+    let config = neng_pass::config::Config::load(&neng_pass::config::config_file());
+    let settings = config
+        .as_ref()
+        .map(|config| config.settings(vault))
+        .unwrap_or_default();
+
+    let output_format = match cli_matches
+        .get_one::<String>("format")
+        .unwrap_or(&settings.format)
+        .as_str()
+    {
+        "json" => OutputFormat::Json,
+        _ => OutputFormat::Text,
+    };
+    OUTPUT_FORMAT.set(output_format).unwrap();
+
+    let mut config = match config {
+        Ok(config) => config,
+        Err(err) => fail(&err),
+    };
+
+    // The configuration is about every vault, so none is opened for it.
+    if let Some(("config", sub_matches)) = cli_matches.subcommand() {
+        run_config_command(&mut config, vault, sub_matches);
+        return;
     }
 
-    eprintln!("[INFO]: Program data are stored in {}", data_dir);
+    let location = match config.resolve(vault) {
+        Ok(location) => location,
+        // Vaults that don't exist yet can be created by their path.
+        Err(_) if cli_matches.subcommand_name() == Some("set-master") => config.resolve_new(vault),
+        Err(err) => fail(&err),
+    };
 
-    let sql_connection = rusqlite::Connection::open(format!("{}/passwords.db", data_dir)).unwrap();
-    sql_connection
-        .execute("CREATE TABLE IF NOT EXISTS passwords (name TEXT, password BLOB);", ())
-        .unwrap();
+    let vault_file_path = match &location {
+        neng_pass::vault::Location::File(path) => Some(path.as_path()),
+        neng_pass::vault::Location::Directory(_) => None,
+    };
+    // Single-file vaults have no data directory, and the commands that need
+    // one are refused for them below.
+    let data_dir = location.path().to_str().unwrap();
 
     let master_key_path = format!("{}/master_key", data_dir);
+    let master_key_input = SecretInput::from_matches(&cli_matches, "master-key", "NENG_PASS_MASTER_KEY_COMMAND");
+
+    // A single-file vault can only be read with the master key, so it is
+    // opened before running the command, unless the command creates it.
+    let mut vault_file = match vault_file_path {
+        Some(path) if cli_matches.subcommand_name() != Some("set-master") => {
+            if let Some(command) = cli_matches
+                .subcommand_name()
+                .filter(|command| DIRECTORY_ONLY_COMMANDS.contains(command))
+            {
+                fail_with(
+                    "unsupported",
+                    &format!("The {} command doesn't work with single-file vaults.", command),
+                    6,
+                );
+            }
+
+            // Git sends its credential requests on the standard input.
+            let input = if cli_matches.subcommand_name() == Some("git-credential") {
+                SecretInput::from_matches(&cli_matches, "master-key", "NENG_PASS_MASTER_KEY_COMMAND").without_stdin()
+            } else {
+                SecretInput::from_matches(&cli_matches, "master-key", "NENG_PASS_MASTER_KEY_COMMAND")
+            };
+
+            let master_key = input.read("Enter the master key: ");
+            let mut vault_file = match neng_pass::vault_file::VaultFile::open(path, &master_key) {
+                Ok(vault_file) => vault_file,
+                Err(err) => fail(&err),
+            };
+            vault_file.set_backups(settings.backups);
+
+            VAULT_FILE_KEY.set(master_key).unwrap();
+            Some(vault_file)
+        }
+        _ => None,
+    };
+
+    // Ensure that the data directory exists in the first place.
+    if vault_file_path.is_none() {
+        if let Err(err) = std::fs::create_dir_all(data_dir) {
+            fail_with(
+                "io_error",
+                &format!("Failed to create the data directory. {}", err),
+                7,
+            );
+        }
+    }
+
+    // Git shows whatever its credential helper prints.
+    if cli_matches.subcommand_name() != Some("git-credential") {
+        match vault_file_path {
+            Some(path) => eprintln!("[INFO]: The vault is {}", path.display()),
+            None => eprintln!("[INFO]: Program data are stored in {}", data_dir),
+        }
+    }
+
+    let directory_connection;
+    let sql_connection = match &vault_file {
+        Some(vault_file) => vault_file.sql_connection(),
+        None => {
+            directory_connection = rusqlite::Connection::open(format!("{}/passwords.db", data_dir)).unwrap();
+            if let Err(err) = neng_pass::prepare_database(&directory_connection) {
+                fail(&err);
+            }
+            &directory_connection
+        }
+    };
+
+    // Single-file vaults have no history.
+    let git_repo = vault_file_path
+        .is_none()
+        .then(|| Path::new(data_dir).join("git"));
 
     match cli_matches.subcommand() {
         Some(("set-master", _)) => {
-            let new_key = rpassword::prompt_password("Enter a new master key: ").unwrap();
-            let new_key_confirmation =
-                rpassword::prompt_password("Confirm your master password: ").unwrap();
+            let new_key = master_key_input.read("Enter a new master key: ");
+            // There's nobody to make typos when the key isn't typed in.
+            let new_key_confirmation = if master_key_input.is_explicit() || !std::io::stdin().is_terminal() {
+                new_key.clone()
+            } else {
+                rpassword::prompt_password("Confirm your master password: ").unwrap()
+            };
             if new_key != new_key_confirmation {
-                eprintln!("The passwords you entered do not match!");
-                std::process::exit(1);
+                fail_with("confirmation_mismatch", "The passwords you entered do not match!", 6);
+            }
+
+            if let Some(path) = vault_file_path {
+                if let Err(err) = neng_pass::vault_file::VaultFile::create(path, &new_key) {
+                    fail(&err);
+                }
+
+                print_json(json!({ "ok": true }));
+                eprintln!("Successfully created the vault {}", path.display());
+                return;
             }
 
             if let Err(err) =
                 neng_pass::set_master_key(format!("{}/master_key", data_dir).as_str(), &new_key)
             {
-                eprintln!("[ERROR]: {}", err.get_message());
-                std::process::exit(1);
+                fail(&err);
             }
 
+            print_json(json!({ "ok": true }));
             eprintln!("Successfully updated the master key file.");
         }
         Some(("new", sub_matches)) => {
-            let master_key = ask_for_password(&master_key_path);
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
             let name = sub_matches.get_one::<String>("NAME").unwrap();
 
             if let Err(err) = neng_pass::create_password(
                 master_key,
-                &name,
-                std::str::from_utf8(&neng_pass::generate_password()).unwrap(),
-                &sql_connection,
+                name,
+                &neng_pass::generate_password_with(&settings.generator),
+                sql_connection,
             ) {
-                eprintln!("[ERROR]: {}", err.get_message());
-                std::process::exit(1);
+                fail(&err);
             }
 
+            record_change(git_repo.as_deref(), sql_connection, &format!("Create {}", name));
+            print_json(json!({ "name": name }));
             eprintln!("Created and saved password named '{}'", name);
         }
         Some(("save", sub_matches)) => {
-            let master_key = ask_for_password(&master_key_path);
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
             let name = sub_matches.get_one::<String>("NAME").unwrap();
-            let password = rpassword::prompt_password("Enter the password to save: ").unwrap();
+            let password = SecretInput::from_matches(sub_matches, "password", "NENG_PASS_PASSWORD_COMMAND")
+                .read("Enter the password to save: ");
 
             if let Err(err) =
-                neng_pass::create_password(master_key, name, &password, &sql_connection)
+                neng_pass::create_password(master_key, name, &password, sql_connection)
             {
-                eprintln!("[ERROR]: {}", err.get_message());
-                std::process::exit(1);
+                fail(&err);
             }
+
+            record_change(git_repo.as_deref(), sql_connection, &format!("Save {}", name));
+            print_json(json!({ "name": name }));
         }
         Some(("get", sub_matches)) => {
-            let master_key = ask_for_password(&master_key_path);
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
             let name = sub_matches.get_one::<String>("NAME").unwrap();
             let decrypted_password =
-                match neng_pass::get_password(master_key, &name, &sql_connection) {
+                match neng_pass::get_password(master_key, name, sql_connection) {
                     Ok(password) => password,
-                    Err(err) => {
-                        eprintln!("[ERROR]: {}", err.get_message());
-                        std::process::exit(1);
-                    }
+                    Err(err) => fail(&err),
             };
 
+            #[cfg(feature = "clipboard")]
+            if sub_matches.get_flag("copy") {
+                let delay = settings.clipboard_clear_delay;
+                print_json(json!({ "name": name, "copied": true, "clear_delay": delay.as_secs() }));
+                if delay.is_zero() {
+                    eprintln!("Copied {} to the clipboard.", name);
+                } else {
+                    eprintln!("Copied {} to the clipboard, for {} seconds.", name, delay.as_secs());
+                }
+
+                if let Err(err) = copy_to_clipboard(trim_password(&decrypted_password), delay) {
+                    fail_with("clipboard_error", &err.to_string(), 8);
+                }
+                return;
+            }
+
             let raw_mode = sub_matches.get_flag("raw");
-            if raw_mode {
+            if is_json_output() {
+                print_json(json!({ "name": name, "password": trim_password(&decrypted_password) }));
+            } else if raw_mode {
                 std::io::stdout()
-                    .write(decrypted_password.as_bytes())
+                    .write_all(decrypted_password.as_bytes())
                     .unwrap();
             } else {
                 eprintln!("Here's the password: {}", decrypted_password);
             }
         }
         Some(("list", _)) => {
-            ask_for_password(&master_key_path);
+            ask_for_password(&master_key_path, &master_key_input);
+
+            let names = match neng_pass::list_passwords(sql_connection) {
+                Ok(names) => names,
+                Err(err) => fail(&err),
+            };
 
-            let sql_query = "SELECT name FROM passwords;";
-            let mut sql_statement = sql_connection.prepare(sql_query).unwrap();
+            if is_json_output() {
+                print_json(json!({ "passwords": names }));
+                return;
+            }
 
             eprintln!("Here is the list of passwords that you have stored.\n");
 
-            sql_statement
-                .query_map([], |row| row.get::<_, String>(0))
-                .unwrap()
-                .for_each(|row| {
-                    let name: String = row.unwrap();
-                    eprintln!("\t - {}", name);
-                });
+            names.iter().for_each(|name| {
+                eprintln!("\t - {}", name);
+            });
         }
         Some(("delete", sub_matches)) => {
-            ask_for_password(&master_key_path);
+            ask_for_password(&master_key_path, &master_key_input);
             let name: &String = sub_matches.get_one("NAME").unwrap();
 
-            if let Err(err) = neng_pass::delete_password(name.as_str(), &sql_connection) {
-                eprintln!("[ERROR]: {}", err.get_message());
-                std::process::exit(1);
+            if let Err(err) = neng_pass::delete_password(name.as_str(), sql_connection) {
+                fail(&err);
             }
 
+            record_change(git_repo.as_deref(), sql_connection, &format!("Delete {}", name));
+            print_json(json!({ "name": name }));
             eprintln!("I have deleted all the passwords named '{}'", name);
         }
+        Some(("merge", sub_matches)) => {
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
+            let master_key = match neng_pass::MasterKey::new(&master_key) {
+                Ok(master_key) => master_key,
+                Err(err) => fail(&err),
+            };
+            let other_path: &String = sub_matches.get_one("OTHER_DB").unwrap();
+            let interactive = !sub_matches.get_flag("no-interactive");
+
+            // The other copy is only read, never upgraded or created.
+            let other = match neng_pass::merge::read_database_file(Path::new(other_path)) {
+                Ok(other) => other,
+                Err(err) => fail(&err),
+            };
+
+            // The merge base is recorded for this copy, wherever it was
+            // opened from.
+            let peer = std::fs::canonicalize(other_path)
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|_| other_path.clone());
+
+            let report = neng_pass::merge::merge_databases(&master_key, sql_connection, &peer, &other, |conflict| {
+                if interactive {
+                    ask_for_resolution(conflict)
+                } else {
+                    None
+                }
+            });
+
+            let report = match report {
+                Ok(report) => report,
+                Err(err) => fail(&err),
+            };
+
+            record_change(git_repo.as_deref(), sql_connection, &format!("Merge {}", other_path));
+            print_json(merge_report_json(&report));
+            print_merge_report(&report);
+            if !report.unresolved.is_empty() {
+                save_vault_file(vault_file.as_mut());
+                std::process::exit(2);
+            }
+
+            eprintln!("Merged {} into this vault.", other_path);
+        }
+        Some(("export", sub_matches)) => {
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
+            let file: &String = sub_matches.get_one("FILE").unwrap();
+
+            let export = match neng_pass::export::export_vault(&master_key, &master_key, sql_connection) {
+                Ok(export) => export,
+                Err(err) => fail(&err),
+            };
+
+            if let Err(err) = neng_pass::write_private_file(Path::new(file), &export) {
+                fail_with("io_error", &format!("Failed to write {}: {}", file, err), 7);
+            }
+
+            print_json(json!({ "file": file }));
+            eprintln!("Exported the passwords to {}", file);
+        }
+        Some(("import", sub_matches)) => {
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
+            let file: &String = sub_matches.get_one("FILE").unwrap();
+
+            let export = match std::fs::read(file) {
+                Ok(export) => export,
+                Err(err) => fail_with("io_error", &format!("Failed to read {}: {}", file, err), 7),
+            };
+
+            let mut report = neng_pass::export::import_vault(&master_key, &master_key, &export, sql_connection);
+            if let Err(neng_pass::Error::WrongKeyForData) = report {
+                // The export may come from a vault with another master key.
+                if std::io::stdin().is_terminal() {
+                    let passphrase = rpassword::prompt_password("Enter the master key the export was made with: ").unwrap();
+                    report = neng_pass::export::import_vault(&master_key, &passphrase, &export, sql_connection);
+                }
+            }
+
+            let report = match report {
+                Ok(report) => report,
+                Err(err) => fail(&err),
+            };
+
+            record_change(git_repo.as_deref(), sql_connection, &format!("Import {}", file));
+            print_json(json!({ "added": report.added, "skipped": report.skipped }));
+            report.added.iter().for_each(|name| eprintln!("Added '{}'", name));
+            if !report.skipped.is_empty() {
+                eprintln!("\nThese passwords already exist with another value, and were left as they are:\n");
+                report.skipped.iter().for_each(|name| eprintln!("\t - {}", name));
+            }
+        }
+        Some(("git", sub_matches)) => {
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
+            let master_key = match neng_pass::MasterKey::new(&master_key) {
+                Ok(master_key) => master_key,
+                Err(err) => fail(&err),
+            };
+            let repo = git_repo.unwrap();
+
+            let result = match sub_matches.subcommand() {
+                Some(("init", init_matches)) => {
+                    let remote = init_matches.get_one::<String>("REMOTE");
+                    neng_pass::git::init(&repo, remote.map(|remote| remote.as_str()), sql_connection)
+                        .map(|_| {
+                            print_json(json!({ "repository": repo }));
+                            eprintln!("The history of the vault is now kept in {}", repo.display());
+                        })
+                }
+                Some(("pull", pull_matches)) => {
+                    let remote = pull_matches.get_one::<String>("REMOTE").unwrap();
+                    let branch = pull_matches.get_one::<String>("BRANCH").unwrap();
+                    neng_pass::git::pull(&master_key, &repo, sql_connection, remote, branch, ask_for_resolution)
+                        .map(|report| {
+                            print_json(merge_report_json(&report));
+                            print_merge_report(&report);
+                            if !report.unresolved.is_empty() {
+                                std::process::exit(2);
+                            }
+                            eprintln!("Pulled {} from {}.", branch, remote);
+                        })
+                }
+                Some(("push", push_matches)) => {
+                    let remote = push_matches.get_one::<String>("REMOTE").unwrap();
+                    let branch = push_matches.get_one::<String>("BRANCH").unwrap();
+                    neng_pass::git::push(&repo, sql_connection, remote, branch)
+                        .map(|_| {
+                            print_json(json!({ "ok": true }));
+                            eprintln!("Pushed the vault to {} on {}.", branch, remote);
+                        })
+                }
+                _ => unreachable!(),
+            };
+
+            if let Err(err) = result {
+                fail(&err);
+            }
+        }
+        Some(("sync", sub_matches)) => {
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
+            let url = sub_matches.get_one::<String>("URL").unwrap();
+            let token = sub_matches
+                .get_one::<String>("token")
+                .cloned()
+                .or_else(|| std::env::var("NENG_PASS_SYNC_TOKEN").ok());
+
+            let client = neng_pass::sync::SyncClient::new(url, token.as_deref());
+
+            let result = neng_pass::sync::SyncKeys::fetch(&client, &master_key).and_then(|keys| {
+                let merge_report = neng_pass::sync::pull(&client, &keys, sql_connection, ask_for_resolution)?;
+                let push_report = neng_pass::sync::push(&client, &keys, sql_connection)?;
+                Ok((merge_report, push_report))
+            });
+
+            let (merge_report, push_report) = match result {
+                Ok(reports) => reports,
+                Err(err) => fail(&err),
+            };
+
+            record_change(git_repo.as_deref(), sql_connection, &format!("Sync with {}", url));
+
+            if is_json_output() {
+                let mut report = merge_report_json(&merge_report);
+                report["pushed"] = json!(push_report.pushed);
+                report["rejected"] = json!(push_report.rejected);
+                print_json(report);
+            } else {
+                print_merge_report(&merge_report);
+                push_report.pushed.iter().for_each(|name| eprintln!("Uploaded '{}'", name));
+            }
+
+            if !push_report.rejected.is_empty() && !is_json_output() {
+                eprintln!("\nThese passwords were changed on the server in the meantime, sync again to merge them:\n");
+                push_report.rejected.iter().for_each(|name| eprintln!("\t - {}", name));
+            }
+
+            if !merge_report.unresolved.is_empty() || !push_report.rejected.is_empty() {
+                save_vault_file(vault_file.as_mut());
+                std::process::exit(2);
+            }
+
+            eprintln!("The vault is in sync with {}.", url);
+        }
+        Some(("share", sub_matches)) => {
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
+            let identity = match neng_pass::share::get_or_create_identity(&master_key, sql_connection) {
+                Ok(identity) => identity,
+                Err(err) => fail(&err),
+            };
+
+            let collection_file = || {
+                sub_matches
+                    .subcommand()
+                    .and_then(|(_, matches)| matches.get_one::<String>("COLLECTION"))
+                    .unwrap()
+                    .as_str()
+            };
+
+            let result = match sub_matches.subcommand() {
+                Some(("identity", _)) => {
+                    if is_json_output() {
+                        print_json(json!({ "public_key": identity.to_public().to_string() }));
+                    } else {
+                        eprintln!("Your public key is:\n");
+                        println!("{}", identity.to_public());
+                    }
+                    Ok(())
+                }
+                Some(("create", _)) => {
+                    neng_pass::share::create_collection(collection_file(), &identity)
+                        .map(|_| {
+                            print_json(json!({ "collection": collection_file() }));
+                            eprintln!("Created a shared collection in {}", collection_file());
+                        })
+                }
+                Some(("add-member", member_matches)) => {
+                    let public_key = member_matches.get_one::<String>("PUBLIC_KEY").unwrap();
+                    neng_pass::share::open_collection(collection_file(), &identity)
+                        .and_then(|collection| collection.add_member(public_key))
+                        .map(|_| {
+                            print_json(json!({ "public_key": public_key }));
+                            eprintln!("{} can now open the collection.", public_key);
+                        })
+                }
+                Some(("remove-member", member_matches)) => {
+                    let public_key = member_matches.get_one::<String>("PUBLIC_KEY").unwrap();
+                    neng_pass::share::open_collection(collection_file(), &identity)
+                        .and_then(|mut collection| collection.remove_member(public_key))
+                        .map(|_| {
+                            print_json(json!({ "public_key": public_key }));
+                            eprintln!("{} can no longer open the collection.", public_key);
+                            eprintln!("Remember that they may have kept copies of the passwords they could see, so change those too.");
+                        })
+                }
+                Some(("members", _)) => {
+                    neng_pass::share::open_collection(collection_file(), &identity)
+                        .and_then(|collection| collection.members())
+                        .map(|members| {
+                            print_json(json!({ "members": members }));
+                            if !is_json_output() {
+                                members.iter().for_each(|member| eprintln!("\t - {}", member));
+                            }
+                        })
+                }
+                Some(("list", _)) => {
+                    neng_pass::share::open_collection(collection_file(), &identity)
+                        .and_then(|collection| collection.list_passwords())
+                        .map(|names| {
+                            if is_json_output() {
+                                print_json(json!({ "passwords": names }));
+                            } else {
+                                eprintln!("Here is the list of passwords in the collection.\n");
+                                names.iter().for_each(|name| eprintln!("\t - {}", name));
+                            }
+                        })
+                }
+                Some(("save", save_matches)) => {
+                    let name = save_matches.get_one::<String>("NAME").unwrap();
+                    neng_pass::share::open_collection(collection_file(), &identity).and_then(|collection| {
+                        let password = SecretInput::from_matches(save_matches, "password", "NENG_PASS_PASSWORD_COMMAND")
+                            .read("Enter the password to save: ");
+                        collection.save_password(name, &password)
+                    })
+                    .map(|_| print_json(json!({ "name": name })))
+                }
+                Some(("get", get_matches)) => {
+                    let name = get_matches.get_one::<String>("NAME").unwrap();
+                    neng_pass::share::open_collection(collection_file(), &identity)
+                        .and_then(|collection| collection.get_password(name))
+                        .map(|password| {
+                            if is_json_output() {
+                                print_json(json!({ "name": name, "password": password }));
+                            } else if get_matches.get_flag("raw") {
+                                std::io::stdout().write_all(password.as_bytes()).unwrap();
+                            } else {
+                                eprintln!("Here's the password: {}", password);
+                            }
+                        })
+                }
+                _ => unreachable!(),
+            };
+
+            if let Err(err) = result {
+                fail(&err);
+            }
+        }
+        Some(("share-entry", sub_matches)) => {
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
+            let name = sub_matches.get_one::<String>("NAME").unwrap();
+            let recipient = sub_matches.get_one::<String>("to").unwrap();
+
+            match neng_pass::share::share_entry(&master_key, name, recipient, sql_connection) {
+                Ok(blob) if is_json_output() => print_json(json!({ "blob": blob })),
+                Ok(blob) => {
+                    eprintln!("Send this to its recipient, they can save it with the receive command:\n");
+                    println!("{}", blob);
+                }
+                Err(err) => fail(&err),
+            }
+        }
+        Some(("receive", sub_matches)) => {
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
+            let blob_file = sub_matches.get_one::<String>("BLOB").unwrap();
+            let new_name = sub_matches.get_one::<String>("as");
+
+            let blob = if blob_file == "-" {
+                let mut blob = String::new();
+                std::io::stdin().read_to_string(&mut blob).map(|_| blob)
+            } else {
+                std::fs::read_to_string(blob_file)
+            };
+
+            let result = blob
+                .map_err(neng_pass::Error::from)
+                .and_then(|blob| {
+                    neng_pass::share::receive_entry(
+                        &master_key,
+                        &blob,
+                        new_name.map(|name| name.as_str()),
+                        sql_connection,
+                    )
+                });
+
+            match result {
+                Ok(name) => {
+                    record_change(git_repo.as_deref(), sql_connection, &format!("Receive {}", name));
+                    print_json(json!({ "name": name }));
+                    eprintln!("Saved the password you received as '{}'", name);
+                }
+                Err(err) => fail(&err),
+            }
+        }
+        Some(("run", sub_matches)) => {
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
+            let mut command = sub_matches.get_many::<String>("COMMAND").unwrap();
+
+            // Variables given with --env take precedence over the dotenv files.
+            let variables = sub_matches
+                .get_many::<String>("dotenv")
+                .into_iter()
+                .flatten()
+                .map(|file| {
+                    let content = std::fs::read_to_string(file)?;
+                    neng_pass::env::parse_dotenv(&content)
+                })
+                .chain(
+                    sub_matches
+                        .get_many::<String>("env")
+                        .into_iter()
+                        .flatten()
+                        .map(|assignment| neng_pass::env::parse_assignment(assignment).map(|variable| vec![variable])),
+                )
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|variables| {
+                    neng_pass::env::resolve_variables(&master_key, &variables.concat(), sql_connection)
+                });
+
+            let variables = match variables {
+                Ok(variables) => variables,
+                Err(err) => fail(&err),
+            };
+
+            let program = command.next().unwrap();
+            let status = std::process::Command::new(program)
+                .args(command)
+                .envs(variables)
+                .status();
+
+            match status {
+                Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+                Err(err) => fail_with(
+                    "command_failed",
+                    &format!("Failed to run {}: {}", program, err),
+                    1,
+                ),
+            }
+        }
+        Some(("inject", sub_matches)) => {
+            let master_key = ask_for_password(&master_key_path, &master_key_input);
+            let input = sub_matches.get_one::<String>("input").unwrap();
+            let output = sub_matches.get_one::<String>("output");
+
+            let template = if input == "-" {
+                let mut template = String::new();
+                std::io::stdin().read_to_string(&mut template).map(|_| template)
+            } else {
+                std::fs::read_to_string(input)
+            };
+
+            let rendered = template
+                .map_err(neng_pass::Error::from)
+                .and_then(|template| neng_pass::template::render(&master_key, &template, sql_connection));
+
+            let rendered = match rendered {
+                Ok(rendered) => rendered,
+                Err(err) => fail(&err),
+            };
+
+            match output {
+                Some(output) => {
+                    if let Err(err) = neng_pass::write_private_file(Path::new(output), rendered.as_bytes()) {
+                        fail(&neng_pass::Error::from(err));
+                    }
+
+                    print_json(json!({ "output": output }));
+                    eprintln!("Wrote {}", output);
+                }
+                None if is_json_output() => print_json(json!({ "output": rendered })),
+                None => std::io::stdout().write_all(rendered.as_bytes()).unwrap(),
+            }
+        }
+        Some(("git-credential", sub_matches)) => {
+            use neng_pass::credential::{self, Credential};
+
+            let operation = sub_matches.get_one::<String>("OPERATION").unwrap();
+            // Git may add operations in the future, and helpers are expected
+            // to ignore the ones they don't know.
+            if !["get", "store", "erase"].contains(&operation.as_str()) {
+                return;
+            }
+
+            // The standard input carries the request, so the master key has to
+            // come from somewhere else.
+            let credential = match Credential::read(std::io::stdin().lock()) {
+                Ok(credential) => credential,
+                Err(err) => fail(&err),
+            };
+
+            let result = match operation.as_str() {
+                "get" => {
+                    let master_key = ask_for_password(&master_key_path, &master_key_input.without_stdin());
+                    credential::get(&master_key, &credential, sql_connection).and_then(|found| match found {
+                        Some(found) => found.write(std::io::stdout().lock()),
+                        None => Ok(()),
+                    })
+                }
+                "store" => {
+                    let master_key = ask_for_password(&master_key_path, &master_key_input.without_stdin());
+                    credential::store(&master_key, &credential, sql_connection).map(|_| {
+                        record_change(git_repo.as_deref(), sql_connection, "Store a git credential");
+                    })
+                }
+                _ => {
+                    let master_key = ask_for_password(&master_key_path, &master_key_input.without_stdin());
+                    credential::erase(&master_key, &credential, sql_connection).map(|_| {
+                        record_change(git_repo.as_deref(), sql_connection, "Erase a git credential");
+                    })
+                }
+            };
+
+            if let Err(err) = result {
+                fail(&err);
+            }
+        }
+        Some(("url", sub_matches)) => {
+            let result = match sub_matches.subcommand() {
+                Some(("add", add_matches)) => {
+                    ask_for_password(&master_key_path, &master_key_input);
+                    let name = add_matches.get_one::<String>("NAME").unwrap();
+                    let url = add_matches.get_one::<String>("URL").unwrap();
+                    neng_pass::autofill::MatchMode::parse(add_matches.get_one::<String>("mode").unwrap())
+                        .and_then(|mode| neng_pass::autofill::add_url(name, url, mode, sql_connection))
+                        .map(|_| {
+                            print_json(json!({ "name": name, "url": url }));
+                            eprintln!("Added {} to '{}'", url, name);
+                        })
+                }
+                Some(("remove", remove_matches)) => {
+                    ask_for_password(&master_key_path, &master_key_input);
+                    let name = remove_matches.get_one::<String>("NAME").unwrap();
+                    let url = remove_matches.get_one::<String>("URL").unwrap();
+                    neng_pass::autofill::remove_url(name, url, sql_connection).map(|_| {
+                        print_json(json!({ "name": name, "url": url }));
+                        eprintln!("Removed {} from '{}'", url, name);
+                    })
+                }
+                Some(("list", list_matches)) => {
+                    let name = list_matches.get_one::<String>("NAME");
+                    neng_pass::autofill::list_urls(name.map(String::as_str), sql_connection).map(|urls| {
+                        if is_json_output() {
+                            let urls = urls
+                                .iter()
+                                .map(|url| json!({ "name": url.name, "url": url.url, "mode": url.mode.as_str() }))
+                                .collect::<Vec<_>>();
+                            print_json(json!({ "urls": urls }));
+                        } else {
+                            urls.iter()
+                                .for_each(|url| eprintln!("\t - {}: {} ({})", url.name, url.url, url.mode.as_str()));
+                        }
+                    })
+                }
+                Some(("match", match_matches)) => {
+                    let url = match_matches.get_one::<String>("URL").unwrap();
+                    neng_pass::autofill::find_entries(url, sql_connection).map(|candidates| {
+                        if is_json_output() {
+                            let candidates = candidates
+                                .iter()
+                                .map(|candidate| {
+                                    json!({
+                                        "name": candidate.name,
+                                        "url": candidate.url,
+                                        "match": candidate.matched.as_str(),
+                                    })
+                                })
+                                .collect::<Vec<_>>();
+                            print_json(json!({ "entries": candidates }));
+                        } else {
+                            candidates.iter().for_each(|candidate| {
+                                eprintln!("\t - {} ({} match on {})", candidate.name, candidate.matched.as_str(), candidate.url)
+                            });
+                        }
+                    })
+                }
+                _ => unreachable!(),
+            };
+
+            if let Err(err) = result {
+                fail(&err);
+            }
+        }
+        Some(("equivalent-domains", sub_matches)) => {
+            let result = match sub_matches.subcommand() {
+                Some(("add", add_matches)) => {
+                    ask_for_password(&master_key_path, &master_key_input);
+                    let domains = add_matches
+                        .get_many::<String>("DOMAINS")
+                        .unwrap()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>();
+                    neng_pass::autofill::add_equivalent_domains(&domains, sql_connection).map(|_| {
+                        print_json(json!({ "domains": domains }));
+                        eprintln!("{} are now equivalent", domains.join(", "));
+                    })
+                }
+                Some(("remove", remove_matches)) => {
+                    ask_for_password(&master_key_path, &master_key_input);
+                    let domain = remove_matches.get_one::<String>("DOMAIN").unwrap();
+                    neng_pass::autofill::remove_equivalent_domain(domain, sql_connection).map(|_| {
+                        print_json(json!({ "domain": domain }));
+                        eprintln!("{} is no longer equivalent to other domains", domain);
+                    })
+                }
+                Some(("list", _)) => neng_pass::autofill::equivalent_domains(sql_connection).map(|groups| {
+                    if is_json_output() {
+                        print_json(json!({ "groups": groups }));
+                    } else {
+                        groups.iter().for_each(|group| eprintln!("\t - {}", group.join(", ")));
+                    }
+                }),
+                _ => unreachable!(),
+            };
+
+            if let Err(err) = result {
+                fail(&err);
+            }
+        }
+        Some(("app-link", sub_matches)) => {
+            let result = match sub_matches.subcommand() {
+                Some(("add", add_matches)) => {
+                    ask_for_password(&master_key_path, &master_key_input);
+                    let package = add_matches.get_one::<String>("PACKAGE").unwrap();
+                    let domain = add_matches.get_one::<String>("DOMAIN").unwrap();
+                    neng_pass::autofill::add_app_link(package, domain, sql_connection).map(|_| {
+                        print_json(json!({ "package": package, "domain": domain }));
+                        eprintln!("{} is now offered the entries for {}", package, domain);
+                    })
+                }
+                Some(("remove", remove_matches)) => {
+                    ask_for_password(&master_key_path, &master_key_input);
+                    let package = remove_matches.get_one::<String>("PACKAGE").unwrap();
+                    let domain = remove_matches.get_one::<String>("DOMAIN").unwrap();
+                    neng_pass::autofill::remove_app_link(package, domain, sql_connection).map(|_| {
+                        print_json(json!({ "package": package, "domain": domain }));
+                        eprintln!("{} is no longer linked to {}", package, domain);
+                    })
+                }
+                Some(("list", list_matches)) => {
+                    let package = list_matches.get_one::<String>("PACKAGE");
+                    neng_pass::autofill::app_links(package.map(String::as_str), sql_connection).map(|links| {
+                        if is_json_output() {
+                            let links = links
+                                .iter()
+                                .map(|link| json!({ "package": link.package, "domain": link.domain }))
+                                .collect::<Vec<_>>();
+                            print_json(json!({ "links": links }));
+                        } else {
+                            links.iter().for_each(|link| eprintln!("\t - {}: {}", link.package, link.domain));
+                        }
+                    })
+                }
+                _ => unreachable!(),
+            };
+
+            if let Err(err) = result {
+                fail(&err);
+            }
+        }
+        Some(("browser", sub_matches)) => {
+            let result = match sub_matches.subcommand() {
+                Some(("add", add_matches)) => {
+                    ask_for_password(&master_key_path, &master_key_input);
+                    let package = add_matches.get_one::<String>("PACKAGE").unwrap();
+                    neng_pass::autofill::add_browser(package, sql_connection).map(|_| {
+                        print_json(json!({ "package": package }));
+                        eprintln!("{} is now trusted to report the pages it shows", package);
+                    })
+                }
+                Some(("remove", remove_matches)) => {
+                    ask_for_password(&master_key_path, &master_key_input);
+                    let package = remove_matches.get_one::<String>("PACKAGE").unwrap();
+                    neng_pass::autofill::remove_browser(package, sql_connection).map(|_| {
+                        print_json(json!({ "package": package }));
+                        eprintln!("{} is no longer trusted", package);
+                    })
+                }
+                Some(("list", _)) => neng_pass::autofill::browsers(sql_connection).map(|browsers| {
+                    if is_json_output() {
+                        print_json(json!({ "browsers": browsers }));
+                    } else {
+                        browsers.iter().for_each(|browser| eprintln!("\t - {}", browser));
+                    }
+                }),
+                _ => unreachable!(),
+            };
+
+            if let Err(err) = result {
+                fail(&err);
+            }
+        }
+        Some(("ssh-key", sub_matches)) => {
+            let result = match sub_matches.subcommand() {
+                Some(("add", add_matches)) => {
+                    let master_key = ask_for_password(&master_key_path, &master_key_input);
+                    let name = add_matches.get_one::<String>("NAME").unwrap();
+                    let file = add_matches.get_one::<String>("FILE").unwrap();
+
+                    std::fs::read_to_string(file)
+                        .map_err(neng_pass::Error::from)
+                        .and_then(|private_key| {
+                            let passphrase = if neng_pass::ssh::needs_passphrase(&private_key)? {
+                                Some(rpassword::prompt_password("Enter the passphrase of the key: ")?)
+                            } else {
+                                None
+                            };
+
+                            neng_pass::ssh::add_key(
+                                &master_key,
+                                name,
+                                &private_key,
+                                passphrase.as_deref(),
+                                add_matches.get_flag("confirm"),
+                                sql_connection,
+                            )
+                        })
+                        .map(|key| {
+                            print_json(json!({ "name": key.name, "public_key": key.public_key }));
+                            eprintln!("Stored the SSH key '{}':\n\n{}", key.name, key.public_key);
+                            eprintln!("\nYou can delete the private key file now.");
+                        })
+                }
+                Some(("list", _)) => neng_pass::ssh::list_keys(sql_connection).map(|keys| {
+                    if is_json_output() {
+                        let keys = keys
+                            .iter()
+                            .map(|key| json!({ "name": key.name, "public_key": key.public_key, "confirm": key.confirm }))
+                            .collect::<Vec<_>>();
+                        print_json(json!({ "keys": keys }));
+                    } else {
+                        keys.iter().for_each(|key| eprintln!("\t - {}: {}", key.name, key.public_key));
+                    }
+                }),
+                Some(("delete", delete_matches)) => {
+                    ask_for_password(&master_key_path, &master_key_input);
+                    let name = delete_matches.get_one::<String>("NAME").unwrap();
+                    neng_pass::ssh::delete_key(name, sql_connection).map(|_| {
+                        print_json(json!({ "name": name }));
+                        eprintln!("Deleted the SSH key '{}'", name);
+                    })
+                }
+                _ => unreachable!(),
+            };
+
+            if let Err(err) = result {
+                fail(&err);
+            }
+        }
+        #[cfg(unix)]
+        Some(("ssh-agent", sub_matches)) => {
+            let socket_path = sub_matches
+                .get_one::<String>("socket")
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(|| Path::new(data_dir).join("ssh-agent.sock"));
+            let agent_socket = agent_socket_path(Path::new(data_dir));
+
+            eprintln!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket_path.display());
+            eprintln!("The keys can be used while the vault is unlocked, see the unlock command.");
+
+            let result = neng_pass::ssh::run_agent(
+                &socket_path,
+                &Path::new(data_dir).join("passwords.db"),
+                move || neng_pass::agent::get_master_key(&agent_socket),
+                confirm_key_use,
+            );
+
+            if let Err(err) = result {
+                fail(&err);
+            }
+        }
+        #[cfg(unix)]
+        Some(("agent", sub_matches)) => {
+            let timeout = sub_matches
+                .get_one::<u64>("timeout")
+                .map(|seconds| std::time::Duration::from_secs(*seconds))
+                .unwrap_or(settings.lock_timeout);
+
+            if let Err(err) = neng_pass::agent::run_agent(&agent_socket_path(Path::new(data_dir)), timeout) {
+                fail(&err);
+            }
+        }
+        #[cfg(unix)]
+        Some(("unlock", sub_matches)) => {
+            let socket_path = agent_socket_path(Path::new(data_dir));
+            let user_input_password = master_key_input.read("Enter the master key: ");
+            let master_key = match neng_pass::query_master_key(&master_key_path, &user_input_password) {
+                Ok(key) => key,
+                Err(err) => fail(&err),
+            };
+
+            if !neng_pass::agent::is_running(&socket_path) {
+                let mut agent = std::process::Command::new(std::env::current_exe().unwrap());
+                agent
+                    .arg("agent")
+                    .stdin(std::process::Stdio::null())
+                    .stdout(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::null());
+                if let Some(timeout) = sub_matches.get_one::<u64>("timeout") {
+                    agent.arg("--timeout").arg(timeout.to_string());
+                }
+                // The agent has to find the same data directory, and the
+                // timeout of the same vault.
+                if let Some(vault) = vault {
+                    agent.arg("--vault").arg(vault);
+                }
+
+                if let Err(err) = agent.spawn() {
+                    fail_with(
+                        "agent_error",
+                        &format!("Failed to start the agent. {}", err),
+                        8,
+                    );
+                }
+
+                // Give the agent a moment to start listening.
+                for _ in 0..50 {
+                    if neng_pass::agent::is_running(&socket_path) {
+                        break;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+            }
+
+            if let Err(err) = neng_pass::agent::unlock(&socket_path, &master_key) {
+                fail(&err);
+            }
+
+            print_json(json!({ "ok": true }));
+            eprintln!("Unlocked. The master key won't be asked for until you lock the vault again.");
+        }
+        #[cfg(unix)]
+        Some(("lock", _)) => {
+            if let Err(err) = neng_pass::agent::lock(&agent_socket_path(Path::new(data_dir))) {
+                fail(&err);
+            }
+
+            print_json(json!({ "ok": true }));
+            eprintln!("Locked.");
+        }
         _ => {
             panic!("truly a bruh moment, this should be unreachable");
         }
     }
+
+    save_vault_file(vault_file.as_mut());
 }
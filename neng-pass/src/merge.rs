@@ -0,0 +1,614 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use rusqlite::OpenFlags;
+
+use crate::{
+    crypto, current_timestamp,
+    storage::{MemoryStorage, Storage},
+    Error, MasterKey, MAX_PASSWORD_LEN,
+};
+
+pub use crate::entry::Entry;
+
+/// Both copies changed the same entry in different ways since they were last
+/// merged.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub name: String,
+    pub local: Option<Entry>,
+    pub other: Option<Entry>,
+}
+
+/// How a conflict should be settled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    KeepLocal,
+    KeepOther,
+    /// Keep the local entry and store the other one under a new name.
+    KeepBoth,
+}
+
+/// What the merge engine decided to do with a single entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    KeepLocal,
+    TakeOther,
+    Conflict,
+}
+
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+    /// Pairs of (original name, new name) for entries kept under a new name.
+    pub renamed: Vec<(String, String)>,
+    /// Conflicts that were resolved, and how.
+    pub resolved: Vec<(String, Resolution)>,
+    /// Conflicts that were left alone. The local entry stays as it is and the
+    /// conflict will show up again on the next merge.
+    pub unresolved: Vec<Conflict>,
+}
+
+fn version(p_entry: Option<&Entry>) -> Option<i64> {
+    p_entry.map(|entry| entry.modified)
+}
+
+/// Decides what to do with one entry, given the version both copies agreed on
+/// the last time they were merged (`p_base`, a modification timestamp) and the
+/// entry as it is in each copy now.
+pub fn merge_entry(p_base: Option<i64>, p_local: Option<&Entry>, p_other: Option<&Entry>) -> Outcome {
+    match (p_local, p_other) {
+        (None, None) => return Outcome::KeepLocal,
+        (Some(local), Some(other)) if local.same_content(other) => return Outcome::KeepLocal,
+        _ => {}
+    }
+
+    let local_changed = version(p_local) != p_base;
+    let other_changed = version(p_other) != p_base;
+
+    if !other_changed {
+        Outcome::KeepLocal
+    } else if !local_changed {
+        Outcome::TakeOther
+    } else {
+        Outcome::Conflict
+    }
+}
+
+/// Reads every entry of a vault, including tombstones.
+pub fn read_entries(p_storage: &dyn Storage) -> Result<BTreeMap<String, Entry>, Error> {
+    let entries = p_storage
+        .list()?
+        .into_iter()
+        .map(|entry| (entry.name.clone(), entry))
+        .collect();
+
+    Ok(entries)
+}
+
+/// Reads the entries of another copy of the vault without changing it. The
+/// file is opened read-only, and copies written by older versions, which don't
+/// have the `modified` and `deleted` columns yet, are read as they are.
+pub fn read_database_file(p_path: &Path) -> Result<MemoryStorage, Error> {
+    if !p_path.is_file() {
+        return Err(Error::VaultDoesntExist(p_path.display().to_string().into()));
+    }
+
+    let sql_connection =
+        rusqlite::Connection::open_with_flags(p_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let mut sql_statement = sql_connection.prepare("PRAGMA table_info(passwords);")?;
+    let columns = sql_statement
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if columns.is_empty() {
+        return Err(Error::SyntaxError(format!(
+            "{} is not a neng-pass vault.",
+            p_path.display()
+        )));
+    }
+
+    let column = |name: &'static str| match columns.iter().any(|column| column == name) {
+        true => name,
+        false => "0",
+    };
+
+    let mut sql_statement = sql_connection.prepare(&format!(
+        "SELECT name, password, {}, {} FROM passwords;",
+        column("modified"),
+        column("deleted")
+    ))?;
+    let entries = sql_statement.query_map([], |row| {
+        Ok(Entry {
+            name: row.get(0)?,
+            password: row.get(1)?,
+            modified: row.get(2)?,
+            deleted: row.get(3)?,
+        })
+    })?;
+
+    let storage = MemoryStorage::new();
+    for entry in entries {
+        storage.put(&entry?)?;
+    }
+
+    Ok(storage)
+}
+
+fn read_merge_base(
+    p_sql_connection: &rusqlite::Connection,
+    p_peer: &str,
+) -> Result<BTreeMap<String, i64>, Error> {
+    let mut sql_statement =
+        p_sql_connection.prepare("SELECT name, modified FROM merge_bases WHERE peer = ?;")?;
+    let base = sql_statement
+        .query_map([p_peer], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+    Ok(base)
+}
+
+/// Writes an entry into the vault, replacing any existing entry with the same
+/// name.
+pub fn write_entry(p_storage: &dyn Storage, p_entry: &Entry) -> Result<(), Error> {
+    p_storage.put(p_entry)
+}
+
+fn set_merge_base(
+    p_sql_connection: &rusqlite::Connection,
+    p_peer: &str,
+    p_name: &str,
+    p_version: Option<i64>,
+) -> Result<(), Error> {
+    match p_version {
+        Some(version) => p_sql_connection.execute(
+            "INSERT OR REPLACE INTO merge_bases (peer, name, modified) VALUES (?, ?, ?);",
+            (p_peer, p_name, version),
+        )?,
+        None => p_sql_connection.execute(
+            "DELETE FROM merge_bases WHERE peer = ? AND name = ?;",
+            (p_peer, p_name),
+        )?,
+    };
+
+    Ok(())
+}
+
+fn unused_name(p_name: &str, p_taken: &BTreeSet<String>) -> String {
+    let mut candidate = format!("{} (merged)", p_name);
+    let mut counter = 2;
+    while p_taken.contains(&candidate) {
+        candidate = format!("{} (merged {})", p_name, counter);
+        counter += 1;
+    }
+
+    candidate
+}
+
+/// Checks that the entries of another copy were encrypted with the same master
+/// key as this one, since merging copies the encrypted passwords as they are.
+/// Sealed passwords can't be opened with another key. The others aren't
+/// authenticated, so they are taken to be when they decrypt to text, which the
+/// random bytes a wrong key gives practically never are.
+pub fn check_master_key<'a>(
+    p_master_key: &MasterKey,
+    p_entries: impl IntoIterator<Item = &'a Entry>,
+) -> Result<(), Error> {
+    for entry in p_entries.into_iter().filter(|entry| !entry.deleted) {
+        let is_readable = if entry.password.len() == MAX_PASSWORD_LEN {
+            let password = crypto::decrypt(p_master_key.as_bytes(), &entry.password);
+            match std::str::from_utf8(&password) {
+                Ok(password) => !password.trim_end_matches('\0').chars().any(char::is_control),
+                Err(_) => false,
+            }
+        } else {
+            crypto::open(p_master_key.as_bytes(), &entry.password).is_some()
+        };
+
+        if !is_readable {
+            return Err(Error::WrongKeyForData);
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies one merged entry set to the local database. `p_entries` yields the
+/// base version followed by the local and other versions of every entry, and
+/// `p_resolve` is asked about every conflict; returning `None` leaves the
+/// conflict unresolved.
+///
+/// `p_peer` names the copy that was merged in, such as the path of a database,
+/// `git:REMOTE/BRANCH` or the URL of a sync server. The merge base is recorded
+/// for that copy only, since another copy may not have seen the same changes.
+///
+/// The other entries are written as they are, so callers check them with
+/// `check_master_key` first, unless they were already authenticated with the
+/// master key, like the blobs of a sync server.
+pub fn apply_merge<'a>(
+    p_local: &rusqlite::Connection,
+    p_peer: &str,
+    p_entries: impl IntoIterator<Item = (String, Option<i64>, Option<&'a Entry>, Option<&'a Entry>)>,
+    mut p_resolve: impl FnMut(&Conflict) -> Option<Resolution>,
+) -> Result<MergeReport, Error> {
+    let mut report = MergeReport::default();
+    let mut taken_names = read_entries(p_local)?.into_keys().collect::<BTreeSet<_>>();
+
+    let transaction = p_local.unchecked_transaction()?;
+
+    for (name, base, local, other) in p_entries {
+        let outcome = merge_entry(base, local, other);
+
+        let resolution = match outcome {
+            Outcome::KeepLocal => Resolution::KeepLocal,
+            Outcome::TakeOther => Resolution::KeepOther,
+            Outcome::Conflict => {
+                let conflict = Conflict {
+                    name: name.clone(),
+                    local: local.cloned(),
+                    other: other.cloned(),
+                };
+
+                match p_resolve(&conflict) {
+                    Some(resolution) => {
+                        report.resolved.push((name.clone(), resolution));
+                        resolution
+                    }
+                    None => {
+                        report.unresolved.push(conflict);
+                        continue;
+                    }
+                }
+            }
+        };
+
+        // Keeping both only makes sense when there is a local secret to keep.
+        let resolution = match (resolution, local) {
+            (Resolution::KeepBoth, None) => Resolution::KeepOther,
+            (Resolution::KeepBoth, Some(local)) if local.deleted => Resolution::KeepOther,
+            (resolution, _) => resolution,
+        };
+
+        match resolution {
+            Resolution::KeepLocal => {}
+            Resolution::KeepOther => match other {
+                Some(other) => {
+                    write_entry(&transaction, other)?;
+                    match local {
+                        None if !other.deleted => report.added.push(name.clone()),
+                        None => {}
+                        Some(_) if other.deleted => report.deleted.push(name.clone()),
+                        Some(_) => report.updated.push(name.clone()),
+                    }
+                }
+                None => {
+                    // The other copy never had the entry; keep the local one, a
+                    // secret is never dropped because of a missing row.
+                }
+            },
+            Resolution::KeepBoth => {
+                if let Some(other) = other.filter(|other| !other.deleted) {
+                    let new_name = unused_name(&name, &taken_names);
+                    write_entry(
+                        &transaction,
+                        &Entry {
+                            name: new_name.clone(),
+                            password: other.password.clone(),
+                            modified: current_timestamp(),
+                            deleted: false,
+                        },
+                    )?;
+                    taken_names.insert(new_name.clone());
+                    report.renamed.push((name.clone(), new_name));
+                }
+            }
+        }
+
+        taken_names.insert(name.clone());
+        set_merge_base(&transaction, p_peer, &name, version(other))?;
+    }
+
+    transaction.commit()?;
+
+    Ok(report)
+}
+
+/// Merges the entries of `p_other` into `p_local`. Changes made on only one
+/// side are applied automatically, using the merge base recorded in the local
+/// database by the previous merge with the same peer (see `apply_merge`).
+/// `p_resolve` is asked about every true conflict; returning `None` leaves the
+/// conflict unresolved. Copies encrypted with another master key are refused.
+pub fn merge_databases(
+    p_master_key: &MasterKey,
+    p_local: &rusqlite::Connection,
+    p_peer: &str,
+    p_other: &dyn Storage,
+    p_resolve: impl FnMut(&Conflict) -> Option<Resolution>,
+) -> Result<MergeReport, Error> {
+    let local_entries = read_entries(p_local)?;
+    let other_entries = read_entries(p_other)?;
+    check_master_key(p_master_key, other_entries.values())?;
+    let base = read_merge_base(p_local, p_peer)?;
+
+    let names = local_entries
+        .keys()
+        .chain(other_entries.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    apply_merge(
+        p_local,
+        p_peer,
+        names.into_iter().map(|name| {
+            let entry_base = base.get(&name).copied();
+            let local = local_entries.get(&name);
+            let other = other_entries.get(&name);
+            (name, entry_base, local, other)
+        }),
+        p_resolve,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> MasterKey {
+        MasterKey::new("hunter2").unwrap()
+    }
+
+    fn entry(p_name: &str, p_password: &[u8], p_modified: i64) -> Entry {
+        Entry {
+            name: p_name.to_string(),
+            password: crypto::encrypt(key().as_bytes(), p_password).to_vec(),
+            modified: p_modified,
+            deleted: false,
+        }
+    }
+
+    fn password(p_entries: &BTreeMap<String, Entry>, p_name: &str) -> Vec<u8> {
+        let password = crypto::decrypt(key().as_bytes(), &p_entries[p_name].password);
+        password.into_iter().take_while(|byte| *byte != 0).collect()
+    }
+
+    fn tombstone(p_name: &str, p_modified: i64) -> Entry {
+        Entry {
+            name: p_name.to_string(),
+            password: Vec::new(),
+            modified: p_modified,
+            deleted: true,
+        }
+    }
+
+    fn database(p_entries: &[Entry]) -> rusqlite::Connection {
+        let sql_connection = rusqlite::Connection::open_in_memory().unwrap();
+        crate::prepare_database(&sql_connection).unwrap();
+        for entry in p_entries {
+            write_entry(&sql_connection, entry).unwrap();
+        }
+
+        sql_connection
+    }
+
+    #[test]
+    fn changes_on_one_side_are_taken() {
+        let base = entry("github", b"old", 1);
+        let changed = entry("github", b"new", 2);
+
+        assert_eq!(
+            merge_entry(Some(1), Some(&base), Some(&base)),
+            Outcome::KeepLocal
+        );
+        assert_eq!(
+            merge_entry(Some(1), Some(&changed), Some(&base)),
+            Outcome::KeepLocal
+        );
+        assert_eq!(
+            merge_entry(Some(1), Some(&base), Some(&changed)),
+            Outcome::TakeOther
+        );
+        assert_eq!(merge_entry(None, None, Some(&changed)), Outcome::TakeOther);
+        assert_eq!(merge_entry(None, Some(&changed), None), Outcome::KeepLocal);
+        assert_eq!(merge_entry(None, None, None), Outcome::KeepLocal);
+    }
+
+    #[test]
+    fn changes_on_both_sides_conflict() {
+        let local = entry("github", b"local", 2);
+        let other = entry("github", b"other", 3);
+
+        assert_eq!(
+            merge_entry(Some(1), Some(&local), Some(&other)),
+            Outcome::Conflict
+        );
+        // Without a base, two different entries are two unrelated secrets.
+        assert_eq!(
+            merge_entry(None, Some(&local), Some(&other)),
+            Outcome::Conflict
+        );
+    }
+
+    #[test]
+    fn deleting_and_editing_conflict() {
+        let deleted = tombstone("github", 2);
+        let edited = entry("github", b"edited", 3);
+
+        assert_eq!(
+            merge_entry(Some(1), Some(&deleted), Some(&edited)),
+            Outcome::Conflict
+        );
+        assert_eq!(
+            merge_entry(Some(1), Some(&edited), Some(&deleted)),
+            Outcome::Conflict
+        );
+    }
+
+    #[test]
+    fn tombstones_are_not_resurrected() {
+        let unchanged = entry("github", b"secret", 1);
+        let deleted = tombstone("github", 2);
+
+        // The other copy still has the entry as it was before the deletion.
+        assert_eq!(
+            merge_entry(Some(1), Some(&deleted), Some(&unchanged)),
+            Outcome::KeepLocal
+        );
+        // The deletion was made in the other copy.
+        assert_eq!(
+            merge_entry(Some(1), Some(&unchanged), Some(&deleted)),
+            Outcome::TakeOther
+        );
+    }
+
+    #[test]
+    fn identical_content_is_not_a_conflict() {
+        let local = entry("github", b"same", 2);
+        let other = entry("github", b"same", 3);
+        assert_eq!(
+            merge_entry(Some(1), Some(&local), Some(&other)),
+            Outcome::KeepLocal
+        );
+
+        let local = tombstone("github", 2);
+        let other = tombstone("github", 3);
+        assert_eq!(
+            merge_entry(Some(1), Some(&local), Some(&other)),
+            Outcome::KeepLocal
+        );
+    }
+
+    #[test]
+    fn merges_two_databases() {
+        let local = database(&[
+            entry("unchanged", b"a", 1),
+            entry("edited-here", b"b2", 5),
+            entry("both", b"local", 5),
+            entry("only-here", b"c", 1),
+        ]);
+        let other = database(&[
+            entry("unchanged", b"a", 1),
+            entry("edited-here", b"b", 1),
+            entry("both", b"other", 6),
+            entry("only-there", b"d", 1),
+            tombstone("deleted-there", 3),
+        ]);
+        write_entry(&local, &entry("deleted-there", b"e", 1)).unwrap();
+        for name in ["unchanged", "edited-here", "both", "deleted-there"] {
+            set_merge_base(&local, "other.db", name, Some(1)).unwrap();
+        }
+
+        let mut asked = Vec::new();
+        let report = merge_databases(&key(), &local, "other.db", &other, |conflict| {
+            asked.push(conflict.name.clone());
+            Some(Resolution::KeepBoth)
+        })
+        .unwrap();
+
+        assert_eq!(asked, ["both"]);
+        assert_eq!(report.added, ["only-there"]);
+        assert_eq!(report.deleted, ["deleted-there"]);
+        assert!(report.updated.is_empty());
+        assert_eq!(
+            report.renamed,
+            [("both".to_string(), "both (merged)".to_string())]
+        );
+        assert!(report.unresolved.is_empty());
+
+        let entries = read_entries(&local).unwrap();
+        assert_eq!(password(&entries, "edited-here"), b"b2");
+        assert_eq!(password(&entries, "both"), b"local");
+        assert_eq!(password(&entries, "both (merged)"), b"other");
+        assert_eq!(password(&entries, "only-here"), b"c");
+        assert_eq!(password(&entries, "only-there"), b"d");
+        assert!(entries["deleted-there"].deleted);
+
+        // Everything was merged, so merging again changes nothing.
+        let report = merge_databases(&key(), &local, "other.db", &other, |_| None).unwrap();
+        assert!(report.added.is_empty() && report.updated.is_empty() && report.deleted.is_empty());
+        assert!(report.unresolved.is_empty());
+    }
+
+    #[test]
+    fn merge_bases_are_kept_per_peer() {
+        let local = database(&[entry("github", b"old", 1)]);
+        let remote = database(&[entry("github", b"pulled", 7)]);
+        let stale_copy = database(&[entry("github", b"old", 1)]);
+        set_merge_base(&local, "git:origin/main", "github", Some(1)).unwrap();
+
+        let report = merge_databases(&key(), &local, "git:origin/main", &remote, |_| None).unwrap();
+        assert_eq!(report.updated, ["github"]);
+
+        // The base recorded for the remote says nothing about this copy, so
+        // its old entry is a conflict rather than a change to take.
+        let report = merge_databases(&key(), &local, "copy.db", &stale_copy, |_| None).unwrap();
+        assert!(report.updated.is_empty());
+        assert_eq!(report.unresolved.len(), 1);
+        assert_eq!(
+            password(&read_entries(&local).unwrap(), "github"),
+            b"pulled"
+        );
+    }
+
+    #[test]
+    fn reads_other_copies_without_changing_them() {
+        let path = std::env::temp_dir().join(format!("neng-pass-merge-{}.db", std::process::id()));
+        {
+            // The layout of the first versions, without timestamps.
+            let sql_connection = rusqlite::Connection::open(&path).unwrap();
+            sql_connection
+                .execute_batch(
+                    "CREATE TABLE passwords (name TEXT, password BLOB);
+                     INSERT INTO passwords VALUES ('github', x'0102');",
+                )
+                .unwrap();
+        }
+        let before = std::fs::read(&path).unwrap();
+
+        let other = read_database_file(&path).unwrap();
+        let entries = read_entries(&other).unwrap();
+        assert_eq!(entries["github"].password, [1, 2]);
+        assert_eq!(entries["github"].modified, 0);
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            read_database_file(&path),
+            Err(Error::VaultDoesntExist(_))
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn copies_with_another_master_key_are_refused() {
+        let local = database(&[entry("github", b"local", 1)]);
+        let other_key = MasterKey::new("hunter3").unwrap();
+        let other = database(&[Entry {
+            name: "gitlab".to_string(),
+            password: crypto::encrypt(other_key.as_bytes(), b"other").to_vec(),
+            modified: 1,
+            deleted: false,
+        }]);
+
+        assert!(matches!(
+            merge_databases(&key(), &local, "other.db", &other, |_| None),
+            Err(Error::WrongKeyForData)
+        ));
+        assert!(!read_entries(&local).unwrap().contains_key("gitlab"));
+
+        let sealed = database(&[Entry {
+            name: "token".to_string(),
+            password: crypto::seal(other_key.as_bytes(), b"a token longer than sixteen bytes"),
+            modified: 1,
+            deleted: false,
+        }]);
+        assert!(matches!(
+            merge_databases(&key(), &local, "other.db", &sealed, |_| None),
+            Err(Error::WrongKeyForData)
+        ));
+    }
+}
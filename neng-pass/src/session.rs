@@ -0,0 +1,176 @@
+//! A vault directory opened by an app rather than by the CLI, for the
+//! bindings to other languages. The session owns the connection and, while
+//! it is unlocked, the master key, so that the app never has to keep the
+//! master key itself. The key is wiped from memory when the session is
+//! locked or dropped.
+
+use std::path::PathBuf;
+
+use crate::{autofill, Error, MasterKey};
+
+/// A login to offer for a page or an app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Login {
+    pub name: String,
+    pub username: Option<String>,
+    pub password: String,
+}
+
+pub struct Session {
+    data_dir: PathBuf,
+    sql_connection: rusqlite::Connection,
+    /// The key the entries are encrypted with, while the vault is unlocked.
+    master_key: Option<MasterKey>,
+}
+
+impl Session {
+    /// Opens the vault in a data directory, creating it if needed. The vault
+    /// is locked until `unlock` is called.
+    pub fn open(p_data_dir: impl Into<PathBuf>) -> Result<Session, Error> {
+        let data_dir = p_data_dir.into();
+
+        let sql_connection = rusqlite::Connection::open(data_dir.join("passwords.db"))?;
+        crate::prepare_database(&sql_connection)?;
+
+        Ok(Session {
+            data_dir,
+            sql_connection,
+            master_key: None,
+        })
+    }
+
+    pub fn sql_connection(&self) -> &rusqlite::Connection {
+        &self.sql_connection
+    }
+
+    pub fn master_key_file(&self) -> String {
+        self.data_dir
+            .join("master_key")
+            .to_string_lossy()
+            .to_string()
+    }
+
+    pub fn is_master_key_set(&self) -> bool {
+        std::path::Path::new(&self.master_key_file()).exists()
+    }
+
+    /// Sets the master key, which must not have been set yet, and unlocks the
+    /// vault with it.
+    pub fn set_master_key(&mut self, p_new_master_key: &str) -> Result<(), Error> {
+        crate::set_master_key(&self.master_key_file(), p_new_master_key)?;
+        self.unlock(p_new_master_key)
+    }
+
+    /// Checks the master key, and keeps it until the vault is locked. Entries
+    /// are only ever decrypted with a key that was checked.
+    pub fn unlock(&mut self, p_master_key: &str) -> Result<(), Error> {
+        drop(zeroize::Zeroizing::new(crate::query_master_key(
+            &self.master_key_file(),
+            p_master_key,
+        )?));
+
+        self.master_key = Some(MasterKey::new(p_master_key)?);
+
+        Ok(())
+    }
+
+    pub fn lock(&mut self) {
+        self.master_key = None;
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.master_key.is_some()
+    }
+
+    pub fn master_key(&self) -> Result<&MasterKey, Error> {
+        self.master_key.as_ref().ok_or(Error::VaultLocked)
+    }
+
+    pub fn list_entries(&self) -> Result<Vec<String>, Error> {
+        crate::list_passwords(&self.sql_connection)
+    }
+
+    pub fn get_entry(&self, p_name: &str) -> Result<String, Error> {
+        let password = zeroize::Zeroizing::new(crate::get_password_with_key(
+            self.master_key()?,
+            p_name,
+            &self.sql_connection,
+        )?);
+        Ok(password.trim_end_matches('\0').to_string())
+    }
+
+    pub fn save_entry(&self, p_name: &str, p_password: &str) -> Result<(), Error> {
+        crate::create_password_with_key(
+            self.master_key()?,
+            p_name,
+            p_password,
+            &self.sql_connection,
+        )
+    }
+
+    pub fn delete_entry(&self, p_name: &str) -> Result<(), Error> {
+        crate::delete_password(p_name, &self.sql_connection)
+    }
+
+    /// The logins for entries found by `autofill`, with their passwords.
+    pub fn logins(&self, p_candidates: Vec<autofill::Candidate>) -> Result<Vec<Login>, Error> {
+        p_candidates
+            .into_iter()
+            .map(|candidate| {
+                Ok(Login {
+                    username: autofill::entry_username(&candidate.name).map(str::to_string),
+                    password: self.get_entry(&candidate.name)?,
+                    name: candidate.name,
+                })
+            })
+            .collect()
+    }
+
+    /// Saves a login that was just used on a page or in an app, replacing the
+    /// one with the same site and user name. Returns the name of its entry.
+    pub fn save_login(
+        &self,
+        p_url: &str,
+        p_username: Option<&str>,
+        p_password: &str,
+    ) -> Result<String, Error> {
+        autofill::save_login(
+            self.master_key()?,
+            p_url,
+            p_username,
+            p_password,
+            &self.sql_connection,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_checked_keys_unlock() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let mut session = Session::open(data_dir.path()).unwrap();
+        assert!(!session.is_master_key_set());
+
+        session.set_master_key("hunter2").unwrap();
+        session.save_entry("github", "hunter3").unwrap();
+        session.lock();
+        assert!(matches!(
+            session.get_entry("github"),
+            Err(Error::VaultLocked)
+        ));
+
+        assert!(matches!(
+            session.unlock("hunter3"),
+            Err(Error::HashError(_))
+        ));
+        assert!(!session.is_unlocked());
+
+        let mut session = Session::open(data_dir.path()).unwrap();
+        session.unlock("hunter2").unwrap();
+        assert_eq!(session.get_entry("github").unwrap(), "hunter3");
+        assert_eq!(session.list_entries().unwrap(), ["github"]);
+    }
+}
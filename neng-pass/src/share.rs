@@ -0,0 +1,411 @@
+//! Collections of passwords shared between several people.
+//!
+//! A collection is a separate database file that can be put wherever all the
+//! members can reach it (a shared drive, a git repository, ...). Its entries
+//! are sealed with a random collection key, and that key is stored once for
+//! every member, encrypted to the member's X25519 public key. Removing a
+//! member rotates the collection key, so they can't read anything saved after
+//! they were removed.
+//!
+//! Single passwords can also be handed to someone as a self-contained blob
+//! (see `share_entry`), without setting up a collection.
+
+use std::str::FromStr;
+
+use age::secrecy::ExposeSecret;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+use crate::{crypto, pad_master_key, storage::Storage, Error};
+
+pub use age::x25519::{Identity, Recipient};
+
+const COLLECTION_KEY_LEN: usize = 32;
+
+/// Returns the X25519 identity of the owner of the vault, creating one the
+/// first time. The secret half is kept in the vault, sealed with the master
+/// key.
+pub fn get_or_create_identity(
+    p_master_key: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<Identity, Error> {
+    let key = pad_master_key(p_master_key.to_string());
+
+    let sealed = p_sql_connection
+        .query_row("SELECT secret FROM identity;", [], |row| row.get::<_, Vec<u8>>(0))
+        .ok();
+
+    if let Some(sealed) = sealed {
+        let secret = crypto::open(key.as_bytes(), &sealed).ok_or(Error::WrongKeyForData)?;
+        let secret = String::from_utf8(secret)?;
+        return Identity::from_str(&secret).map_err(|_| Error::WrongKeyForData);
+    }
+
+    let identity = Identity::generate();
+    let sealed = crypto::seal(
+        key.as_bytes(),
+        identity.to_string().expose_secret().as_bytes(),
+    );
+    p_sql_connection.execute("INSERT INTO identity (secret) VALUES (?);", [&sealed])?;
+
+    Ok(identity)
+}
+
+/// Parses a public key in the `age1...` format.
+pub fn parse_public_key(p_public_key: &str) -> Result<Recipient, Error> {
+    Recipient::from_str(p_public_key.trim())
+        .map_err(|_| Error::InvalidPublicKey(Box::from(p_public_key)))
+}
+
+fn wrap_key(p_key: &[u8], p_member: &Recipient) -> Result<Vec<u8>, Error> {
+    age::encrypt(p_member, p_key).map_err(|err| Error::EncryptionError(err.to_string()))
+}
+
+fn generate_collection_key() -> [u8; COLLECTION_KEY_LEN] {
+    let mut key = [0u8; COLLECTION_KEY_LEN];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut key);
+    key
+}
+
+/// A shared collection that has been unlocked with the identity of one of its
+/// members.
+pub struct Collection {
+    connection: rusqlite::Connection,
+    key: [u8; COLLECTION_KEY_LEN],
+}
+
+/// Creates a new collection in `p_file`, with the owner of `p_identity` as its
+/// only member.
+pub fn create_collection(p_file: &str, p_identity: &Identity) -> Result<Collection, Error> {
+    if std::path::Path::new(p_file).exists() {
+        return Err(Error::CollectionAlreadyExists(Box::from(p_file)));
+    }
+
+    let connection = rusqlite::Connection::open(p_file)?;
+    connection.execute(
+        "CREATE TABLE members (public_key TEXT PRIMARY KEY, wrapped_key BLOB NOT NULL);",
+        (),
+    )?;
+    connection.execute(
+        "CREATE TABLE entries (name TEXT PRIMARY KEY, password BLOB NOT NULL);",
+        (),
+    )?;
+
+    let key = generate_collection_key();
+    let owner = p_identity.to_public();
+    connection.execute(
+        "INSERT INTO members (public_key, wrapped_key) VALUES (?, ?);",
+        (owner.to_string(), wrap_key(&key, &owner)?),
+    )?;
+
+    Ok(Collection { connection, key })
+}
+
+/// Opens a collection and unwraps its key with `p_identity`.
+pub fn open_collection(p_file: &str, p_identity: &Identity) -> Result<Collection, Error> {
+    if !std::path::Path::new(p_file).exists() {
+        return Err(Error::CollectionDoesntExist(Box::from(p_file)));
+    }
+
+    let connection = rusqlite::Connection::open(p_file)?;
+    let wrapped_key = connection
+        .query_row(
+            "SELECT wrapped_key FROM members WHERE public_key = ?;",
+            [p_identity.to_public().to_string()],
+            |row| row.get::<_, Vec<u8>>(0),
+        )
+        .map_err(|_| Error::NotACollectionMember)?;
+
+    let unwrapped =
+        age::decrypt(p_identity, &wrapped_key).map_err(|_| Error::NotACollectionMember)?;
+    let key = unwrapped
+        .try_into()
+        .map_err(|_| Error::NotACollectionMember)?;
+
+    Ok(Collection { connection, key })
+}
+
+impl Collection {
+    /// The public keys of all the members.
+    pub fn members(&self) -> Result<Vec<String>, Error> {
+        let mut sql_statement = self.connection.prepare("SELECT public_key FROM members;")?;
+        let members = sql_statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(members)
+    }
+
+    /// Gives the owner of `p_public_key` access to the collection.
+    pub fn add_member(&self, p_public_key: &str) -> Result<(), Error> {
+        let member = parse_public_key(p_public_key)?;
+        if self.members()?.contains(&member.to_string()) {
+            return Err(Error::MemberAlreadyExists(Box::from(p_public_key)));
+        }
+
+        self.connection.execute(
+            "INSERT INTO members (public_key, wrapped_key) VALUES (?, ?);",
+            (member.to_string(), wrap_key(&self.key, &member)?),
+        )?;
+
+        Ok(())
+    }
+
+    /// Takes away the access of the owner of `p_public_key`. A new collection
+    /// key is generated, every entry is sealed again with it, and it is
+    /// wrapped for the remaining members.
+    pub fn remove_member(&mut self, p_public_key: &str) -> Result<(), Error> {
+        let removed = parse_public_key(p_public_key)?.to_string();
+        let members = self.members()?;
+        if !members.contains(&removed) {
+            return Err(Error::MemberDoesntExist(Box::from(p_public_key)));
+        }
+
+        if members.len() == 1 {
+            return Err(Error::CantRemoveLastMember);
+        }
+
+        let new_key = generate_collection_key();
+        let transaction = self.connection.unchecked_transaction()?;
+
+        transaction.execute("DELETE FROM members WHERE public_key = ?;", [&removed])?;
+
+        for member in members.iter().filter(|member| **member != removed) {
+            let recipient = parse_public_key(member)?;
+            transaction.execute(
+                "UPDATE members SET wrapped_key = ? WHERE public_key = ?;",
+                (wrap_key(&new_key, &recipient)?, member),
+            )?;
+        }
+
+        let mut sql_statement = transaction.prepare("SELECT name, password FROM entries;")?;
+        let entries = sql_statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        drop(sql_statement);
+
+        for (name, sealed) in entries {
+            let password = crypto::open(&self.key, &sealed).ok_or(Error::WrongKeyForData)?;
+            transaction.execute(
+                "UPDATE entries SET password = ? WHERE name = ?;",
+                (crypto::seal(&new_key, &password), &name),
+            )?;
+        }
+
+        transaction.commit()?;
+        self.key = new_key;
+
+        Ok(())
+    }
+
+    pub fn list_passwords(&self) -> Result<Vec<String>, Error> {
+        let mut sql_statement = self.connection.prepare("SELECT name FROM entries;")?;
+        let names = sql_statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(names)
+    }
+
+    pub fn get_password(&self, p_name: &str) -> Result<String, Error> {
+        let sealed = self
+            .connection
+            .query_row(
+                "SELECT password FROM entries WHERE name = ?;",
+                [p_name],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .map_err(|_| Error::PasswordDoesntExist(Box::from(p_name)))?;
+
+        let password = crypto::open(&self.key, &sealed).ok_or(Error::WrongKeyForData)?;
+        Ok(String::from_utf8(password)?)
+    }
+
+    pub fn save_password(&self, p_name: &str, p_password: &str) -> Result<(), Error> {
+        if self.list_passwords()?.iter().any(|name| name == p_name) {
+            return Err(Error::PasswordAlreadyExists);
+        }
+
+        self.connection.execute(
+            "INSERT INTO entries (name, password) VALUES (?, ?);",
+            (p_name, crypto::seal(&self.key, p_password.as_bytes())),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn delete_password(&self, p_name: &str) -> Result<(), Error> {
+        self.connection
+            .execute("DELETE FROM entries WHERE name = ?;", [p_name])?;
+        Ok(())
+    }
+}
+
+/// What a shared entry looks like inside its encrypted blob.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SharedEntry {
+    name: String,
+    password: String,
+}
+
+/// Encrypts a single password to `p_recipient`, as an armored age file that
+/// can be sent over any channel. Apart from `receive_entry`, it can also be
+/// read with any age implementation, using the recipient's identity.
+pub fn share_entry(
+    p_master_key: &str,
+    p_name: &str,
+    p_recipient: &str,
+    p_storage: &dyn Storage,
+) -> Result<String, Error> {
+    let recipient = parse_public_key(p_recipient)?;
+    let password = crate::get_password(p_master_key.to_string(), p_name, p_storage)?;
+
+    let shared = SharedEntry {
+        name: p_name.to_string(),
+        password: password.trim_end_matches('\0').to_string(),
+    };
+    let plaintext = serde_json::to_vec(&shared).expect("serializing an entry can't fail");
+
+    age::encrypt_and_armor(&recipient, &plaintext)
+        .map_err(|err| Error::EncryptionError(err.to_string()))
+}
+
+/// Decrypts a blob made by `share_entry` with the identity of the vault and
+/// saves the password in it, under `p_name` if given or else under the name
+/// it was shared with. Returns the name it was saved under.
+pub fn receive_entry(
+    p_master_key: &str,
+    p_blob: &str,
+    p_name: Option<&str>,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<String, Error> {
+    let identity = get_or_create_identity(p_master_key, p_sql_connection)?;
+
+    let plaintext =
+        age::decrypt(&identity, p_blob.trim().as_bytes()).map_err(|_| Error::WrongKeyForData)?;
+    let shared: SharedEntry =
+        serde_json::from_slice(&plaintext).map_err(|_| Error::WrongKeyForData)?;
+
+    let name = p_name.unwrap_or(&shared.name).to_string();
+    crate::create_password(
+        p_master_key.to_string(),
+        &name,
+        &shared.password,
+        p_sql_connection,
+    )?;
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public_key(p_identity: &Identity) -> String {
+        p_identity.to_public().to_string()
+    }
+
+    fn vault() -> rusqlite::Connection {
+        let sql_connection = rusqlite::Connection::open_in_memory().unwrap();
+        crate::prepare_database(&sql_connection).unwrap();
+        sql_connection
+    }
+
+    #[test]
+    fn removed_members_lose_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("team.db");
+        let file = file.to_str().unwrap();
+        let (alice, bob, carol) = (
+            Identity::generate(),
+            Identity::generate(),
+            Identity::generate(),
+        );
+
+        let mut collection = create_collection(file, &alice).unwrap();
+        collection.add_member(&public_key(&bob)).unwrap();
+        collection.add_member(&public_key(&carol)).unwrap();
+        collection.save_password("github", "hunter2").unwrap();
+
+        let bobs_collection = open_collection(file, &bob).unwrap();
+        assert_eq!(bobs_collection.get_password("github").unwrap(), "hunter2");
+
+        collection.remove_member(&public_key(&bob)).unwrap();
+        assert!(matches!(
+            open_collection(file, &bob),
+            Err(Error::NotACollectionMember)
+        ));
+        // The key Bob had before was rotated, so it no longer opens anything.
+        assert!(matches!(
+            bobs_collection.get_password("github"),
+            Err(Error::WrongKeyForData)
+        ));
+
+        let carols_collection = open_collection(file, &carol).unwrap();
+        assert_eq!(carols_collection.get_password("github").unwrap(), "hunter2");
+        assert_eq!(collection.get_password("github").unwrap(), "hunter2");
+        assert_eq!(collection.members().unwrap().len(), 2);
+
+        collection.remove_member(&public_key(&carol)).unwrap();
+        assert!(matches!(
+            collection.remove_member(&public_key(&alice)),
+            Err(Error::CantRemoveLastMember)
+        ));
+    }
+
+    #[test]
+    fn entries_are_shared_and_received() {
+        let alice = vault();
+        crate::create_password("alice".to_string(), "github", "hunter2", &alice).unwrap();
+
+        let bob = vault();
+        let bobs_identity = get_or_create_identity("bob", &bob).unwrap();
+        let blob = share_entry("alice", "github", &public_key(&bobs_identity), &alice).unwrap();
+
+        assert_eq!(receive_entry("bob", &blob, None, &bob).unwrap(), "github");
+        assert_eq!(
+            receive_entry("bob", &blob, Some("alice's github"), &bob).unwrap(),
+            "alice's github"
+        );
+        for name in ["github", "alice's github"] {
+            let password = crate::get_password("bob".to_string(), name, &bob).unwrap();
+            assert_eq!(password.trim_end_matches('\0'), "hunter2");
+        }
+
+        // The identity is kept in the vault, sealed with the master key.
+        assert_eq!(
+            public_key(&get_or_create_identity("bob", &bob).unwrap()),
+            public_key(&bobs_identity)
+        );
+        assert!(matches!(
+            get_or_create_identity("eve", &bob),
+            Err(Error::WrongKeyForData)
+        ));
+    }
+
+    #[test]
+    fn blobs_for_someone_else_are_refused() {
+        let alice = vault();
+        crate::create_password("alice".to_string(), "github", "hunter2", &alice).unwrap();
+        let bobs_identity = get_or_create_identity("bob", &vault()).unwrap();
+        let blob = share_entry("alice", "github", &public_key(&bobs_identity), &alice).unwrap();
+
+        let carol = vault();
+        assert!(matches!(
+            receive_entry("carol", &blob, None, &carol),
+            Err(Error::WrongKeyForData)
+        ));
+        assert!(crate::list_passwords(&carol).unwrap().is_empty());
+
+        assert!(matches!(
+            receive_entry("carol", "not a blob", None, &carol),
+            Err(Error::WrongKeyForData)
+        ));
+        assert!(matches!(
+            share_entry("alice", "github", "age1nope", &alice),
+            Err(Error::InvalidPublicKey(_))
+        ));
+    }
+}
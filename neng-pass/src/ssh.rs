@@ -0,0 +1,361 @@
+//! SSH private keys kept in the vault, and an SSH agent that signs with them.
+//!
+//! Keys are stored in their own table, sealed with the master key, next to
+//! their public half so that they can be listed without unlocking anything.
+//! The agent speaks the OpenSSH agent protocol, and only offers and uses the
+//! keys while the master key can be obtained, which in practice means while
+//! the neng-pass agent is unlocked.
+
+use ssh_key::{HashAlg, LineEnding, PrivateKey, PublicKey};
+
+use crate::{crypto, pad_master_key, Error};
+
+/// An SSH key as listed from the vault, without its private half.
+#[derive(Debug, Clone)]
+pub struct StoredKey {
+    pub name: String,
+    /// The public key, in the `authorized_keys` format.
+    pub public_key: String,
+    /// Whether every use of the key has to be confirmed.
+    pub confirm: bool,
+}
+
+fn ssh_key_error(p_error: ssh_key::Error) -> Error {
+    Error::SshKeyError(p_error.to_string())
+}
+
+/// Whether an OpenSSH private key is protected with a passphrase.
+pub fn needs_passphrase(p_private_key: &str) -> Result<bool, Error> {
+    let private_key = PrivateKey::from_openssh(p_private_key).map_err(ssh_key_error)?;
+    Ok(private_key.is_encrypted())
+}
+
+/// Stores an OpenSSH private key in the vault. Keys protected with a
+/// passphrase are decrypted with `p_passphrase` first, the vault protects
+/// them from then on.
+pub fn add_key(
+    p_master_key: &str,
+    p_name: &str,
+    p_private_key: &str,
+    p_passphrase: Option<&str>,
+    p_confirm: bool,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<StoredKey, Error> {
+    if list_keys(p_sql_connection)?.iter().any(|key| key.name == p_name) {
+        return Err(Error::SshKeyAlreadyExists(Box::from(p_name)));
+    }
+
+    let mut private_key = PrivateKey::from_openssh(p_private_key).map_err(ssh_key_error)?;
+    if private_key.is_encrypted() {
+        let passphrase = p_passphrase.ok_or_else(|| {
+            Error::SshKeyError("The key is protected with a passphrase.".to_string())
+        })?;
+        private_key = private_key
+            .decrypt(passphrase)
+            .map_err(|_| Error::SshKeyError("The passphrase is incorrect.".to_string()))?;
+    }
+
+    let public_key = private_key.public_key().to_openssh().map_err(ssh_key_error)?;
+    let pem = private_key.to_openssh(LineEnding::LF).map_err(ssh_key_error)?;
+    let key = pad_master_key(p_master_key.to_string());
+
+    p_sql_connection.execute(
+        "INSERT INTO ssh_keys (name, public_key, private_key, confirm) VALUES (?, ?, ?, ?);",
+        (
+            p_name,
+            &public_key,
+            crypto::seal(key.as_bytes(), pem.as_bytes()),
+            p_confirm,
+        ),
+    )?;
+
+    Ok(StoredKey {
+        name: p_name.to_string(),
+        public_key,
+        confirm: p_confirm,
+    })
+}
+
+pub fn list_keys(p_sql_connection: &rusqlite::Connection) -> Result<Vec<StoredKey>, Error> {
+    let mut sql_statement =
+        p_sql_connection.prepare("SELECT name, public_key, confirm FROM ssh_keys ORDER BY name;")?;
+    let keys = sql_statement
+        .query_map([], |row| {
+            Ok(StoredKey {
+                name: row.get(0)?,
+                public_key: row.get(1)?,
+                confirm: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(keys)
+}
+
+pub fn delete_key(p_name: &str, p_sql_connection: &rusqlite::Connection) -> Result<(), Error> {
+    let deleted = p_sql_connection.execute("DELETE FROM ssh_keys WHERE name = ?;", [p_name])?;
+    if deleted == 0 {
+        return Err(Error::SshKeyDoesntExist(Box::from(p_name)));
+    }
+
+    Ok(())
+}
+
+/// Unseals the private half of a key.
+pub fn get_private_key(
+    p_master_key: &str,
+    p_name: &str,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<PrivateKey, Error> {
+    let sealed = p_sql_connection
+        .query_row(
+            "SELECT private_key FROM ssh_keys WHERE name = ?;",
+            [p_name],
+            |row| row.get::<_, Vec<u8>>(0),
+        )
+        .map_err(|_| Error::SshKeyDoesntExist(Box::from(p_name)))?;
+
+    let key = pad_master_key(p_master_key.to_string());
+    let pem = crypto::open(key.as_bytes(), &sealed).ok_or(Error::WrongKeyForData)?;
+    PrivateKey::from_openssh(pem).map_err(ssh_key_error)
+}
+
+/// The key in the wire format used by the agent protocol.
+fn public_key_blob(p_public_key: &str) -> Option<Vec<u8>> {
+    PublicKey::from_openssh(p_public_key).ok()?.to_bytes().ok()
+}
+
+/// Converts an RSA key for the `rsa` crate. This is done here rather than
+/// with ssh-key's own conversion, which passes the first prime twice and so
+/// never produces a usable key.
+fn rsa_private_key(p_keypair: &ssh_key::private::RsaKeypair) -> Result<rsa::RsaPrivateKey, Error> {
+    let to_uint = |p_value: &ssh_key::Mpint| {
+        p_value
+            .as_positive_bytes()
+            .map(rsa::BigUint::from_bytes_be)
+            .ok_or_else(|| Error::SshKeyError("The RSA key is malformed.".to_string()))
+    };
+
+    rsa::RsaPrivateKey::from_components(
+        to_uint(&p_keypair.public.n)?,
+        to_uint(&p_keypair.public.e)?,
+        to_uint(&p_keypair.private.d)?,
+        vec![to_uint(&p_keypair.private.p)?, to_uint(&p_keypair.private.q)?],
+    )
+    .map_err(|err| Error::SshKeyError(err.to_string()))
+}
+
+/// Signs `p_data` the way the agent protocol expects, and returns the
+/// signature blob.
+fn sign(p_private_key: &PrivateKey, p_data: &[u8], p_flags: u32) -> Result<Vec<u8>, Error> {
+    use rsa::signature::{SignatureEncoding, Signer};
+
+    const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+    const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+    let signature = match p_private_key.key_data().rsa() {
+        // Without either flag, the client asks for the original SHA-1
+        // signatures, which aren't offered.
+        Some(_) if p_flags & (SSH_AGENT_RSA_SHA2_256 | SSH_AGENT_RSA_SHA2_512) == 0 => {
+            return Err(Error::SshKeyError(
+                "SHA-1 signatures with RSA keys aren't supported".to_string(),
+            ))
+        }
+        Some(keypair) => {
+            let private_key = rsa_private_key(keypair)?;
+            let (hash, data) = if p_flags & SSH_AGENT_RSA_SHA2_256 != 0 {
+                let signing_key = rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new(private_key);
+                (HashAlg::Sha256, signing_key.try_sign(p_data).map(|signature| signature.to_vec()))
+            } else {
+                let signing_key = rsa::pkcs1v15::SigningKey::<sha2::Sha512>::new(private_key);
+                (HashAlg::Sha512, signing_key.try_sign(p_data).map(|signature| signature.to_vec()))
+            };
+
+            let data = data.map_err(|err| Error::SshKeyError(err.to_string()))?;
+            ssh_key::Signature::new(ssh_key::Algorithm::Rsa { hash: Some(hash) }, data)
+                .map_err(ssh_key_error)?
+        }
+        None => p_private_key
+            .try_sign(p_data)
+            .map_err(|err| Error::SshKeyError(err.to_string()))?,
+    };
+
+    let mut blob = Vec::new();
+    write_string(&mut blob, signature.algorithm().as_str().as_bytes());
+    write_string(&mut blob, signature.as_bytes());
+    Ok(blob)
+}
+
+fn write_string(p_buffer: &mut Vec<u8>, p_string: &[u8]) {
+    p_buffer.extend((p_string.len() as u32).to_be_bytes());
+    p_buffer.extend(p_string);
+}
+
+/// Reads a length-prefixed string from the start of `p_buffer`, and returns it
+/// along with what follows it.
+fn read_string(p_buffer: &[u8]) -> Option<(&[u8], &[u8])> {
+    let length = u32::from_be_bytes(p_buffer.get(..4)?.try_into().ok()?) as usize;
+    let string = p_buffer.get(4..4 + length)?;
+    Some((string, &p_buffer[4 + length..]))
+}
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+
+/// Requests bigger than this are refused, OpenSSH uses the same limit.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// Answers a single agent request. Anything that isn't listing keys or
+/// signing (adding keys, locking, extensions) is refused.
+fn handle_request(
+    p_request: &[u8],
+    p_master_key: Option<&str>,
+    p_sql_connection: &rusqlite::Connection,
+    p_confirm: &dyn Fn(&StoredKey) -> bool,
+) -> Vec<u8> {
+    let failure = vec![SSH_AGENT_FAILURE];
+
+    let (message_type, body) = match p_request.split_first() {
+        Some(request) => request,
+        None => return failure,
+    };
+
+    // Without the master key, the agent behaves as if it had no keys.
+    let keys = match (p_master_key, list_keys(p_sql_connection)) {
+        (Some(_), Ok(keys)) => keys,
+        _ => Vec::new(),
+    };
+
+    match *message_type {
+        SSH_AGENTC_REQUEST_IDENTITIES => {
+            let identities = keys
+                .iter()
+                .filter_map(|key| Some((public_key_blob(&key.public_key)?, &key.name)))
+                .collect::<Vec<_>>();
+
+            let mut response = vec![SSH_AGENT_IDENTITIES_ANSWER];
+            response.extend((identities.len() as u32).to_be_bytes());
+            for (blob, name) in identities {
+                write_string(&mut response, &blob);
+                write_string(&mut response, name.as_bytes());
+            }
+            response
+        }
+        SSH_AGENTC_SIGN_REQUEST => {
+            let request = read_string(body).and_then(|(blob, rest)| {
+                let (data, rest) = read_string(rest)?;
+                let flags = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?);
+                Some((blob, data, flags))
+            });
+            let (blob, data, flags) = match request {
+                Some(request) => request,
+                None => return failure,
+            };
+
+            let key = keys
+                .iter()
+                .find(|key| public_key_blob(&key.public_key).as_deref() == Some(blob));
+            let key = match key {
+                Some(key) => key,
+                None => return failure,
+            };
+
+            if key.confirm && !p_confirm(key) {
+                return failure;
+            }
+
+            let signature = p_master_key
+                .ok_or(Error::MasterKeyDoesntExist)
+                .and_then(|master_key| get_private_key(master_key, &key.name, p_sql_connection))
+                .and_then(|private_key| sign(&private_key, data, flags));
+
+            match signature {
+                Ok(signature) => {
+                    let mut response = vec![SSH_AGENT_SIGN_RESPONSE];
+                    write_string(&mut response, &signature);
+                    response
+                }
+                Err(_) => failure,
+            }
+        }
+        _ => failure,
+    }
+}
+
+/// Runs an SSH agent on `p_socket_path` until the process is stopped.
+/// `p_master_key` is asked for the master key on every request, and the keys
+/// are only available while it returns one. `p_confirm` is asked before every
+/// use of a key that needs confirmation.
+#[cfg(unix)]
+pub fn run_agent(
+    p_socket_path: &std::path::Path,
+    p_database_path: &std::path::Path,
+    p_master_key: impl Fn() -> Option<String> + Send + Sync + 'static,
+    p_confirm: impl Fn(&StoredKey) -> bool + Send + Sync + 'static,
+) -> Result<(), Error> {
+    use std::{io::Read, io::Write, sync::Arc};
+
+    let listener = crate::agent::bind_private_socket(p_socket_path)?;
+    let own_uid = unsafe { libc::getuid() };
+    let master_key = Arc::new(p_master_key);
+    let confirm = Arc::new(p_confirm);
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        if crate::agent::peer_uid(&stream) != Some(own_uid) {
+            continue;
+        }
+
+        let master_key = Arc::clone(&master_key);
+        let confirm = Arc::clone(&confirm);
+        let database_path = p_database_path.to_path_buf();
+
+        // Clients keep their connection open for several requests, so every
+        // client gets its own thread and its own connection to the vault.
+        std::thread::spawn(move || {
+            let sql_connection = match rusqlite::Connection::open(&database_path) {
+                Ok(connection) => connection,
+                Err(_) => return,
+            };
+
+            loop {
+                let mut length = [0u8; 4];
+                if stream.read_exact(&mut length).is_err() {
+                    return;
+                }
+
+                let length = u32::from_be_bytes(length) as usize;
+                if length > MAX_MESSAGE_LEN {
+                    return;
+                }
+
+                let mut request = vec![0u8; length];
+                if stream.read_exact(&mut request).is_err() {
+                    return;
+                }
+
+                let response = handle_request(
+                    &request,
+                    (*master_key)().as_deref(),
+                    &sql_connection,
+                    confirm.as_ref(),
+                );
+
+                let mut message = (response.len() as u32).to_be_bytes().to_vec();
+                message.extend(response);
+                if stream.write_all(&message).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
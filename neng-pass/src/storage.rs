@@ -0,0 +1,413 @@
+//! Where the entries of a vault are kept. The entry functions of the crate,
+//! and the modules that only deal with entries (the git credential helper,
+//! environment variables, templates, exports and git history), take any
+//! `Storage`, so a vault can live in SQLite (the default), in memory, or in a
+//! single file, and other backends only have to implement the trait.
+//!
+//! The rest of what a vault keeps still needs SQLite: the URLs, app links and
+//! browsers of autofill, SSH keys, the sharing identity, and what merges and
+//! syncs remember about other copies.
+//!
+//! Backends only ever see records as they are stored, with the password
+//! encrypted, so they never need the master key.
+
+use std::{cell::RefCell, collections::BTreeMap};
+
+use crate::{entry::Entry, Error};
+
+pub trait Storage {
+    /// The record of an entry, deleted or not.
+    fn get(&self, p_name: &str) -> Result<Option<Entry>, Error>;
+
+    /// Stores a record, replacing the one with the same name.
+    fn put(&self, p_entry: &Entry) -> Result<(), Error>;
+
+    /// Removes a record for good. Entries are deleted by storing a tombstone
+    /// instead, so that the deletion is merged into other copies of the vault.
+    fn delete(&self, p_name: &str) -> Result<(), Error>;
+
+    /// Every record, tombstones included, in no particular order.
+    fn list(&self) -> Result<Vec<Entry>, Error>;
+
+    /// Runs `p_body`, and keeps the changes it made through this storage only
+    /// if it succeeds.
+    fn transaction(&self, p_body: &mut dyn FnMut() -> Result<(), Error>) -> Result<(), Error>;
+}
+
+/// Keeps the records in memory only, for tests and for vaults that are read
+/// from somewhere else.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: RefCell<BTreeMap<String, Entry>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, Entry> {
+        self.entries.borrow().clone()
+    }
+
+    fn restore(&self, p_snapshot: BTreeMap<String, Entry>) {
+        *self.entries.borrow_mut() = p_snapshot;
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, p_name: &str) -> Result<Option<Entry>, Error> {
+        Ok(self.entries.borrow().get(p_name).cloned())
+    }
+
+    fn put(&self, p_entry: &Entry) -> Result<(), Error> {
+        self.entries
+            .borrow_mut()
+            .insert(p_entry.name.clone(), p_entry.clone());
+        Ok(())
+    }
+
+    fn delete(&self, p_name: &str) -> Result<(), Error> {
+        self.entries.borrow_mut().remove(p_name);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<Entry>, Error> {
+        Ok(self.entries.borrow().values().cloned().collect())
+    }
+
+    fn transaction(&self, p_body: &mut dyn FnMut() -> Result<(), Error>) -> Result<(), Error> {
+        let snapshot = self.snapshot();
+        let result = p_body();
+        if result.is_err() {
+            self.restore(snapshot);
+        }
+
+        result
+    }
+}
+
+#[cfg(feature = "native")]
+pub use native::FileStorage;
+
+#[cfg(feature = "native")]
+mod native {
+    use std::{
+        cell::Cell,
+        path::{Path, PathBuf},
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{MemoryStorage, Storage};
+    use crate::{entry::Entry, Error};
+
+    impl Storage for rusqlite::Connection {
+        fn get(&self, p_name: &str) -> Result<Option<Entry>, Error> {
+            let mut sql_statement = self.prepare(
+                "SELECT name, password, modified, deleted FROM passwords WHERE name = ?;",
+            )?;
+            let mut entries = sql_statement.query_map([p_name], |row| {
+                Ok(Entry {
+                    name: row.get(0)?,
+                    password: row.get(1)?,
+                    modified: row.get(2)?,
+                    deleted: row.get(3)?,
+                })
+            })?;
+
+            Ok(entries.next().transpose()?)
+        }
+
+        fn put(&self, p_entry: &Entry) -> Result<(), Error> {
+            // Connections have a `transaction` method of their own.
+            Storage::transaction(self, &mut || {
+                self.execute("DELETE FROM passwords WHERE name = ?;", [&p_entry.name])?;
+                self.execute(
+                    "INSERT INTO passwords (name, password, modified, deleted) VALUES (?, ?, ?, ?);",
+                    (
+                        &p_entry.name,
+                        &p_entry.password,
+                        p_entry.modified,
+                        p_entry.deleted,
+                    ),
+                )?;
+                Ok(())
+            })
+        }
+
+        fn delete(&self, p_name: &str) -> Result<(), Error> {
+            self.execute("DELETE FROM passwords WHERE name = ?;", [p_name])?;
+            Ok(())
+        }
+
+        fn list(&self) -> Result<Vec<Entry>, Error> {
+            let mut sql_statement =
+                self.prepare("SELECT name, password, modified, deleted FROM passwords;")?;
+            let entries = sql_statement
+                .query_map([], |row| {
+                    Ok(Entry {
+                        name: row.get(0)?,
+                        password: row.get(1)?,
+                        modified: row.get(2)?,
+                        deleted: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(entries)
+        }
+
+        fn transaction(&self, p_body: &mut dyn FnMut() -> Result<(), Error>) -> Result<(), Error> {
+            // A savepoint, unlike a transaction, also works inside another
+            // one, like the ones the rest of the crate opens.
+            self.execute_batch("SAVEPOINT storage;")?;
+
+            match p_body() {
+                Ok(()) => {
+                    self.execute_batch("RELEASE storage;")?;
+                    Ok(())
+                }
+                Err(err) => {
+                    self.execute_batch("ROLLBACK TO storage; RELEASE storage;")?;
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    impl Storage for rusqlite::Transaction<'_> {
+        fn get(&self, p_name: &str) -> Result<Option<Entry>, Error> {
+            Storage::get(&**self, p_name)
+        }
+
+        fn put(&self, p_entry: &Entry) -> Result<(), Error> {
+            Storage::put(&**self, p_entry)
+        }
+
+        fn delete(&self, p_name: &str) -> Result<(), Error> {
+            Storage::delete(&**self, p_name)
+        }
+
+        fn list(&self) -> Result<Vec<Entry>, Error> {
+            Storage::list(&**self)
+        }
+
+        fn transaction(&self, p_body: &mut dyn FnMut() -> Result<(), Error>) -> Result<(), Error> {
+            Storage::transaction(&**self, p_body)
+        }
+    }
+
+    /// What a record looks like in a storage file.
+    #[derive(Serialize, Deserialize)]
+    struct StoredEntry {
+        name: String,
+        password: String,
+        modified: i64,
+        deleted: bool,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct StorageFile {
+        entries: Vec<StoredEntry>,
+    }
+
+    /// Keeps the records in a single JSON file, which is read when the storage
+    /// is opened and rewritten after every change, or at the end of a
+    /// transaction.
+    pub struct FileStorage {
+        path: PathBuf,
+        memory: MemoryStorage,
+        /// How many transactions are running, inside each other.
+        depth: Cell<usize>,
+    }
+
+    impl FileStorage {
+        /// Opens a storage file. If it doesn't exist yet, it is created on the
+        /// first change.
+        pub fn open(p_path: impl Into<PathBuf>) -> Result<FileStorage, Error> {
+            let path = p_path.into();
+            let memory = MemoryStorage::new();
+
+            if path.exists() {
+                let invalid = || {
+                    Error::SyntaxError(format!(
+                        "{} is not a neng-pass storage file.",
+                        path.display()
+                    ))
+                };
+
+                let file: StorageFile =
+                    serde_json::from_slice(&std::fs::read(&path)?).map_err(|_| invalid())?;
+                for entry in file.entries {
+                    memory.put(&Entry {
+                        password: hex::decode(&entry.password).map_err(|_| invalid())?,
+                        name: entry.name,
+                        modified: entry.modified,
+                        deleted: entry.deleted,
+                    })?;
+                }
+            }
+
+            Ok(FileStorage {
+                path,
+                memory,
+                depth: Cell::new(0),
+            })
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        fn save(&self) -> Result<(), Error> {
+            let file = StorageFile {
+                entries: self
+                    .memory
+                    .list()?
+                    .into_iter()
+                    .map(|entry| StoredEntry {
+                        password: hex::encode(&entry.password),
+                        name: entry.name,
+                        modified: entry.modified,
+                        deleted: entry.deleted,
+                    })
+                    .collect(),
+            };
+
+            let content = serde_json::to_vec(&file).expect("serializing entries can't fail");
+            crate::write_private_file(&self.path, &content)?;
+            Ok(())
+        }
+    }
+
+    impl Storage for FileStorage {
+        fn get(&self, p_name: &str) -> Result<Option<Entry>, Error> {
+            self.memory.get(p_name)
+        }
+
+        fn put(&self, p_entry: &Entry) -> Result<(), Error> {
+            self.transaction(&mut || self.memory.put(p_entry))
+        }
+
+        fn delete(&self, p_name: &str) -> Result<(), Error> {
+            self.transaction(&mut || self.memory.delete(p_name))
+        }
+
+        fn list(&self) -> Result<Vec<Entry>, Error> {
+            self.memory.list()
+        }
+
+        fn transaction(&self, p_body: &mut dyn FnMut() -> Result<(), Error>) -> Result<(), Error> {
+            let snapshot = self.memory.snapshot();
+
+            self.depth.set(self.depth.get() + 1);
+            let result = p_body();
+            self.depth.set(self.depth.get() - 1);
+
+            // The file is only written once the outermost transaction is done.
+            let result = result.and_then(|_| match self.depth.get() {
+                0 => self.save(),
+                _ => Ok(()),
+            });
+
+            if result.is_err() {
+                self.memory.restore(snapshot);
+            }
+
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(p_name: &str, p_modified: i64) -> Entry {
+        Entry {
+            name: p_name.to_string(),
+            password: vec![1, 2, 3],
+            modified: p_modified,
+            deleted: false,
+        }
+    }
+
+    /// What every backend has to do.
+    fn check_backend(p_storage: &dyn Storage) {
+        assert!(p_storage.get("github").unwrap().is_none());
+
+        p_storage.put(&entry("github", 1)).unwrap();
+        p_storage.put(&entry("gitlab", 1)).unwrap();
+        p_storage.put(&entry("github", 2)).unwrap();
+        assert_eq!(p_storage.get("github").unwrap(), Some(entry("github", 2)));
+        assert_eq!(p_storage.list().unwrap().len(), 2);
+
+        p_storage.delete("gitlab").unwrap();
+        assert_eq!(p_storage.list().unwrap(), [entry("github", 2)]);
+
+        // A failed transaction leaves nothing behind, even from the ones
+        // inside it that succeeded.
+        let result = p_storage.transaction(&mut || {
+            p_storage.transaction(&mut || p_storage.put(&entry("gitea", 3)))?;
+            p_storage.delete("github")?;
+            Err(Error::PasswordAlreadyExists)
+        });
+        assert!(matches!(result, Err(Error::PasswordAlreadyExists)));
+        assert_eq!(p_storage.list().unwrap(), [entry("github", 2)]);
+
+        p_storage
+            .transaction(&mut || p_storage.put(&entry("gitea", 3)))
+            .unwrap();
+        assert_eq!(p_storage.get("gitea").unwrap(), Some(entry("gitea", 3)));
+    }
+
+    #[test]
+    fn memory_storage_works() {
+        check_backend(&MemoryStorage::new());
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn sqlite_storage_works() {
+        let sql_connection = rusqlite::Connection::open_in_memory().unwrap();
+        crate::prepare_database(&sql_connection).unwrap();
+        check_backend(&sql_connection);
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn file_storage_works() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("vault.json");
+
+        check_backend(&FileStorage::open(&path).unwrap());
+
+        let reopened = FileStorage::open(&path).unwrap();
+        let mut names: Vec<_> = reopened
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, ["gitea", "github"]);
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn entries_can_live_in_memory() {
+        let storage = MemoryStorage::new();
+        crate::create_password("hunter2".to_string(), "github", "hunter3", &storage).unwrap();
+        assert_eq!(
+            crate::get_password("hunter2".to_string(), "github", &storage)
+                .unwrap()
+                .trim_end_matches('\0'),
+            "hunter3"
+        );
+
+        crate::delete_password("github", &storage).unwrap();
+        assert!(crate::list_passwords(&storage).unwrap().is_empty());
+        assert!(storage.get("github").unwrap().unwrap().deleted);
+    }
+}
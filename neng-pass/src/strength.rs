@@ -0,0 +1,114 @@
+//! Estimates how hard a password is to guess, to warn about weak ones.
+//!
+//! This is a rough estimate: every character adds the entropy of the smallest
+//! set of characters it could have been picked from, except for characters
+//! that repeat or continue a run (`aaa`, `abc`, `321`), which add almost
+//! nothing. Common passwords are recognized regardless of their case and of
+//! digits added at the end.
+
+/// The most common passwords, without the digits usually added to them.
+const COMMON_PASSWORDS: &[&str] = &[
+    "password",
+    "qwerty",
+    "qwertyuiop",
+    "asdfgh",
+    "azerty",
+    "letmein",
+    "welcome",
+    "admin",
+    "administrator",
+    "login",
+    "iloveyou",
+    "monkey",
+    "dragon",
+    "football",
+    "baseball",
+    "master",
+    "sunshine",
+    "princess",
+    "shadow",
+    "superman",
+    "trustno",
+    "passw0rd",
+    "secret",
+    "abc",
+];
+
+/// How many guesses a common password takes at most, as bits.
+const COMMON_PASSWORD_ENTROPY: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Strength {
+    /// The estimated entropy, in bits: the base 2 logarithm of the number of
+    /// guesses it takes to find the password.
+    pub entropy: f64,
+    /// From 0 (very weak) to 4 (very strong).
+    pub score: u8,
+}
+
+impl Strength {
+    pub fn label(&self) -> &'static str {
+        match self.score {
+            0 => "very weak",
+            1 => "weak",
+            2 => "fair",
+            3 => "strong",
+            _ => "very strong",
+        }
+    }
+}
+
+/// The size of the set of characters `p_character` was likely picked from.
+fn pool_size(p_character: char) -> f64 {
+    if p_character.is_ascii_lowercase() || p_character.is_ascii_uppercase() {
+        26.0
+    } else if p_character.is_ascii_digit() {
+        10.0
+    } else if p_character.is_ascii_graphic() || p_character == ' ' {
+        33.0
+    } else {
+        100.0
+    }
+}
+
+fn is_common(p_password: &str) -> bool {
+    let word = p_password
+        .trim_end_matches(|character: char| character.is_ascii_digit())
+        .to_lowercase();
+
+    // Passwords made of digits only, like 123456, are common too.
+    (word.is_empty() && p_password.len() <= 10) || COMMON_PASSWORDS.contains(&word.as_str())
+}
+
+pub fn estimate(p_password: &str) -> Strength {
+    let entropy = if is_common(p_password) {
+        COMMON_PASSWORD_ENTROPY.min(p_password.len() as f64 * 10f64.log2())
+    } else {
+        let mut entropy = 0.0;
+        let mut previous: Option<char> = None;
+
+        for character in p_password.chars() {
+            let continues_run =
+                previous.is_some_and(|previous| (character as i64 - previous as i64).abs() <= 1);
+
+            entropy += if continues_run {
+                1.0
+            } else {
+                pool_size(character).log2()
+            };
+            previous = Some(character);
+        }
+
+        entropy
+    };
+
+    let score = match entropy {
+        entropy if entropy < 28.0 => 0,
+        entropy if entropy < 36.0 => 1,
+        entropy if entropy < 50.0 => 2,
+        entropy if entropy < 64.0 => 3,
+        _ => 4,
+    };
+
+    Strength { entropy, score }
+}
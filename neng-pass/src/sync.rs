@@ -0,0 +1,422 @@
+//! Client side of the sync protocol spoken by `neng-pass-server`.
+//!
+//! The server only ever stores opaque blobs. Every entry is sealed before it
+//! leaves the machine, and is identified by a keyed hash of its name, so
+//! neither names nor passwords are visible to the server. The keys for both
+//! are derived from the master key with Argon2id, and a salt the server keeps
+//! for the vault, so what the server stores can't be used to guess the master
+//! key any faster than that.
+//!
+//! Every blob has a revision number, and uploads state the revision they
+//! replace, so two machines can never silently overwrite each other.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{
+    crypto::{self, KdfParams},
+    merge::{self, Conflict, Entry, MergeReport, Resolution},
+    Error,
+};
+
+/// A sealed entry, as stored on the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteBlob {
+    pub id: String,
+    pub revision: u64,
+    /// The hex encoded sealed entry.
+    pub data: String,
+}
+
+/// The response to `GET /v1/blobs?since=N`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobList {
+    /// The latest revision on the server.
+    pub revision: u64,
+    /// Every blob changed after the requested revision.
+    pub blobs: Vec<RemoteBlob>,
+}
+
+/// The body of `PUT /v1/blobs/{id}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PutBlob {
+    /// The revision of the blob that is being replaced, or 0 for a new blob.
+    pub base_revision: u64,
+    pub data: String,
+}
+
+/// The response to a successful `PUT /v1/blobs/{id}`. A rejected upload gets
+/// a `409 Conflict` with the current `RemoteBlob` instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PutResponse {
+    pub revision: u64,
+}
+
+/// The response to `GET /v1/key`, and the body of `PUT /v1/key`: the salt and
+/// the Argon2id parameters the sync keys of the vault are derived with. The
+/// first machine to sync picks them, and the server keeps them for the others.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyParams {
+    /// The hex encoded salt.
+    pub salt: String,
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KeyParams {
+    /// A new salt, with the default parameters.
+    pub fn generate() -> KeyParams {
+        let params = KdfParams::default();
+        KeyParams {
+            salt: hex::encode(crypto::random_salt()),
+            memory: params.memory,
+            iterations: params.iterations,
+            parallelism: params.parallelism,
+        }
+    }
+
+    fn kdf_params(&self) -> KdfParams {
+        KdfParams {
+            memory: self.memory,
+            iterations: self.iterations,
+            parallelism: self.parallelism,
+        }
+    }
+
+    /// Whether the salt and parameters can be used. They come from the server,
+    /// which must not be able to make deriving the keys hang.
+    pub fn is_valid(&self) -> bool {
+        self.salt.len() == 2 * crypto::SALT_LEN
+            && hex::decode(&self.salt).is_ok()
+            && self.kdf_params().is_acceptable()
+    }
+}
+
+/// The keys entries are identified and sealed with on the server, derived
+/// from the master key.
+pub struct SyncKeys {
+    id_key: Zeroizing<[u8; 32]>,
+    seal_key: Zeroizing<[u8; 32]>,
+}
+
+impl SyncKeys {
+    /// Derives the keys with the salt and parameters kept on the server,
+    /// storing new ones there if this is the first machine to sync.
+    pub fn fetch(p_client: &SyncClient, p_master_key: &str) -> Result<SyncKeys, Error> {
+        let params = match p_client.key_params()? {
+            Some(params) => params,
+            None => p_client.put_key_params(&KeyParams::generate())?,
+        };
+
+        SyncKeys::derive(p_master_key, &params)
+    }
+
+    pub fn derive(p_master_key: &str, p_params: &KeyParams) -> Result<SyncKeys, Error> {
+        if !p_params.is_valid() {
+            return Err(Error::SyncError(
+                "The server sent an invalid salt or key derivation parameters.".to_string(),
+            ));
+        }
+
+        let salt = hex::decode(&p_params.salt).expect("the salt was checked");
+        let root = Zeroizing::new(crypto::derive_key(
+            p_master_key,
+            &salt,
+            p_params.kdf_params(),
+        )?);
+
+        // Separate keys, so that the identifiers, which the server sees as
+        // they are, reveal nothing about the key the entries are sealed with.
+        Ok(SyncKeys {
+            id_key: Zeroizing::new(crypto::keyed_hash(root.as_ref(), b"neng-pass sync id")),
+            seal_key: Zeroizing::new(crypto::keyed_hash(root.as_ref(), b"neng-pass sync seal")),
+        })
+    }
+}
+
+/// What the entry looks like before being sealed.
+#[derive(Serialize, Deserialize)]
+struct SealedEntry {
+    name: String,
+    modified: i64,
+    deleted: bool,
+    password: String,
+}
+
+pub enum PutOutcome {
+    Stored(u64),
+    /// Somebody else changed the blob first.
+    Rejected(RemoteBlob),
+}
+
+pub struct SyncClient {
+    url: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl SyncClient {
+    /// `p_url` is the base URL of the server, such as `http://localhost:8080`.
+    pub fn new(p_url: &str, p_token: Option<&str>) -> SyncClient {
+        SyncClient {
+            url: p_url.trim_end_matches('/').to_string(),
+            token: p_token.map(|token| token.to_string()),
+            agent: ureq::AgentBuilder::new().build(),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn request(&self, p_method: &str, p_path: &str) -> ureq::Request {
+        let request = self
+            .agent
+            .request(p_method, &format!("{}{}", self.url, p_path));
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    pub fn fetch(&self, p_since: u64) -> Result<BlobList, Error> {
+        self.request("GET", &format!("/v1/blobs?since={}", p_since))
+            .call()
+            .map_err(sync_error)?
+            .into_json()
+            .map_err(|err| Error::SyncError(format!("Invalid response: {}", err)))
+    }
+
+    /// The salt and parameters of the sync keys, if a machine already synced.
+    pub fn key_params(&self) -> Result<Option<KeyParams>, Error> {
+        match self.request("GET", "/v1/key").call() {
+            Ok(response) => response
+                .into_json()
+                .map(Some)
+                .map_err(|err| Error::SyncError(format!("Invalid response: {}", err))),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(err) => Err(sync_error(err)),
+        }
+    }
+
+    /// Stores the salt and parameters of the sync keys, unless another machine
+    /// did first. Returns the ones that are in use either way.
+    pub fn put_key_params(&self, p_params: &KeyParams) -> Result<KeyParams, Error> {
+        self.request("PUT", "/v1/key")
+            .send_json(p_params)
+            .map_err(sync_error)?
+            .into_json()
+            .map_err(|err| Error::SyncError(format!("Invalid response: {}", err)))
+    }
+
+    pub fn put(&self, p_id: &str, p_base_revision: u64, p_data: String) -> Result<PutOutcome, Error> {
+        let response = self.request("PUT", &format!("/v1/blobs/{}", p_id)).send_json(PutBlob {
+            base_revision: p_base_revision,
+            data: p_data,
+        });
+
+        match response {
+            Ok(response) => {
+                let response: PutResponse = response
+                    .into_json()
+                    .map_err(|err| Error::SyncError(format!("Invalid response: {}", err)))?;
+                Ok(PutOutcome::Stored(response.revision))
+            }
+            Err(ureq::Error::Status(409, response)) => {
+                let current: RemoteBlob = response
+                    .into_json()
+                    .map_err(|err| Error::SyncError(format!("Invalid response: {}", err)))?;
+                Ok(PutOutcome::Rejected(current))
+            }
+            Err(err) => Err(sync_error(err)),
+        }
+    }
+}
+
+fn sync_error(p_error: ureq::Error) -> Error {
+    match p_error {
+        ureq::Error::Status(401, _) => {
+            Error::SyncError("The server rejected the access token.".to_string())
+        }
+        ureq::Error::Status(status, response) => Error::SyncError(format!(
+            "The server responded with {}: {}",
+            status,
+            response.into_string().unwrap_or_default()
+        )),
+        ureq::Error::Transport(err) => Error::SyncError(err.to_string()),
+    }
+}
+
+/// The identifier of an entry on the server.
+fn blob_id(p_keys: &SyncKeys, p_name: &str) -> String {
+    let id = crypto::keyed_hash(p_keys.id_key.as_ref(), p_name.as_bytes());
+    hex::encode(id)
+}
+
+fn seal_entry(p_keys: &SyncKeys, p_entry: &Entry) -> String {
+    let sealed = SealedEntry {
+        name: p_entry.name.clone(),
+        modified: p_entry.modified,
+        deleted: p_entry.deleted,
+        password: hex::encode(&p_entry.password),
+    };
+
+    let plaintext = serde_json::to_vec(&sealed).expect("serializing an entry can't fail");
+    hex::encode(crypto::seal(p_keys.seal_key.as_ref(), &plaintext))
+}
+
+fn open_blob(p_keys: &SyncKeys, p_blob: &RemoteBlob) -> Result<Entry, Error> {
+    let sealed = hex::decode(&p_blob.data).map_err(|_| Error::WrongKeyForData)?;
+    let plaintext =
+        crypto::open(p_keys.seal_key.as_ref(), &sealed).ok_or(Error::WrongKeyForData)?;
+    let entry: SealedEntry =
+        serde_json::from_slice(&plaintext).map_err(|_| Error::WrongKeyForData)?;
+
+    // A blob stored under the wrong identifier has been moved around.
+    if blob_id(p_keys, &entry.name) != p_blob.id {
+        return Err(Error::WrongKeyForData);
+    }
+
+    Ok(Entry {
+        name: entry.name,
+        modified: entry.modified,
+        deleted: entry.deleted,
+        password: hex::decode(entry.password).map_err(|_| Error::WrongKeyForData)?,
+    })
+}
+
+/// The revision and version of every entry as of the last sync with a server.
+fn read_sync_state(
+    p_sql_connection: &rusqlite::Connection,
+    p_url: &str,
+) -> Result<BTreeMap<String, (u64, i64)>, Error> {
+    let mut sql_statement = p_sql_connection
+        .prepare("SELECT name, revision, modified FROM sync_entries WHERE url = ?;")?;
+    let state = sql_statement
+        .query_map([p_url], |row| {
+            Ok((row.get::<_, String>(0)?, (row.get::<_, u64>(1)?, row.get::<_, i64>(2)?)))
+        })?
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+    Ok(state)
+}
+
+fn set_sync_state(
+    p_sql_connection: &rusqlite::Connection,
+    p_url: &str,
+    p_name: &str,
+    p_revision: u64,
+    p_modified: i64,
+) -> Result<(), Error> {
+    p_sql_connection.execute(
+        "INSERT OR REPLACE INTO sync_entries (url, name, revision, modified) VALUES (?, ?, ?, ?);",
+        (p_url, p_name, p_revision, p_modified),
+    )?;
+
+    Ok(())
+}
+
+/// Downloads everything that changed on the server since the last pull and
+/// merges it into the local vault, asking `p_resolve` about conflicts.
+pub fn pull(
+    p_client: &SyncClient,
+    p_keys: &SyncKeys,
+    p_sql_connection: &rusqlite::Connection,
+    p_resolve: impl FnMut(&Conflict) -> Option<Resolution>,
+) -> Result<MergeReport, Error> {
+    let url = p_client.url();
+
+    let since = p_sql_connection
+        .query_row("SELECT revision FROM sync_servers WHERE url = ?;", [url], |row| {
+            row.get::<_, u64>(0)
+        })
+        .unwrap_or(0);
+
+    let list = p_client.fetch(since)?;
+    let remote_entries = list
+        .blobs
+        .iter()
+        .map(|blob| Ok((open_blob(p_keys, blob)?, blob.revision)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let state = read_sync_state(p_sql_connection, url)?;
+    let local_entries = merge::read_entries(p_sql_connection)?;
+
+    let report = merge::apply_merge(
+        p_sql_connection,
+        url,
+        remote_entries.iter().map(|(remote, _)| {
+            let base = state.get(&remote.name).map(|(_, modified)| *modified);
+            (
+                remote.name.clone(),
+                base,
+                local_entries.get(&remote.name),
+                Some(remote),
+            )
+        }),
+        p_resolve,
+    )?;
+
+    let mut next_since = list.revision;
+    for (remote, revision) in &remote_entries {
+        if report
+            .unresolved
+            .iter()
+            .any(|conflict| conflict.name == remote.name)
+        {
+            // Fetch it again next time, so the conflict isn't forgotten.
+            next_since = next_since.min(revision.saturating_sub(1));
+        } else {
+            set_sync_state(p_sql_connection, url, &remote.name, *revision, remote.modified)?;
+        }
+    }
+
+    p_sql_connection.execute(
+        "INSERT OR REPLACE INTO sync_servers (url, revision) VALUES (?, ?);",
+        (url, next_since),
+    )?;
+
+    Ok(report)
+}
+
+#[derive(Debug, Default)]
+pub struct PushReport {
+    pub pushed: Vec<String>,
+    /// Entries that were changed on the server since the last pull. Pull to
+    /// merge them, then push again.
+    pub rejected: Vec<String>,
+}
+
+/// Uploads every entry that changed locally since the last sync.
+pub fn push(
+    p_client: &SyncClient,
+    p_keys: &SyncKeys,
+    p_sql_connection: &rusqlite::Connection,
+) -> Result<PushReport, Error> {
+    let url = p_client.url();
+    let state = read_sync_state(p_sql_connection, url)?;
+
+    let mut report = PushReport::default();
+
+    for entry in merge::read_entries(p_sql_connection)?.values() {
+        let base_revision = match state.get(&entry.name) {
+            Some((_, modified)) if *modified == entry.modified => continue,
+            Some((revision, _)) => *revision,
+            None => 0,
+        };
+
+        let id = blob_id(p_keys, &entry.name);
+        match p_client.put(&id, base_revision, seal_entry(p_keys, entry))? {
+            PutOutcome::Stored(revision) => {
+                set_sync_state(p_sql_connection, url, &entry.name, revision, entry.modified)?;
+                report.pushed.push(entry.name.clone());
+            }
+            PutOutcome::Rejected(_) => report.rejected.push(entry.name.clone()),
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,246 @@
+//! Renders configuration files that refer to passwords in the vault.
+//!
+//! A reference looks like `{{ neng "prod/db" "password" }}`, where the second
+//! string is the field of the entry and defaults to `password`. Anything else
+//! between `{{` and `}}` is left alone, so templates can also be used by other
+//! tools that share the same braces.
+
+use crate::{storage::Storage, Error};
+
+/// The fields of an entry that a template can refer to.
+pub const FIELDS: &[&str] = &["password"];
+
+/// A reference to a field of an entry.
+struct Reference {
+    name: String,
+    field: String,
+}
+
+enum Piece<'a> {
+    Text(&'a str),
+    Reference(Reference),
+}
+
+/// Reads the quoted strings of a reference, with `\"` and `\\` as escapes.
+fn parse_strings(p_arguments: &str) -> Result<Vec<String>, String> {
+    let mut strings = Vec::new();
+    let mut chars = p_arguments.trim().chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        if c != '"' {
+            return Err("the name and field must be quoted with \"".to_string());
+        }
+
+        let mut string = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some(escaped) => string.push(escaped),
+                    None => return Err("unterminated string".to_string()),
+                },
+                Some(c) => string.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+
+        if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+            return Err("strings must be separated by spaces".to_string());
+        }
+
+        strings.push(string);
+    }
+
+    Ok(strings)
+}
+
+/// Finds the `}}` that closes a reference, skipping the ones inside quoted
+/// strings, since entry names can contain anything.
+fn find_closing_braces(p_arguments: &str) -> Option<usize> {
+    let mut is_quoted = false;
+    let mut chars = p_arguments.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' if is_quoted => {
+                chars.next();
+            }
+            '"' => is_quoted = !is_quoted,
+            '}' if !is_quoted && p_arguments[index + 1..].starts_with('}') => return Some(index),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn parse(p_template: &str) -> Result<Vec<Piece<'_>>, Error> {
+    let mut pieces = Vec::new();
+    let mut rest = p_template;
+
+    while let Some(start) = rest.find("{{") {
+        let offset = p_template.len() - rest.len() + start;
+        let line = p_template[..offset].matches('\n').count() + 1;
+        let syntax_error = |message: String| Error::SyntaxError(format!("line {}: {}", line, message));
+
+        let after = &rest[start + 2..];
+        let arguments = after.trim_start().strip_prefix("neng").filter(|arguments| {
+            arguments.is_empty()
+                || arguments.starts_with(char::is_whitespace)
+                || arguments.starts_with("}}")
+        });
+
+        let arguments = match arguments {
+            Some(arguments) => arguments,
+            None => match after.find("}}") {
+                Some(end) => {
+                    // Not one of ours.
+                    let end = start + 2 + end + 2;
+                    pieces.push(Piece::Text(&rest[..end]));
+                    rest = &rest[end..];
+                    continue;
+                }
+                None => break,
+            },
+        };
+
+        let end = find_closing_braces(arguments)
+            .ok_or_else(|| syntax_error("the reference is never closed with }}".to_string()))?;
+        let next = &arguments[end + 2..];
+        let arguments = &arguments[..end];
+
+        let mut strings = parse_strings(arguments).map_err(syntax_error)?.into_iter();
+        let name = strings
+            .next()
+            .ok_or_else(|| syntax_error("the reference doesn't name an entry".to_string()))?;
+        let field = strings.next().unwrap_or_else(|| "password".to_string());
+        if strings.next().is_some() {
+            return Err(syntax_error("a reference takes a name and a field, nothing else".to_string()));
+        }
+
+        if !FIELDS.contains(&field.as_str()) {
+            return Err(syntax_error(format!(
+                "entries have no field named '{}', only {}",
+                field,
+                FIELDS.join(", ")
+            )));
+        }
+
+        pieces.push(Piece::Text(&rest[..start]));
+        pieces.push(Piece::Reference(Reference { name, field }));
+        rest = next;
+    }
+
+    pieces.push(Piece::Text(rest));
+    Ok(pieces)
+}
+
+/// Replaces every reference in a template with the value from the vault.
+/// Nothing is rendered unless every reference can be resolved.
+pub fn render(
+    p_master_key: &str,
+    p_template: &str,
+    p_storage: &dyn Storage,
+) -> Result<String, Error> {
+    parse(p_template)?
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => Ok(text.to_string()),
+            Piece::Reference(reference) => match reference.field.as_str() {
+                "password" => {
+                    let password =
+                        crate::get_password(p_master_key.to_string(), &reference.name, p_storage)?;
+                    Ok(password.trim_end_matches('\0').to_string())
+                }
+                _ => unreachable!("fields are checked while parsing"),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn vault() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        for (name, password) in [
+            ("prod/db", "hunter2"),
+            ("a}}b", "braces"),
+            ("say \"hi\"", "quotes"),
+        ] {
+            crate::create_password("key".to_string(), name, password, &storage).unwrap();
+        }
+        storage
+    }
+
+    fn error(p_template: &str) -> String {
+        match render("key", p_template, &vault()) {
+            Err(Error::SyntaxError(message)) => message,
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn references_are_replaced() {
+        let template = "\
+user = admin
+password = {{ neng \"prod/db\" }}
+again = {{neng \"prod/db\" \"password\"}}
+braces = {{ neng \"a}}b\" }}
+quotes = {{ neng \"say \\\"hi\\\"\" }}
+other = {{ .Values.name }} {{ nenglish }}
+stray = {{ not closed
+";
+
+        assert_eq!(
+            render("key", template, &vault()).unwrap(),
+            "\
+user = admin
+password = hunter2
+again = hunter2
+braces = braces
+quotes = quotes
+other = {{ .Values.name }} {{ nenglish }}
+stray = {{ not closed
+"
+        );
+    }
+
+    #[test]
+    fn malformed_references_are_refused() {
+        assert_eq!(
+            error("a\n{{ neng \"prod/db\""),
+            "line 2: the reference is never closed with }}"
+        );
+        assert_eq!(
+            error("{{ neng \"a}} }}"),
+            "line 1: the reference is never closed with }}"
+        );
+        assert_eq!(
+            error("{{ neng prod/db }}"),
+            "line 1: the name and field must be quoted with \""
+        );
+        assert_eq!(
+            error("{{ neng }}"),
+            "line 1: the reference doesn't name an entry"
+        );
+        assert_eq!(
+            error("{{ neng \"prod/db\" \"username\" }}"),
+            "line 1: entries have no field named 'username', only password"
+        );
+        assert_eq!(
+            error("{{ neng \"prod/db\" \"password\" \"x\" }}"),
+            "line 1: a reference takes a name and a field, nothing else"
+        );
+        assert!(matches!(
+            render("key", "{{ neng \"missing\" }}", &vault()),
+            Err(Error::PasswordDoesntExist(_))
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+//! Vaults, wherever they are. A vault is either a directory with a
+//! `passwords.db` and a `master_key` in it, or a single `.nengvault` file (see
+//! `vault_file`).
+//!
+//! An open `Vault` owns its database and its master key, so any number of
+//! vaults can be open at the same time, each with its own key.
+
+use std::path::{Path, PathBuf};
+
+use zeroize::Zeroizing;
+
+use crate::{vault_file::VaultFile, Error};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Directory(PathBuf),
+    File(PathBuf),
+}
+
+impl Location {
+    /// A `.nengvault` path is a single-file vault, anything else a directory.
+    pub fn from_path(p_path: impl Into<PathBuf>) -> Location {
+        let path = p_path.into();
+        if crate::vault_file::is_vault_file(&path) {
+            Location::File(path)
+        } else {
+            Location::Directory(path)
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Location::Directory(path) | Location::File(path) => path,
+        }
+    }
+
+    /// Whether the vault was created, that is whether it has a master key.
+    pub fn exists(&self) -> bool {
+        match self {
+            Location::Directory(path) => path.join("master_key").exists(),
+            Location::File(path) => path.exists(),
+        }
+    }
+
+    /// Creates an empty vault with this master key.
+    pub fn create(&self, p_master_key: &str) -> Result<(), Error> {
+        match self {
+            Location::Directory(path) => {
+                std::fs::create_dir_all(path)?;
+                crate::set_master_key(path_to_str(&path.join("master_key"))?, p_master_key)
+            }
+            Location::File(path) => VaultFile::create(path, p_master_key).map(|_| ()),
+        }
+    }
+}
+
+fn path_to_str(p_path: &Path) -> Result<&str, Error> {
+    p_path.to_str().ok_or_else(|| {
+        Error::SyntaxError(format!("{} is not a valid UTF-8 path.", p_path.display()))
+    })
+}
+
+enum Backend {
+    Directory(rusqlite::Connection),
+    File(VaultFile),
+}
+
+pub struct Vault {
+    location: Location,
+    master_key: Zeroizing<String>,
+    backend: Backend,
+}
+
+impl Vault {
+    /// Opens a vault, checking the master key.
+    pub fn open(p_location: Location, p_master_key: &str) -> Result<Vault, Error> {
+        let backend = match &p_location {
+            Location::Directory(path) => {
+                crate::query_master_key(path_to_str(&path.join("master_key"))?, p_master_key)?;
+
+                let sql_connection = rusqlite::Connection::open(path.join("passwords.db"))?;
+                crate::prepare_database(&sql_connection)?;
+                Backend::Directory(sql_connection)
+            }
+            Location::File(path) => Backend::File(VaultFile::open(path, p_master_key)?),
+        };
+
+        Ok(Vault {
+            location: p_location,
+            master_key: Zeroizing::new(p_master_key.to_string()),
+            backend,
+        })
+    }
+
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    pub fn master_key(&self) -> &str {
+        &self.master_key
+    }
+
+    /// The database of the vault. Changes to a single-file vault are only kept
+    /// once it is saved.
+    pub fn sql_connection(&self) -> &rusqlite::Connection {
+        match &self.backend {
+            Backend::Directory(sql_connection) => sql_connection,
+            Backend::File(vault_file) => vault_file.sql_connection(),
+        }
+    }
+
+    /// Keeps this many of the previous versions of a single-file vault when it
+    /// is saved. Directories don't have backups.
+    pub fn set_backups(&mut self, p_backups: usize) {
+        if let Backend::File(vault_file) = &mut self.backend {
+            vault_file.set_backups(p_backups);
+        }
+    }
+
+    /// Writes the changes to a single-file vault. Directories are written to as
+    /// they are changed, so there is nothing to do for them.
+    pub fn save(&mut self) -> Result<(), Error> {
+        match &mut self.backend {
+            Backend::Directory(_) => Ok(()),
+            Backend::File(vault_file) => vault_file.save().map(|_| ()),
+        }
+    }
+}
@@ -0,0 +1,342 @@
+//! Single-file vaults: the whole vault in one encrypted file, instead of a
+//! `passwords.db` next to a `master_key`, so it can be carried on a USB stick
+//! or synced with any file-sync tool.
+//!
+//! The file starts with a header: `NPVT`, the version of the format, and the
+//! Argon2id parameters and salt of the key, which is derived from the master
+//! key. The rest is the SQLite database of the vault, sealed with that key,
+//! header included, so nothing in the file can be changed without the master
+//! key. Opening the file is what checks the master key.
+//!
+//! While it is open, the vault is an in-memory database, so everything that
+//! works on a `passwords.db` works on it too. It is written back with `save`,
+//! to a temporary file that then replaces the vault, so the vault is never
+//! left half written. If another program wrote the file in the meantime, like
+//! a file-sync tool, `save` refuses to overwrite its changes.
+
+use std::{
+    path::{Path, PathBuf},
+    ptr::NonNull,
+};
+
+use argon2::password_hash::Error as Argon2Error;
+use rusqlite::{serialize::OwnedData, DatabaseName};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{
+    crypto::{self, FileHeader},
+    write_private_file, Error, MAX_MASTER_KEY_LEN,
+};
+
+pub const MAGIC: &[u8; 4] = b"NPVT";
+pub const VERSION: u8 = 1;
+pub const EXTENSION: &str = "nengvault";
+
+/// Whether `p_path` names a single-file vault rather than a directory.
+pub fn is_vault_file(p_path: &Path) -> bool {
+    p_path
+        .extension()
+        .is_some_and(|extension| extension == EXTENSION)
+}
+
+pub struct VaultFile {
+    path: PathBuf,
+    header: FileHeader,
+    key: Zeroizing<[u8; MAX_MASTER_KEY_LEN]>,
+    sql_connection: rusqlite::Connection,
+    /// The database as it is in the file, to only write the file when
+    /// something changed.
+    saved: Zeroizing<Vec<u8>>,
+    /// The hash of the file as it was read or last written, to notice when
+    /// another program changed it.
+    file_hash: Option<[u8; 32]>,
+    /// How many of the previous versions of the file are kept.
+    backups: usize,
+}
+
+impl VaultFile {
+    /// Creates a new, empty vault with this master key.
+    pub fn create(p_path: impl Into<PathBuf>, p_master_key: &str) -> Result<VaultFile, Error> {
+        let path = p_path.into();
+        if path.exists() {
+            return Err(Error::MasterKeyAlreadyExists);
+        }
+
+        if p_master_key.len() > MAX_MASTER_KEY_LEN {
+            return Err(Error::MasterKeyTooLong);
+        }
+
+        let header = FileHeader::new(MAGIC, VERSION);
+        let sql_connection = rusqlite::Connection::open_in_memory()?;
+        crate::prepare_database(&sql_connection)?;
+
+        let mut vault = VaultFile {
+            path,
+            key: Zeroizing::new(header.derive_key(p_master_key)?),
+            header,
+            sql_connection,
+            saved: Zeroizing::new(Vec::new()),
+            file_hash: None,
+            backups: 0,
+        };
+        vault.save()?;
+
+        Ok(vault)
+    }
+
+    /// Opens a vault. Fails with the same error as `query_master_key` if the
+    /// master key is wrong.
+    pub fn open(p_path: impl Into<PathBuf>, p_master_key: &str) -> Result<VaultFile, Error> {
+        let path = p_path.into();
+        if !path.exists() {
+            return Err(Error::MasterKeyDoesntExist);
+        }
+
+        let content = std::fs::read(&path)?;
+        let header = FileHeader::parse(MAGIC, &content).ok_or_else(|| {
+            Error::SyntaxError(format!("{} is not a neng-pass vault.", path.display()))
+        })?;
+
+        if header.version != VERSION {
+            return Err(Error::SyntaxError(format!(
+                "{} is in version {} of the format, which this version of neng-pass can't read.",
+                path.display(),
+                header.version
+            )));
+        }
+
+        // The header is only checked once the key is derived, so a tampered
+        // one must not be able to ask for more time or memory than that.
+        if !header.params.is_acceptable() {
+            return Err(Error::SyntaxError(format!(
+                "{} asks for key derivation parameters that neng-pass doesn't accept.",
+                path.display()
+            )));
+        }
+
+        let key = Zeroizing::new(header.derive_key(p_master_key)?);
+        let database = Zeroizing::new(
+            crypto::open_with_header(
+                key.as_ref(),
+                &content[..FileHeader::LEN],
+                &content[FileHeader::LEN..],
+            )
+            .ok_or(Error::HashError(Argon2Error::Password))?,
+        );
+
+        let mut sql_connection = rusqlite::Connection::open_in_memory()?;
+        sql_connection.deserialize(DatabaseName::Main, copy_to_sqlite(&database)?, false)?;
+        // The vault may have been written by an older version.
+        crate::prepare_database(&sql_connection)?;
+
+        Ok(VaultFile {
+            path,
+            header,
+            key,
+            sql_connection,
+            saved: database,
+            file_hash: Some(Sha256::digest(&content).into()),
+            backups: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Keeps this many of the previous versions of the file, as `<file>.1` (the
+    /// latest), `<file>.2` and so on. None are kept by default.
+    pub fn set_backups(&mut self, p_backups: usize) {
+        self.backups = p_backups;
+    }
+
+    /// The database of the vault, to use like the one in `passwords.db`.
+    /// Changes are only kept once the vault is saved.
+    pub fn sql_connection(&self) -> &rusqlite::Connection {
+        &self.sql_connection
+    }
+
+    /// Writes the vault back to its file, if anything changed since it was
+    /// opened or last saved. Returns whether it was written.
+    pub fn save(&mut self) -> Result<bool, Error> {
+        let database = Zeroizing::new(self.sql_connection.serialize(DatabaseName::Main)?.to_vec());
+        if database == self.saved {
+            return Ok(false);
+        }
+
+        let header = self.header.to_bytes();
+        let mut content = header.clone();
+        content.extend(crypto::seal_with_header(
+            self.key.as_ref(),
+            &header,
+            &database,
+        ));
+
+        let file_hash = match std::fs::read(&self.path) {
+            Ok(on_disk) => Some(Sha256::digest(on_disk).into()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        if file_hash != self.file_hash {
+            return Err(Error::VaultChanged(Box::from(
+                self.path.to_string_lossy().as_ref(),
+            )));
+        }
+
+        if self.backups > 0 && self.path.exists() {
+            self.back_up()?;
+        }
+
+        write_private_file(&self.path, &content)?;
+        self.saved = database;
+        self.file_hash = Some(Sha256::digest(&content).into());
+
+        Ok(true)
+    }
+
+    fn backup_path(&self, p_index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", p_index));
+        PathBuf::from(path)
+    }
+
+    /// Shifts the backups by one, forgetting the oldest, and copies the file
+    /// as it is now to the first one.
+    fn back_up(&self) -> std::io::Result<()> {
+        match std::fs::remove_file(self.backup_path(self.backups)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+
+        for index in (1..self.backups).rev() {
+            if self.backup_path(index).exists() {
+                std::fs::rename(self.backup_path(index), self.backup_path(index + 1))?;
+            }
+        }
+
+        std::fs::copy(&self.path, self.backup_path(1))?;
+        Ok(())
+    }
+}
+
+/// SQLite takes ownership of the databases it deserializes, so they have to
+/// be in memory it allocated.
+fn copy_to_sqlite(p_database: &[u8]) -> Result<OwnedData, Error> {
+    // SAFETY: the memory is allocated with the size of the database, which
+    // is copied into it whole, and is then owned by the returned value only.
+    unsafe {
+        let pointer = rusqlite::ffi::sqlite3_malloc64(p_database.len() as u64) as *mut u8;
+        let pointer = NonNull::new(pointer).ok_or_else(|| {
+            Error::EncryptionError("Not enough memory to open the vault.".to_string())
+        })?;
+        std::ptr::copy_nonoverlapping(p_database.as_ptr(), pointer.as_ptr(), p_database.len());
+
+        Ok(OwnedData::from_raw_nonnull(pointer, p_database.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault_with_entry(p_path: &Path) {
+        let mut vault = VaultFile::create(p_path, "hunter2").unwrap();
+        crate::create_password(
+            "hunter2".to_string(),
+            "github",
+            "hunter3",
+            vault.sql_connection(),
+        )
+        .unwrap();
+        assert!(vault.save().unwrap());
+        assert!(!vault.save().unwrap());
+    }
+
+    #[test]
+    fn vaults_round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("vault.nengvault");
+        vault_with_entry(&path);
+
+        let vault = VaultFile::open(&path, "hunter2").unwrap();
+        let password =
+            crate::get_password("hunter2".to_string(), "github", vault.sql_connection()).unwrap();
+        assert_eq!(password.trim_end_matches('\0'), "hunter3");
+
+        assert!(matches!(
+            VaultFile::open(&path, "hunter3"),
+            Err(Error::HashError(Argon2Error::Password))
+        ));
+        assert!(matches!(
+            VaultFile::create(&path, "hunter2"),
+            Err(Error::MasterKeyAlreadyExists)
+        ));
+    }
+
+    #[test]
+    fn tampered_vaults_are_refused() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("vault.nengvault");
+        vault_with_entry(&path);
+        let content = std::fs::read(&path).unwrap();
+
+        // The header is sealed with the database, so changing a byte of
+        // either looks like a wrong master key.
+        for offset in [FileHeader::LEN - 1, FileHeader::LEN + 30, content.len() - 1] {
+            let mut tampered = content.clone();
+            tampered[offset] ^= 1;
+            std::fs::write(&path, &tampered).unwrap();
+            assert!(matches!(
+                VaultFile::open(&path, "hunter2"),
+                Err(Error::HashError(Argon2Error::Password))
+            ));
+        }
+
+        // Costly parameters are refused before deriving the key. The memory,
+        // the iterations and the parallelism follow the magic and the version.
+        for offset in [5, 9, 13] {
+            let mut tampered = content.clone();
+            tampered[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            std::fs::write(&path, &tampered).unwrap();
+            assert!(matches!(
+                VaultFile::open(&path, "hunter2"),
+                Err(Error::SyntaxError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn changes_by_others_are_not_overwritten() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("vault.nengvault");
+        vault_with_entry(&path);
+
+        let mut first = VaultFile::open(&path, "hunter2").unwrap();
+        let mut second = VaultFile::open(&path, "hunter2").unwrap();
+
+        crate::create_password(
+            "hunter2".to_string(),
+            "gitlab",
+            "hunter4",
+            second.sql_connection(),
+        )
+        .unwrap();
+        second.save().unwrap();
+
+        crate::create_password(
+            "hunter2".to_string(),
+            "gitea",
+            "hunter5",
+            first.sql_connection(),
+        )
+        .unwrap();
+        assert!(matches!(first.save(), Err(Error::VaultChanged(_))));
+
+        let vault = VaultFile::open(&path, "hunter2").unwrap();
+        assert_eq!(
+            crate::list_passwords(vault.sql_connection()).unwrap(),
+            ["github", "gitlab"]
+        );
+    }
+}